@@ -0,0 +1,42 @@
+#ifndef TRACING_TIMING_GRAPH_H
+#define TRACING_TIMING_GRAPH_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Opaque handle to a span timing layer and the corresponding graph */
+typedef struct ttg_layer_t ttg_layer_t;
+
+/* Create a new layer with the default settings. The layer must be released
+ * with `ttg_layer_free`. */
+ttg_layer_t* ttg_layer_new(void);
+
+/* Install `layer` as the global default subscriber. This returns 0 on
+ * success, and -1 if `layer` is NULL, was already installed, or if another
+ * global subscriber was already set. */
+int ttg_layer_install(ttg_layer_t* layer);
+
+/* Get all the data recorded by `layer` as JSON. The returned string must be
+ * released with `ttg_string_free`. */
+char* ttg_layer_json(const ttg_layer_t* layer);
+
+/* Get a per span summary table of the data recorded by `layer`. The returned
+ * string must be released with `ttg_string_free`. */
+char* ttg_layer_table(const ttg_layer_t* layer);
+
+/* Remove all the data recorded so far by `layer`, keeping its settings */
+void ttg_layer_reset(ttg_layer_t* layer);
+
+/* Release a layer created with `ttg_layer_new`. If the layer was installed,
+ * recording continues but the data can no longer be accessed. */
+void ttg_layer_free(ttg_layer_t* layer);
+
+/* Release a string returned by this library */
+void ttg_string_free(char* string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
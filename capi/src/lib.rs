@@ -0,0 +1,129 @@
+//! C API to record span timings from Rust libraries embedded in C or C++
+//! applications. See `include/tracing_timing_graph.h` for the corresponding
+//! declarations.
+
+#![allow(non_camel_case_types)]
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use tracing_timing_graph::{Format, GraphView, SpanTimingLayer};
+
+/// Opaque handle to a `SpanTimingLayer` and the corresponding graph
+pub struct ttg_layer_t {
+    /// the layer, until it is installed as the global default subscriber
+    layer: Option<SpanTimingLayer>,
+    graph: GraphView,
+}
+
+/// Create a new layer with the default settings. The layer must be released
+/// with `ttg_layer_free`.
+#[no_mangle]
+pub extern "C" fn ttg_layer_new() -> *mut ttg_layer_t {
+    let layer = SpanTimingLayer::new();
+    let graph = layer.view();
+    return Box::into_raw(Box::new(ttg_layer_t {
+        layer: Some(layer),
+        graph: graph,
+    }));
+}
+
+/// Install `layer` as the global default subscriber. This returns 0 on
+/// success, and -1 if `layer` is NULL, was already installed, or if another
+/// global subscriber was already set.
+///
+/// # Safety
+///
+/// `layer` must be NULL or a pointer returned by `ttg_layer_new` which was
+/// not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn ttg_layer_install(layer: *mut ttg_layer_t) -> c_int {
+    let layer = match layer.as_mut().and_then(|layer| layer.layer.take()) {
+        Some(layer) => layer,
+        None => return -1,
+    };
+
+    match tracing_timing_graph::try_init_with(layer) {
+        Ok(_) => return 0,
+        Err(_) => return -1,
+    }
+}
+
+/// Get all the data recorded by `layer` as JSON, see `SpanGraph::as_json`.
+/// The returned string must be released with `ttg_string_free`. This
+/// returns NULL if `layer` is NULL.
+///
+/// # Safety
+///
+/// `layer` must be NULL or a pointer returned by `ttg_layer_new` which was
+/// not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn ttg_layer_json(layer: *const ttg_layer_t) -> *mut c_char {
+    match layer.as_ref() {
+        Some(layer) => c_string(layer.graph.read().as_json()),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Get a per span summary table of the data recorded by `layer`, see
+/// `SpanGraph::as_table`. The returned string must be released with
+/// `ttg_string_free`. This returns NULL if `layer` is NULL.
+///
+/// # Safety
+///
+/// `layer` must be NULL or a pointer returned by `ttg_layer_new` which was
+/// not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn ttg_layer_table(layer: *const ttg_layer_t) -> *mut c_char {
+    match layer.as_ref() {
+        Some(layer) => c_string(layer.graph.read().render(Format::Table)),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Remove all the data recorded so far by `layer`, keeping its settings.
+/// Recording continues afterward.
+///
+/// # Safety
+///
+/// `layer` must be NULL or a pointer returned by `ttg_layer_new` which was
+/// not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn ttg_layer_reset(layer: *mut ttg_layer_t) {
+    if let Some(layer) = layer.as_ref() {
+        layer.graph.clear();
+    }
+}
+
+/// Release a layer created with `ttg_layer_new`. If the layer was installed,
+/// recording continues but the data can no longer be accessed.
+///
+/// # Safety
+///
+/// `layer` must be NULL or a pointer returned by `ttg_layer_new` which was
+/// not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn ttg_layer_free(layer: *mut ttg_layer_t) {
+    if !layer.is_null() {
+        drop(Box::from_raw(layer));
+    }
+}
+
+/// Release a string returned by this library.
+///
+/// # Safety
+///
+/// `string` must be NULL or a pointer returned by one of the functions in
+/// this library which was not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn ttg_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Convert `string` to a C string, removing any NUL byte from it
+fn c_string(string: String) -> *mut c_char {
+    let string = CString::new(string.replace('\0', "")).expect("NUL bytes were removed");
+    return string.into_raw();
+}
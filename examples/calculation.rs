@@ -1,6 +1,3 @@
-use tracing_timing_graph::SpanTimingLayer;
-use tracing_subscriber::{prelude::*, registry::Registry};
-
 #[tracing::instrument]
 fn run_computation(max: u64) {
     for i in 0..max {
@@ -39,16 +36,12 @@ fn run_other_5ms() {
 }
 
 fn main() {
-    let span_timer = SpanTimingLayer::new();
-    let graph = span_timer.graph();
-    let subscriber = Registry::default().with(span_timer);
-
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    let graph = tracing_timing_graph::init();
 
     run_other_5ms();
     run_computation(10);
 
-    let graph = graph.lock(); // .unwrap();
+    let graph = graph.read();
 
     println!("{}", graph.as_dot());
     println!("{}", graph.as_json());
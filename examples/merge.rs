@@ -0,0 +1,23 @@
+//! Merge graphs saved as JSON by multiple processes, and print the combined
+//! graph.
+//!
+//! Usage: cargo run --example merge -- graph-1.json graph-2.json ...
+
+fn main() {
+    let paths = std::env::args().skip(1).collect::<Vec<_>>();
+    if paths.is_empty() {
+        eprintln!("usage: merge <graph.json>...");
+        std::process::exit(1);
+    }
+
+    let graph = match tracing_timing_graph::SpanGraph::merge_json_files(&paths) {
+        Ok(graph) => graph,
+        Err(error) => {
+            eprintln!("failed to merge graphs: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    println!("{}", graph.as_table());
+    println!("{}", graph.as_json());
+}
@@ -0,0 +1,29 @@
+use rayon::prelude::*;
+use tracing_timing_graph::ParentSpan;
+
+#[tracing::instrument]
+fn work(item: u64) {
+    std::thread::sleep(std::time::Duration::from_micros(100 * item));
+}
+
+#[tracing::instrument]
+fn run_parallel(items: &[u64]) {
+    // without `ParentSpan`, `work` would be a root span when running on the
+    // rayon worker threads
+    let parent = ParentSpan::current();
+    items
+        .par_iter()
+        .for_each(|&item| parent.in_scope(|| work(item)));
+}
+
+fn main() {
+    let graph = tracing_timing_graph::init();
+
+    let items = (0..64).collect::<Vec<_>>();
+    run_parallel(&items);
+
+    let graph = graph.read();
+
+    println!("{}", graph.as_dot());
+    println!("{}", graph.as_table());
+}
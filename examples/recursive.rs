@@ -1,6 +1,3 @@
-use tracing_timing_graph::SpanTimingLayer;
-use tracing_subscriber::{prelude::*, registry::Registry};
-
 #[tracing::instrument]
 fn function_a(repeat: bool) {
     std::thread::sleep(std::time::Duration::from_millis(1));
@@ -25,16 +22,12 @@ fn recursive(mut count: usize) {
 }
 
 fn main() {
-    let span_timer = SpanTimingLayer::new();
-    let graph = span_timer.graph();
-    let subscriber = Registry::default().with(span_timer);
-
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    let graph = tracing_timing_graph::init();
 
     recursive(4);
     function_a(true);
 
-    let graph = graph.lock();
+    let graph = graph.read();
 
     println!("{}", graph.as_dot());
     println!("{}", graph.as_json());
@@ -0,0 +1,273 @@
+//! Python bindings to load and explore graphs produced by
+//! `tracing-timing-graph`, typically saved with `Format::Json` or
+//! `SpanGraph::to_bytes` at the end of a run.
+//!
+//! ```python
+//! import pandas
+//! from tracing_timing_graph import SpanGraph
+//!
+//! graph = SpanGraph.read_json_file("timings.json")
+//! print(graph.as_table())
+//! spans = pandas.DataFrame(graph.spans())
+//! ```
+
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use pyo3::exceptions::{PyIOError, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use timing_graph::{Format, ImportError, SpanIndex};
+
+fn import_error(error: ImportError) -> PyErr {
+    match error {
+        ImportError::Io(error) => PyIOError::new_err(error.to_string()),
+        ImportError::Invalid(message) => PyValueError::new_err(message),
+    }
+}
+
+/// Call graph of spans, with timing data for each span. Times are given in
+/// seconds.
+#[pyclass(name = "SpanGraph", module = "tracing_timing_graph")]
+struct SpanGraph {
+    graph: timing_graph::SpanGraph,
+}
+
+impl SpanGraph {
+    fn find(&self, name: &str) -> PyResult<SpanIndex> {
+        self.graph
+            .find(name)
+            .ok_or_else(|| PyKeyError::new_err(format!("no span named '{}'", name)))
+    }
+
+    fn names(&self, spans: impl Iterator<Item = SpanIndex>) -> Vec<String> {
+        spans
+            .map(|span| self.graph.span(span).name.clone())
+            .collect()
+    }
+}
+
+#[pymethods]
+impl SpanGraph {
+    /// Load a graph from a JSON string, as produced by `as_json`
+    #[staticmethod]
+    fn from_json(data: &str) -> PyResult<SpanGraph> {
+        let graph = timing_graph::SpanGraph::from_json(data).map_err(import_error)?;
+        return Ok(SpanGraph { graph: graph });
+    }
+
+    /// Load a graph from a JSON file, as produced by `Format::Json`
+    #[staticmethod]
+    fn read_json_file(path: &str) -> PyResult<SpanGraph> {
+        let graph = timing_graph::SpanGraph::read_json_file(path).map_err(import_error)?;
+        return Ok(SpanGraph { graph: graph });
+    }
+
+    /// Load a graph from a binary snapshot, as produced by `to_bytes`
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<SpanGraph> {
+        let graph = timing_graph::SpanGraph::from_bytes(data).map_err(import_error)?;
+        return Ok(SpanGraph { graph: graph });
+    }
+
+    /// Get a binary snapshot of this graph
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.graph.to_bytes())
+    }
+
+    /// Metadata attached to this graph
+    fn metadata(&self) -> std::collections::BTreeMap<String, String> {
+        self.graph.metadata().clone()
+    }
+
+    /// Number of spans in this graph
+    fn span_count(&self) -> usize {
+        self.graph.span_count()
+    }
+
+    /// Total number of calls to all spans in this graph
+    fn total_calls(&self) -> usize {
+        self.graph.total_calls()
+    }
+
+    /// Total time spent in spans called outside of any other span
+    fn total_elapsed(&self) -> f64 {
+        self.graph.total_elapsed().as_secs_f64()
+    }
+
+    /// Get the data for all spans, as a list of dictionaries with one entry
+    /// per span. This can be passed directly to `pandas.DataFrame`.
+    fn spans<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let mut spans = Vec::new();
+        for node in self.graph.as_petgraph().node_indices() {
+            let index = SpanIndex::from(node);
+            let span = self.graph.span(index);
+            let data = PyDict::new(py);
+            data.set_item("id", span.id)?;
+            data.set_item("name", &span.name)?;
+            data.set_item("level", span.level.map(|level| level.to_string()))?;
+            data.set_item("target", &span.target)?;
+            data.set_item("module_path", &span.module_path)?;
+            data.set_item("called", span.called)?;
+            data.set_item("elapsed", span.elapsed.as_secs_f64())?;
+            data.set_item("self_time", self.graph.self_time(index).as_secs_f64())?;
+            data.set_item("mean", span.mean().as_secs_f64())?;
+            data.set_item("stddev", span.stddev().as_secs_f64())?;
+            data.set_item("max", span.max.as_secs_f64())?;
+            data.set_item("idle", span.idle.as_secs_f64())?;
+            data.set_item("max_depth", span.max_depth)?;
+            data.set_item("errors", span.errors)?;
+            data.set_item("successes", span.successes)?;
+            data.set_item("failures", span.failures)?;
+            data.set_item("panics", span.panics)?;
+            data.set_item("counters", span.counters.clone())?;
+            spans.push(data);
+        }
+        return Ok(spans);
+    }
+
+    /// Get all calls in this graph, as a list of `(caller, callee, count)`
+    /// tuples containing span names
+    fn calls(&self) -> Vec<(String, String, usize)> {
+        self.graph
+            .calls()
+            .map(|call| {
+                (
+                    self.graph.span(call.caller).name.clone(),
+                    self.graph.span(call.callee).name.clone(),
+                    call.count,
+                )
+            })
+            .collect()
+    }
+
+    /// Get the spans calling the span with the given `name`, as a list of
+    /// `(caller, count)` tuples
+    fn callers(&self, name: &str) -> PyResult<Vec<(String, usize)>> {
+        let span = self.find(name)?;
+        return Ok(self
+            .graph
+            .callers(span)
+            .map(|(caller, count)| (self.graph.span(caller).name.clone(), count))
+            .collect());
+    }
+
+    /// Get the spans called by the span with the given `name`, as a list of
+    /// `(callee, count)` tuples
+    fn callees(&self, name: &str) -> PyResult<Vec<(String, usize)>> {
+        let span = self.find(name)?;
+        return Ok(self
+            .graph
+            .callees(span)
+            .map(|(callee, count)| (self.graph.span(callee).name.clone(), count))
+            .collect());
+    }
+
+    /// Get the names of spans called outside of any other span
+    fn roots(&self) -> Vec<String> {
+        self.names(self.graph.roots())
+    }
+
+    /// Get the names of spans which did not call any other span
+    fn leaves(&self) -> Vec<String> {
+        self.names(self.graph.leaves())
+    }
+
+    /// Get the names of spans with a maximal invocation duration larger than
+    /// `factor` times their mean duration
+    fn outliers(&self, factor: f64) -> Vec<String> {
+        self.names(self.graph.outliers(factor).into_iter())
+    }
+
+    /// Get the time spent in the span with the given `name`, excluding the
+    /// time spent in the spans it called
+    fn self_time(&self, name: &str) -> PyResult<f64> {
+        let span = self.find(name)?;
+        return Ok(self.graph.self_time(span).as_secs_f64());
+    }
+
+    /// Get the sub-graph containing the span with the given `name` and all
+    /// the spans it called
+    fn subgraph(&self, name: &str) -> PyResult<SpanGraph> {
+        let span = self.find(name)?;
+        return Ok(SpanGraph {
+            graph: self.graph.subgraph(span),
+        });
+    }
+
+    /// Add the data from `other` to this graph
+    fn merge(&mut self, other: &SpanGraph) {
+        self.graph.merge(&other.graph);
+    }
+
+    /// Render this graph in the given `format`: one of "table", "tree",
+    /// "pareto", "json", "dot", "graphml", "callgrind", "d3-flamegraph",
+    /// "cytoscape", "mermaid-gantt", "plotly-timeline" or "svg"
+    fn render(&self, format: &str) -> PyResult<String> {
+        let format = match format {
+            "table" => Format::Table,
+            "tree" => Format::Tree,
+            "pareto" => Format::Pareto,
+            "json" => Format::Json,
+            "dot" => Format::Dot,
+            "graphml" => Format::GraphML,
+            "callgrind" => Format::Callgrind,
+            "d3-flamegraph" => Format::D3Flamegraph,
+            "cytoscape" => Format::Cytoscape,
+            "mermaid-gantt" => Format::MermaidGantt,
+            "plotly-timeline" => Format::PlotlyTimeline,
+            "svg" => Format::Svg,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown format '{}'",
+                    format
+                )))
+            }
+        };
+        return Ok(self.graph.render(format));
+    }
+
+    /// Get a per span summary table of this graph
+    fn as_table(&self) -> String {
+        self.graph.render(Format::Table)
+    }
+
+    /// Get the call tree of this graph as an indented tree
+    fn as_tree(&self) -> String {
+        self.graph.render(Format::Tree)
+    }
+
+    /// Get all the data in this graph in JSON
+    fn as_json(&self) -> String {
+        self.graph.as_json()
+    }
+
+    /// Get the graph in graphviz dot format
+    fn as_dot(&self) -> String {
+        self.graph.as_dot()
+    }
+
+    /// Get the graph as an SVG image. Jupyter uses this to display the graph
+    /// directly in notebooks.
+    fn _repr_svg_(&self) -> String {
+        self.graph.as_svg()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "SpanGraph({} spans, {} calls)",
+            self.graph.span_count(),
+            self.graph.total_calls()
+        )
+    }
+
+    fn __str__(&self) -> String {
+        self.graph.render(Format::Table)
+    }
+}
+
+#[pymodule]
+fn tracing_timing_graph(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<SpanGraph>()?;
+    return Ok(());
+}
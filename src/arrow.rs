@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+
+use crate::{SpanGraph, SpanTiming};
+
+impl SpanGraph {
+    /// Get the spans in this graph as an Arrow `RecordBatch`, with one row
+    /// per span. The batch can be handed over to dataframe libraries such as
+    /// polars or pandas (through pyarrow) without any copy or parsing.
+    ///
+    /// The columns are `id`, `name`, `level`, `target`, `module_path`,
+    /// `called`, `elapsed_ns`, `mean_ns`, `stddev_ns`, `max_ns`, `idle_ns`
+    /// and `max_depth`. `level`, `target` and `module_path` can be null.
+    pub fn spans_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let spans = self.spans().collect::<Vec<_>>();
+        let integers = |value: fn(&SpanTiming) -> u64| -> ArrayRef {
+            Arc::new(
+                spans
+                    .iter()
+                    .map(|span| value(span))
+                    .collect::<UInt64Array>(),
+            )
+        };
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::UInt64, false),
+            Field::new("name", DataType::Utf8, false),
+            Field::new("level", DataType::Utf8, true),
+            Field::new("target", DataType::Utf8, true),
+            Field::new("module_path", DataType::Utf8, true),
+            Field::new("called", DataType::UInt64, false),
+            Field::new("elapsed_ns", DataType::UInt64, false),
+            Field::new("mean_ns", DataType::UInt64, false),
+            Field::new("stddev_ns", DataType::UInt64, false),
+            Field::new("max_ns", DataType::UInt64, false),
+            Field::new("idle_ns", DataType::UInt64, false),
+            Field::new("max_depth", DataType::UInt64, false),
+        ]);
+
+        let columns: Vec<ArrayRef> = vec![
+            integers(|span| span.id as u64),
+            Arc::new(
+                spans
+                    .iter()
+                    .map(|span| Some(span.name.as_str()))
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(
+                spans
+                    .iter()
+                    .map(|span| span.level.map(|level| level.to_string()))
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(
+                spans
+                    .iter()
+                    .map(|span| span.target.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(
+                spans
+                    .iter()
+                    .map(|span| span.module_path.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            integers(|span| span.called as u64),
+            integers(|span| span.elapsed.as_nanos() as u64),
+            integers(|span| span.mean().as_nanos() as u64),
+            integers(|span| span.stddev().as_nanos() as u64),
+            integers(|span| span.max.as_nanos() as u64),
+            integers(|span| span.idle.as_nanos() as u64),
+            integers(|span| span.max_depth as u64),
+        ];
+
+        return RecordBatch::try_new(Arc::new(schema), columns);
+    }
+
+    /// Get the calls in this graph as an Arrow `RecordBatch`, with one row
+    /// per pair of caller and callee. The `caller` and `callee` columns refer
+    /// to the `id` column of `SpanGraph::spans_record_batch`, and `count`
+    /// contains the number of calls.
+    pub fn calls_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let calls = self.calls().collect::<Vec<_>>();
+
+        let schema = Schema::new(vec![
+            Field::new("caller", DataType::UInt64, false),
+            Field::new("callee", DataType::UInt64, false),
+            Field::new("count", DataType::UInt64, false),
+        ]);
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(
+                calls
+                    .iter()
+                    .map(|call| self.span(call.caller).id as u64)
+                    .collect::<UInt64Array>(),
+            ),
+            Arc::new(
+                calls
+                    .iter()
+                    .map(|call| self.span(call.callee).id as u64)
+                    .collect::<UInt64Array>(),
+            ),
+            Arc::new(
+                calls
+                    .iter()
+                    .map(|call| call.count as u64)
+                    .collect::<UInt64Array>(),
+            ),
+        ];
+
+        return RecordBatch::try_new(Arc::new(schema), columns);
+    }
+}
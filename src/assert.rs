@@ -0,0 +1,106 @@
+use crate::SpanGraph;
+
+/// Assert that the span with the given full name was called exactly `times`
+/// times in a `SpanGraph`, see `SpanGraph::assert_called`.
+///
+/// ```
+/// use tracing_timing_graph::assert_called;
+///
+/// #[tracing::instrument]
+/// fn compute(n: u64) -> u64 {
+///     (0..n).sum()
+/// }
+///
+/// let (_, graph) = tracing_timing_graph::profile(|| {
+///     compute(10);
+///     compute(20);
+/// });
+/// # // the layer does not record anything with the `noop` features
+/// # #[cfg(not(any(feature = "noop", all(feature = "release_noop", not(debug_assertions)))))]
+/// assert_called!(graph, concat!(module_path!(), "::compute"), 2);
+/// ```
+#[macro_export]
+macro_rules! assert_called {
+    ($graph: expr, $name: expr, $times: expr) => {
+        $graph.assert_called($name, $times)
+    };
+}
+
+/// Assert that the span `caller` called the span `callee` exactly `count`
+/// times in a `SpanGraph`, see `SpanGraph::assert_edge`.
+#[macro_export]
+macro_rules! assert_edge {
+    ($graph: expr, $caller: expr, $callee: expr, $count: expr) => {
+        $graph.assert_edge($caller, $callee, $count)
+    };
+}
+
+impl SpanGraph {
+    /// Check that the span with the given full `name` exists in this graph and
+    /// was called exactly `times` times.
+    ///
+    /// This is intended to be used in integration tests, to verify that
+    /// instrumented code paths are exercised as expected.
+    ///
+    /// # Panic
+    ///
+    /// This function panics if the span does not exist or was called a
+    /// different number of times.
+    #[track_caller]
+    pub fn assert_called(&self, name: &str, times: usize) {
+        let span = match self.find(name) {
+            Some(span) => self.span(span),
+            None => panic!(
+                "span '{}' was never called, known spans are: {}",
+                name,
+                self.span_names()
+            ),
+        };
+
+        assert_eq!(
+            span.called, times,
+            "span '{}' was called {} times instead of {}",
+            name, span.called, times
+        );
+    }
+
+    /// Check that the span with the full name `caller` called the span with
+    /// the full name `callee` exactly `count` times.
+    ///
+    /// # Panic
+    ///
+    /// This function panics if one of the spans does not exist, or if the
+    /// number of calls is different.
+    #[track_caller]
+    pub fn assert_edge(&self, caller: &str, callee: &str, count: usize) {
+        let find = |name| match self.find(name) {
+            Some(span) => span,
+            None => panic!(
+                "span '{}' was never called, known spans are: {}",
+                name,
+                self.span_names()
+            ),
+        };
+        let caller_index = find(caller);
+        let callee_index = find(callee);
+
+        let actual = self
+            .callees(caller_index)
+            .find(|&(span, _)| span == callee_index)
+            .map_or(0, |(_, count)| count);
+
+        assert_eq!(
+            actual, count,
+            "span '{}' called '{}' {} times instead of {}",
+            caller, callee, actual, count
+        );
+    }
+
+    /// Get the names of all spans in this graph, for error messages
+    fn span_names(&self) -> String {
+        self.spans()
+            .map(|span| format!("'{}'", span.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
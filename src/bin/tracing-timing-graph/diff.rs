@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+use tracing_timing_graph::{SpanComparison, SpanGraph};
+
+/// Compare `current` with `baseline`, flagging spans slower by more than
+/// `tolerance` (relative) and `min_delta` (absolute) as regressions, and get
+/// all the comparisons.
+pub fn diff(
+    baseline: &SpanGraph,
+    current: &SpanGraph,
+    tolerance: f64,
+    min_delta: Duration,
+) -> Vec<SpanComparison> {
+    let mut comparisons = current.compare(baseline, tolerance);
+    for comparison in &mut comparisons {
+        if let (Some(baseline), Some(current)) = (comparison.baseline, comparison.current) {
+            if current.saturating_sub(baseline) < min_delta {
+                comparison.regression = false;
+            }
+        }
+    }
+    return comparisons;
+}
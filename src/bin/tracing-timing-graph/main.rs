@@ -0,0 +1,295 @@
+//! Render span graphs saved by a previous run, so that heavy rendering can
+//! happen offline instead of inside the instrumented process.
+//!
+//! ```bash
+//! tracing-timing-graph render timings.json --format tree
+//! tracing-timing-graph render timings.bin --format flamegraph -o flamegraph.svg
+//! tracing-timing-graph render timings.json --filter 'name ~ "compute" && elapsed > 10ms'
+//! tracing-timing-graph diff baseline.json timings.json --tolerance 5 --min-delta 1ms
+//! tracing-timing-graph watch timings.json --view pareto --interval 500ms
+//! ```
+
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use tracing_timing_graph::{
+    ColorMode, Format, ImportError, ReportOptions, SpanComparison, SpanFilter, SpanGraph,
+};
+
+mod diff;
+mod watch;
+
+#[derive(Parser)]
+#[command(name = "tracing-timing-graph", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Render a graph saved as JSON (`Format::Json`) or as a binary snapshot
+    /// (`SpanGraph::to_bytes`)
+    Render {
+        /// Path to the saved graph
+        input: PathBuf,
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+        /// Write the output to this file instead of the standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Only include the spans selected by this filter expression, such as
+        /// `name ~ "compute" && elapsed > 10ms`. Outside of the table, tree
+        /// and pareto formats, the other spans are removed from the graph
+        /// and their calls re-attached to their callers.
+        #[arg(long)]
+        filter: Option<SpanFilter>,
+    },
+    /// Compare the elapsed time of spans between two saved graphs, exiting
+    /// with code 1 if any span regressed and 2 on other errors
+    Diff {
+        /// Path to the baseline graph
+        baseline: PathBuf,
+        /// Path to the current graph
+        current: PathBuf,
+        /// Flag spans slower than in the baseline by more than this
+        /// percentage as regressions
+        #[arg(short, long, default_value_t = 10.0)]
+        tolerance: f64,
+        /// Ignore regressions smaller than this absolute duration (e.g.
+        /// `500us` or `2ms`), to filter out noise from short spans
+        #[arg(short, long, value_parser = parse_duration, default_value = "0s")]
+        min_delta: Duration,
+        /// Only show the spans which regressed
+        #[arg(long)]
+        only_regressions: bool,
+        /// Compare the fraction of the total time spent in each span instead
+        /// of the absolute time, to compare graphs recorded on machines of
+        /// different speeds. Durations are then shown with 1s meaning 100%.
+        #[arg(long)]
+        normalize: bool,
+    },
+    /// Continuously display a graph periodically saved by a running process
+    /// (for example with `SpanTimingLayer::with_output`), like `top` for
+    /// spans
+    Watch {
+        /// Path to the saved graph
+        input: PathBuf,
+        /// Report to display
+        #[arg(short, long, value_enum, default_value_t = watch::View::Pareto)]
+        view: watch::View,
+        /// Time between two refreshes (e.g. `500ms` or `2s`)
+        #[arg(short, long, value_parser = parse_duration, default_value = "1s")]
+        interval: Duration,
+        /// Only include the spans selected by this filter expression, such as
+        /// `name ~ "compute" && elapsed > 10ms`
+        #[arg(long)]
+        filter: Option<SpanFilter>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Tree,
+    Pareto,
+    Json,
+    Dot,
+    Graphml,
+    Callgrind,
+    Svg,
+    /// flamegraph SVG rendered with inferno
+    Flamegraph,
+}
+
+/// Load a graph saved as JSON or as a binary snapshot from the file at `path`
+fn load(path: &Path) -> Result<SpanGraph, ImportError> {
+    let data = std::fs::read(path).map_err(ImportError::Io)?;
+    let json = data.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{');
+
+    if json {
+        let data = String::from_utf8(data)
+            .map_err(|_| ImportError::Invalid("the JSON file is not valid UTF-8".into()))?;
+        return SpanGraph::from_json(&data);
+    } else {
+        return SpanGraph::from_bytes(&data);
+    }
+}
+
+/// Parse a duration such as `500us`, `1.5ms` or `2s`
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("invalid duration '{}'", value))?;
+    let scale = match unit.trim() {
+        "ns" => 1e-9,
+        "us" | "µs" => 1e-6,
+        "ms" => 1e-3,
+        "s" => 1.0,
+        _ => {
+            return Err(format!(
+                "invalid duration '{}', expected a unit of ns, us, ms or s",
+                value
+            ))
+        }
+    };
+
+    return Duration::try_from_secs_f64(number * scale)
+        .map_err(|_| format!("invalid duration '{}', the value is too large", value));
+}
+
+fn render(
+    graph: &SpanGraph,
+    format: OutputFormat,
+    options: &ReportOptions,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    let format = match format {
+        OutputFormat::Table => return output.write_all(graph.as_table_with(options).as_bytes()),
+        OutputFormat::Tree => return output.write_all(graph.as_tree_with(options).as_bytes()),
+        OutputFormat::Pareto => {
+            return output.write_all(graph.as_pareto_table_with(options).as_bytes())
+        }
+        OutputFormat::Json => Format::Json,
+        OutputFormat::Dot => Format::Dot,
+        OutputFormat::Graphml => Format::GraphML,
+        OutputFormat::Callgrind => Format::Callgrind,
+        OutputFormat::Svg => Format::Svg,
+        OutputFormat::Flamegraph => return graph.write_flamegraph_svg_to(output),
+    };
+    return graph.write_to(format, output);
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Render {
+            input,
+            format,
+            output,
+            filter,
+        } => {
+            let mut graph = match load(&input) {
+                Ok(graph) => graph,
+                Err(error) => {
+                    eprintln!("error: failed to load {}: {}", input.display(), error);
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            // only color reports written to a terminal
+            let mut options = ReportOptions::new();
+            if output.is_some() {
+                options = options.with_colors(ColorMode::Never);
+            }
+            if let Some(filter) = filter {
+                let report = matches!(
+                    format,
+                    OutputFormat::Table | OutputFormat::Tree | OutputFormat::Pareto
+                );
+                if !report {
+                    // other formats do not use report options, remove the
+                    // spans from the graph instead
+                    graph.prune(|span| !filter.matches(span));
+                }
+                options = options.with_filter(filter);
+            }
+
+            let result = match output {
+                Some(path) => std::fs::File::create(&path).and_then(|file| {
+                    let mut writer = std::io::BufWriter::new(file);
+                    render(&graph, format, &options, &mut writer)?;
+                    writer.flush()
+                }),
+                None => render(&graph, format, &options, &mut std::io::stdout().lock()),
+            };
+
+            if let Err(error) = result {
+                eprintln!("error: failed to render the graph: {}", error);
+                return ExitCode::FAILURE;
+            }
+        }
+        Command::Diff {
+            baseline,
+            current,
+            tolerance,
+            min_delta,
+            only_regressions,
+            normalize,
+        } => {
+            return run_diff(
+                &baseline,
+                &current,
+                tolerance,
+                min_delta,
+                only_regressions,
+                normalize,
+            )
+        }
+        Command::Watch {
+            input,
+            view,
+            interval,
+            filter,
+        } => watch::watch(&input, view, interval, filter),
+    }
+
+    return ExitCode::SUCCESS;
+}
+
+fn run_diff(
+    baseline: &Path,
+    current: &Path,
+    tolerance: f64,
+    min_delta: Duration,
+    only_regressions: bool,
+    normalize: bool,
+) -> ExitCode {
+    let (baseline, current) = match (load(baseline), load(current)) {
+        (Ok(baseline), Ok(current)) => (baseline, current),
+        (Err(error), _) => {
+            eprintln!("error: failed to load {}: {}", baseline.display(), error);
+            return ExitCode::from(2);
+        }
+        (_, Err(error)) => {
+            eprintln!("error: failed to load {}: {}", current.display(), error);
+            return ExitCode::from(2);
+        }
+    };
+
+    let (baseline, current) = if normalize {
+        (baseline.normalized(), current.normalized())
+    } else {
+        (baseline, current)
+    };
+
+    let mut comparisons = diff::diff(&baseline, &current, tolerance / 100.0, min_delta);
+    let regressions = comparisons
+        .iter()
+        .filter(|comparison| comparison.regression)
+        .count();
+    if only_regressions {
+        comparisons.retain(|comparison| comparison.regression);
+    }
+
+    print!("{}", SpanComparison::table(&comparisons));
+    if regressions != 0 {
+        println!("{} span(s) regressed compared to the baseline", regressions);
+        return ExitCode::FAILURE;
+    }
+
+    return ExitCode::SUCCESS;
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use clap::ValueEnum;
+
+use tracing_timing_graph::{ColorMode, ReportOptions, SpanFilter, SpanGraph};
+
+/// Report shown in watch mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum View {
+    /// Per span summary table
+    Table,
+    /// Indented call tree
+    Tree,
+    /// Spans sorted by self time
+    Pareto,
+}
+
+/// Re-load the graph at `path` every `interval` and refresh the terminal
+/// with the corresponding `view`, only including the spans selected by
+/// `filter`, until the process is interrupted.
+///
+/// The file is expected to be written periodically by the instrumented
+/// process, for example with `SpanTimingLayer::with_output` and
+/// `GraphView::flush`.
+pub fn watch(path: &Path, view: View, interval: Duration, filter: Option<SpanFilter>) -> ! {
+    let mut options = ReportOptions::new().with_colors(ColorMode::Auto);
+    if let Some(filter) = filter {
+        options = options.with_filter(filter);
+    }
+    // `None` until the first refresh, then the modification time of the file
+    // (if any) when it was last loaded
+    let mut last_modified = None;
+    let mut output = String::new();
+    let mut failed = false;
+    loop {
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        // only parse the file again if it changed, or if it could not be
+        // loaded the last time
+        if last_modified != Some(modified) || failed {
+            last_modified = Some(modified);
+            match crate::load(path) {
+                Ok(graph) => {
+                    output = render(&graph, view, &options);
+                    failed = false;
+                }
+                Err(error) => {
+                    output = format!("failed to load {}: {}\n", path.display(), error);
+                    failed = true;
+                }
+            }
+        }
+
+        // clear the terminal and move the cursor to the top left corner
+        print!("\x1b[2J\x1b[H");
+        println!(
+            "{} — updated {}, refreshing every {:?}\n",
+            path.display(),
+            age(modified),
+            interval
+        );
+        print!("{}", output);
+
+        std::thread::sleep(interval);
+    }
+}
+
+fn render(graph: &SpanGraph, view: View, options: &ReportOptions) -> String {
+    match view {
+        View::Table => graph.as_table_with(options),
+        View::Tree => graph.as_tree_with(options),
+        View::Pareto => graph.as_pareto_table_with(options),
+    }
+}
+
+/// Describe how long ago a file was last modified
+fn age(modified: Option<SystemTime>) -> String {
+    let elapsed = modified.and_then(|modified| modified.elapsed().ok());
+    match elapsed {
+        Some(elapsed) => format!("{}s ago", elapsed.as_secs()),
+        None => "at an unknown time".into(),
+    }
+}
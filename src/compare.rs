@@ -0,0 +1,242 @@
+use std::path::Path;
+use std::time::Duration;
+
+use term_table::row::Row;
+use term_table::table_cell::TableCell;
+
+use crate::graph::right_aligned;
+use crate::{ImportError, ReportOptions, SpanGraph};
+
+/// Comparison of the elapsed time of a single span group between two graphs
+#[derive(Clone, Debug)]
+pub struct SpanComparison {
+    /// Full span name, used to match spans between the two graphs
+    pub name: String,
+    /// Total elapsed time in the baseline graph, if the span exists there
+    pub baseline: Option<Duration>,
+    /// Total elapsed time in the current graph, if the span exists there
+    pub current: Option<Duration>,
+    /// Did the elapsed time increase by more than the tolerance?
+    pub regression: bool,
+}
+
+impl SpanComparison {
+    /// Get the relative change in elapsed time between the baseline and the
+    /// current graph (`0.1` meaning 10% slower), or `None` if the span is
+    /// missing from one of the graphs or was not running in the baseline.
+    pub fn change(&self) -> Option<f64> {
+        let baseline = self.baseline?.as_secs_f64();
+        let current = self.current?.as_secs_f64();
+        if baseline == 0.0 {
+            return None;
+        }
+        return Some((current - baseline) / baseline);
+    }
+}
+
+/// Error returned by `SpanGraph::assert_no_regression`
+#[derive(Debug)]
+pub enum RegressionError {
+    /// The baseline graph could not be loaded
+    Baseline(ImportError),
+    /// Some spans are slower than in the baseline by more than the tolerance
+    Regressions(Vec<SpanComparison>),
+}
+
+impl std::fmt::Display for RegressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegressionError::Baseline(error) => write!(f, "failed to load baseline: {}", error),
+            RegressionError::Regressions(regressions) => {
+                write!(
+                    f,
+                    "{} span(s) regressed compared to the baseline",
+                    regressions.len()
+                )?;
+                for regression in regressions {
+                    write!(
+                        f,
+                        "\n    {}: {:.2?} => {:.2?} ({:+.1}%)",
+                        regression.name,
+                        regression.baseline.unwrap_or_default(),
+                        regression.current.unwrap_or_default(),
+                        100.0 * regression.change().unwrap_or_default(),
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegressionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RegressionError::Baseline(error) => Some(error),
+            RegressionError::Regressions(_) => None,
+        }
+    }
+}
+
+impl SpanGraph {
+    /// Compare the elapsed time of all spans in this graph with the ones in
+    /// the `baseline` graph, matching spans by name. Spans which are slower
+    /// than in the baseline by more than `tolerance` (relative to the
+    /// baseline, `0.1` meaning 10%) are flagged as regressions.
+    ///
+    /// Spans are returned in the same order as `SpanGraph::as_table`, spans
+    /// only present in the baseline coming last.
+    pub fn compare(&self, baseline: &SpanGraph, tolerance: f64) -> Vec<SpanComparison> {
+        let mut comparisons = Vec::new();
+        for node in self.sorted_nodes() {
+            let span = &self.graph[node];
+            let mut comparison = SpanComparison {
+                name: span.name.clone(),
+                baseline: baseline
+                    .find(&span.name)
+                    .map(|other| baseline.span(other).elapsed),
+                current: Some(span.elapsed),
+                regression: false,
+            };
+            comparison.regression = comparison.change().is_some_and(|change| change > tolerance);
+            comparisons.push(comparison);
+        }
+
+        for node in baseline.sorted_nodes() {
+            let span = &baseline.graph[node];
+            if self.find(&span.name).is_none() {
+                comparisons.push(SpanComparison {
+                    name: span.name.clone(),
+                    baseline: Some(span.elapsed),
+                    current: None,
+                    regression: false,
+                });
+            }
+        }
+
+        return comparisons;
+    }
+
+    /// Check that no span in this graph is slower than in the baseline graph
+    /// stored in the JSON file at `baseline` by more than `tolerance` (see
+    /// `SpanGraph::compare`), returning the list of regressions otherwise.
+    ///
+    /// This is intended to be used in tests or CI, to prevent performance
+    /// regressions from being merged.
+    ///
+    /// ```no_run
+    /// # use tracing_timing_graph::SpanGraph;
+    /// # let graph = SpanGraph::new();
+    /// // fail if any span is more than 20% slower than in the baseline
+    /// graph.assert_no_regression("baseline.json", 0.2).unwrap();
+    /// ```
+    pub fn assert_no_regression<P: AsRef<Path>>(
+        &self,
+        baseline: P,
+        tolerance: f64,
+    ) -> Result<(), RegressionError> {
+        let baseline = SpanGraph::read_json_file(baseline).map_err(RegressionError::Baseline)?;
+        let regressions = self
+            .compare(&baseline, tolerance)
+            .into_iter()
+            .filter(|comparison| comparison.regression)
+            .collect::<Vec<_>>();
+
+        if regressions.is_empty() {
+            return Ok(());
+        } else {
+            return Err(RegressionError::Regressions(regressions));
+        }
+    }
+
+    /// Get a table comparing the elapsed time of all spans in this graph with
+    /// the `baseline` graph side by side, see `SpanGraph::compare`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_comparison_table(&self, baseline: &SpanGraph, tolerance: f64) -> String {
+        self.as_comparison_table_with(baseline, tolerance, &ReportOptions::default())
+    }
+
+    /// Get a table comparing the elapsed time of all spans in this graph with
+    /// the `baseline` graph side by side, using the table style from
+    /// `options`. See `SpanGraph::as_comparison_table`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_comparison_table_with(
+        &self,
+        baseline: &SpanGraph,
+        tolerance: f64,
+        options: &ReportOptions,
+    ) -> String {
+        SpanComparison::table_with(&self.compare(baseline, tolerance), options)
+    }
+}
+
+impl SpanComparison {
+    /// Get a table showing the given `comparisons` side by side, see
+    /// `SpanGraph::as_comparison_table`. This is useful to render
+    /// comparisons after adjusting them, for example to ignore regressions
+    /// below an absolute threshold.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn table(comparisons: &[SpanComparison]) -> String {
+        SpanComparison::table_with(comparisons, &ReportOptions::default())
+    }
+
+    /// Get a table showing the given `comparisons` side by side, using the
+    /// table style from `options`. See `SpanComparison::table`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn table_with(comparisons: &[SpanComparison], options: &ReportOptions) -> String {
+        let mut table = term_table::Table::new();
+
+        table.add_row(Row::new(vec![
+            // pad "span name" to make the table look nicer with short names
+            "span name                                   ",
+            "baseline",
+            "current",
+            "delta",
+            "change",
+            "",
+        ]));
+
+        for comparison in comparisons {
+            let format = |duration: Option<Duration>| {
+                duration.map_or_else(|| "—".into(), |duration| format!("{:.2?}", duration))
+            };
+
+            let delta = match (comparison.baseline, comparison.current) {
+                (Some(baseline), Some(current)) if current >= baseline => {
+                    format!("+{:.2?}", current - baseline)
+                }
+                (Some(baseline), Some(current)) => format!("-{:.2?}", baseline - current),
+                _ => "—".into(),
+            };
+
+            let change = comparison
+                .change()
+                .map_or_else(|| "—".into(), |change| format!("{:+.1}%", 100.0 * change));
+
+            let flag = if comparison.regression {
+                "regression"
+            } else if comparison.baseline.is_none() {
+                "new"
+            } else if comparison.current.is_none() {
+                "removed"
+            } else {
+                ""
+            };
+
+            table.add_row(Row::new(vec![
+                TableCell::new(&comparison.name),
+                right_aligned(format(comparison.baseline)),
+                right_aligned(format(comparison.current)),
+                right_aligned(delta),
+                right_aligned(change),
+                TableCell::new(flag),
+            ]));
+        }
+
+        return options.render_table(table);
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+/// Directory containing the RAPL (Running Average Power Limit) energy
+/// counters exposed by the Linux powercap framework
+const POWERCAP: &str = "/sys/class/powercap";
+
+/// A single RAPL energy counter
+#[derive(Debug)]
+struct Domain {
+    /// `energy_uj` file, containing the energy consumed in microjoules
+    file: File,
+    /// value at which the energy counter wraps around
+    max_energy: u64,
+}
+
+/// Sampler reading the energy consumed by all CPU packages, using RAPL
+/// counters
+#[derive(Debug)]
+pub(crate) struct EnergySampler {
+    domains: Vec<Domain>,
+}
+
+/// Read a single integer from a sysfs file
+fn read_integer(file: &File) -> io::Result<u64> {
+    let mut buffer = [0u8; 32];
+    let size = file.read_at(&mut buffer, 0)?;
+    return std::str::from_utf8(&buffer[..size])
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid RAPL counter"));
+}
+
+impl EnergySampler {
+    /// Open the RAPL counters for all CPU packages
+    pub fn new() -> io::Result<EnergySampler> {
+        let mut domains = Vec::new();
+        for entry in std::fs::read_dir(POWERCAP)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+            // only use package domains (`intel-rapl:0`), since sub-domains
+            // (`intel-rapl:0:0`) are already included in the package energy
+            if !name.starts_with("intel-rapl:") || name.matches(':').count() != 1 {
+                continue;
+            }
+
+            let max_energy = read_integer(&File::open(path.join("max_energy_range_uj"))?)?;
+            domains.push(Domain {
+                file: File::open(path.join("energy_uj"))?,
+                max_energy: max_energy,
+            });
+        }
+
+        if domains.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no RAPL energy counters found",
+            ));
+        }
+
+        return Ok(EnergySampler { domains: domains });
+    }
+
+    /// Read the current value of all energy counters, in microjoules
+    pub fn sample(&self) -> Option<Vec<u64>> {
+        self.domains
+            .iter()
+            .map(|domain| read_integer(&domain.file).ok())
+            .collect()
+    }
+
+    /// Get the energy consumed between the `start` and `end` samples, in
+    /// joules
+    pub fn energy(&self, start: &[u64], end: &[u64]) -> f64 {
+        let mut total = 0;
+        for ((domain, &start), &end) in self.domains.iter().zip(start).zip(end) {
+            total += if end >= start {
+                end - start
+            } else {
+                // the counter wrapped around
+                end + (domain.max_energy - start)
+            };
+        }
+        return total as f64 * 1e-6;
+    }
+}
@@ -0,0 +1,106 @@
+/// Errors encountered by a `SpanTimingLayer` while recording span data.
+///
+/// The layer never panics on such errors, and instead skips the
+/// corresponding data. Errors can be observed by registering a handler with
+/// `SpanTimingLayer::with_error_handler`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerError {
+    /// The span could not be found in the subscriber registry
+    MissingSpan {
+        /// Name of the `Layer` callback where the error occurred
+        callback: &'static str,
+    },
+    /// The span does not contain timing data, for example because it was
+    /// created before this layer was installed
+    MissingExtension {
+        /// Name of the `Layer` callback where the error occurred
+        callback: &'static str,
+    },
+    /// A span was entered while it was already entered
+    AlreadyEntered,
+    /// A span was exited without being entered first
+    NotEntered,
+    /// A span was closed while it was still entered
+    ClosedWhileEntered,
+    /// The report could not be written to the configured output file
+    Output {
+        /// Kind of the underlying IO error
+        kind: std::io::ErrorKind,
+    },
+}
+
+impl std::fmt::Display for LayerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayerError::MissingSpan { callback } => {
+                write!(f, "{}: failed to get span", callback)
+            }
+            LayerError::MissingExtension { callback } => {
+                write!(f, "{}: failed to get span timing data", callback)
+            }
+            LayerError::AlreadyEntered => write!(f, "span entered while already entered"),
+            LayerError::NotEntered => write!(f, "span exited without being entered"),
+            LayerError::ClosedWhileEntered => write!(f, "span closed while still entered"),
+            LayerError::Output { kind } => write!(f, "failed to write report: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for LayerError {}
+
+/// Errors encountered while loading a `SpanGraph` from a file
+#[derive(Debug)]
+pub enum ImportError {
+    /// The file could not be read
+    Io(std::io::Error),
+    /// The data does not describe a valid span graph
+    Invalid(String),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Io(error) => write!(f, "failed to read span graph: {}", error),
+            ImportError::Invalid(message) => write!(f, "invalid span graph: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImportError::Io(error) => Some(error),
+            ImportError::Invalid(_) => None,
+        }
+    }
+}
+
+/// Error returned when parsing an invalid `SpanFilter`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterError {
+    /// Byte offset of the error in the filter expression
+    pub position: usize,
+    /// Description of the error
+    pub message: String,
+}
+
+impl FilterError {
+    pub(crate) fn new(position: usize, message: impl Into<String>) -> FilterError {
+        FilterError {
+            position: position,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid filter at position {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+impl std::error::Error for FilterError {}
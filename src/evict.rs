@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use crate::SpanGraph;
+
+/// Strategy used to remove spans from a graph larger than the size limit
+/// set with `SpanTimingLayer::with_size_limit`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Remove the spans with the smallest elapsed time together with all
+    /// their calls. Spans only called from removed spans become roots.
+    DropColdest,
+    /// Remove the spans with the smallest elapsed time, re-attaching the
+    /// calls they made to their callers (see `SpanGraph::prune`). The time
+    /// spent in removed spans stays included in the time of their callers.
+    MergeIntoParent,
+}
+
+/// Amount of data removed from a graph to keep it under the size limit, see
+/// `SpanGraph::evicted`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvictionStats {
+    /// Number of span groups removed from the graph
+    pub spans: usize,
+    /// Total number of calls to the removed span groups
+    pub calls: usize,
+    /// Total elapsed time in the removed span groups
+    pub elapsed: Duration,
+}
+
+impl SpanGraph {
+    /// Get the amount of data removed from this graph to keep it under the
+    /// size limit set with `SpanTimingLayer::with_size_limit`
+    pub fn evicted(&self) -> EvictionStats {
+        self.evicted
+    }
+
+    /// Remove spans from this graph according to the configured eviction
+    /// policy if it contains more spans than the size limit. Spans which were
+    /// never called are kept, since they are the parents of spans which are
+    /// still running.
+    ///
+    /// Spans are removed until the graph is 10% below the size limit, so that
+    /// the graph does not need to be rebuilt every time a new span is created.
+    ///
+    /// Any `SpanIndex` obtained before calling this function is invalidated.
+    pub(crate) fn enforce_size_limit(&mut self) {
+        let (max, policy) = match self.settings.size_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+
+        let count = self.graph.node_count();
+        if count <= max {
+            return;
+        }
+
+        // spans which were never called yet are the parents of running spans,
+        // and will be called soon
+        let target = max - max / 10;
+        let mut nodes = self
+            .graph
+            .node_indices()
+            .filter(|&node| self.graph[node].called != 0)
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|&node| self.graph[node].elapsed);
+        let removed = nodes.into_iter().take(count - target).collect::<Vec<_>>();
+
+        let mut keep = vec![true; count];
+        for &node in &removed {
+            let span = &self.graph[node];
+            self.evicted.spans += 1;
+            self.evicted.calls += span.called;
+            self.evicted.elapsed += span.elapsed;
+            keep[node.index()] = false;
+        }
+
+        if policy == EvictionPolicy::MergeIntoParent {
+            self.bypass(&removed);
+        }
+
+        self.retain_nodes(&keep);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::tests::calls;
+
+    /// Create a graph where `root` calls `cold` (which calls `leaf`) and
+    /// `hot`, with an additional `running` span which was never called
+    fn graph(policy: EvictionPolicy) -> SpanGraph {
+        let mut graph = SpanGraph::new();
+        graph.settings.size_limit = Some((4, policy));
+
+        let root = graph.find_or_create("root");
+        let cold = graph.find_or_create("cold");
+        let leaf = graph.find_or_create("leaf");
+        let hot = graph.find_or_create("hot");
+        graph.find_or_create("running");
+
+        graph.increase_timing(root, Duration::from_millis(100));
+        graph.increase_timing(cold, Duration::from_millis(1));
+        graph.increase_timing(leaf, Duration::from_millis(50));
+        graph.increase_timing(hot, Duration::from_millis(80));
+        graph.increase_timing(hot, Duration::from_millis(20));
+
+        graph.increase_call_count(root, cold);
+        graph.increase_call_count(root, hot);
+        graph.increase_call_count(root, hot);
+        graph.increase_call_count(cold, leaf);
+        return graph;
+    }
+
+    fn evicted_cold(graph: &SpanGraph) {
+        assert_eq!(graph.span_count(), 4);
+        assert!(graph.find("cold").is_none());
+        assert!(graph.find("running").is_some());
+        assert_eq!(calls(graph, "root", "hot"), Some(2));
+        assert_eq!(
+            graph.evicted(),
+            EvictionStats {
+                spans: 1,
+                calls: 1,
+                elapsed: Duration::from_millis(1),
+            }
+        );
+    }
+
+    #[test]
+    fn drop_coldest() {
+        let mut graph = graph(EvictionPolicy::DropColdest);
+        graph.enforce_size_limit();
+        evicted_cold(&graph);
+
+        // the calls made by `cold` are removed with it
+        assert_eq!(calls(&graph, "root", "leaf"), None);
+        let leaf = graph.find("leaf").unwrap();
+        assert!(graph.roots().any(|root| root == leaf));
+    }
+
+    #[test]
+    fn merge_into_parent() {
+        let mut graph = graph(EvictionPolicy::MergeIntoParent);
+        graph.enforce_size_limit();
+        evicted_cold(&graph);
+
+        // the calls made by `cold` are re-attached to `root`
+        assert_eq!(calls(&graph, "root", "leaf"), Some(1));
+        let leaf = graph.find("leaf").unwrap();
+        assert!(graph.roots().all(|root| root != leaf));
+        let root = graph.find("root").unwrap();
+        assert_eq!(graph.span(root).elapsed, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn size_limit() {
+        let mut graph = graph(EvictionPolicy::DropColdest);
+        graph.settings.size_limit = Some((5, EvictionPolicy::DropColdest));
+        graph.enforce_size_limit();
+        assert_eq!(graph.span_count(), 5);
+        assert_eq!(graph.evicted(), EvictionStats::default());
+
+        graph.settings.size_limit = None;
+        graph.enforce_size_limit();
+        assert_eq!(graph.span_count(), 5);
+
+        // spans are removed until the graph is 10% below the limit
+        let mut graph = SpanGraph::new();
+        graph.settings.size_limit = Some((20, EvictionPolicy::DropColdest));
+        for i in 0..21 {
+            let span = graph.find_or_create(&format!("span {}", i));
+            graph.increase_timing(span, Duration::from_millis(i));
+        }
+        graph.enforce_size_limit();
+        assert_eq!(graph.span_count(), 18);
+        assert_eq!(graph.evicted().spans, 3);
+        assert!(graph.find("span 2").is_none());
+        assert!(graph.find("span 3").is_some());
+    }
+}
@@ -0,0 +1,428 @@
+use std::time::Duration;
+
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+
+use crate::{SpanGraph, SpanIndex};
+
+/// Maximal number of nodes in a call tree. The number of call paths can grow
+/// exponentially with the size of the graph, and calls past this limit are
+/// collapsed into a single node for each caller.
+pub(crate) const MAX_CALL_TREE_NODES: usize = 100_000;
+
+/// Name of call tree nodes containing collapsed calls
+const COLLAPSED_CALLS: &str = "…";
+
+/// Tree of calls, obtained by splitting the call graph into one node for each
+/// path from the outer spans.
+#[derive(Clone, Debug)]
+pub(crate) struct CallTree {
+    /// span corresponding to this node, or `None` for the calls collapsed
+    /// after reaching `MAX_CALL_TREE_NODES`
+    pub span: Option<SpanIndex>,
+    /// part of the span elapsed time attributed to this call path
+    pub elapsed: Duration,
+    /// number of calls to the span through this call path
+    pub count: usize,
+    /// calls made from this call path
+    pub children: Vec<CallTree>,
+}
+
+impl CallTree {
+    /// Get the name of the span corresponding to this node in `graph`
+    pub fn name<'a>(&self, graph: &'a SpanGraph) -> &'a str {
+        match self.span {
+            Some(span) => &graph.span(span).name,
+            None => COLLAPSED_CALLS,
+        }
+    }
+}
+
+impl SpanGraph {
+    /// Get the full graph in [GraphML](http://graphml.graphdrawing.org/)
+    /// format, with timing data as attributes on the nodes and the number of
+    /// calls as attribute on the edges. Times are given in microseconds.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_graphml(&self) -> String {
+        let mut output = String::new();
+        output += "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+        output += "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n";
+        output += "  <key id=\"name\" for=\"node\" attr.name=\"name\" attr.type=\"string\"/>\n";
+        output +=
+            "  <key id=\"elapsed\" for=\"node\" attr.name=\"elapsed\" attr.type=\"double\"/>\n";
+        output += "  <key id=\"called\" for=\"node\" attr.name=\"called\" attr.type=\"long\"/>\n";
+        output +=
+            "  <key id=\"max_depth\" for=\"node\" attr.name=\"max_depth\" attr.type=\"long\"/>\n";
+        output += "  <key id=\"count\" for=\"edge\" attr.name=\"count\" attr.type=\"long\"/>\n";
+        for (i, key) in self.metadata.keys().enumerate() {
+            output += &format!(
+                "  <key id=\"meta{}\" for=\"graph\" attr.name=\"{}\" attr.type=\"string\"/>\n",
+                i,
+                xml_escape(key)
+            );
+        }
+        output += "  <graph id=\"spans\" edgedefault=\"directed\">\n";
+        for (i, value) in self.metadata.values().enumerate() {
+            output += &format!("    <data key=\"meta{}\">{}</data>\n", i, xml_escape(value));
+        }
+
+        for span in self.spans() {
+            output += &format!("    <node id=\"n{}\">\n", span.id);
+            output += &format!(
+                "      <data key=\"name\">{}</data>\n",
+                xml_escape(&span.name)
+            );
+            output += &format!(
+                "      <data key=\"elapsed\">{}</data>\n",
+                span.elapsed.as_secs_f64() * 1e6
+            );
+            output += &format!("      <data key=\"called\">{}</data>\n", span.called);
+            output += &format!("      <data key=\"max_depth\">{}</data>\n", span.max_depth);
+            output += "    </node>\n";
+        }
+
+        for (i, call) in self.calls().enumerate() {
+            output += &format!(
+                "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">\n",
+                i,
+                self.span(call.caller).id,
+                self.span(call.callee).id,
+            );
+            output += &format!("      <data key=\"count\">{}</data>\n", call.count);
+            output += "    </edge>\n";
+        }
+
+        output += "  </graph>\n";
+        output += "</graphml>\n";
+        return output;
+    }
+
+    /// Get the call tree corresponding to this graph, starting with the spans
+    /// that have been called outside of any other span.
+    ///
+    /// Spans called from multiple places appear multiple times in the tree.
+    /// Their elapsed time is shared between the different callers
+    /// proportionally to the number of calls coming from each one. Recursive
+    /// calls are not expanded, and calls past `MAX_CALL_TREE_NODES` are
+    /// collapsed into a single `…` node for each caller.
+    pub(crate) fn call_tree(&self) -> Vec<CallTree> {
+        let mut roots = Vec::new();
+        let mut path = Vec::new();
+        let mut budget = MAX_CALL_TREE_NODES;
+        for node in self.graph.node_indices() {
+            let called = self.graph[node].called;
+            let from_callers = self
+                .graph
+                .edges_directed(node, Direction::Incoming)
+                .map(|edge| *edge.weight())
+                .sum::<usize>();
+
+            if called > from_callers {
+                let fraction = (called - from_callers) as f64 / called as f64;
+                roots.push(self.call_tree_node(
+                    node,
+                    called - from_callers,
+                    fraction,
+                    &mut path,
+                    &mut budget,
+                ));
+            }
+        }
+
+        return roots;
+    }
+
+    /// Build the call tree for `node`, where the current call `path` accounts
+    /// for `count` calls, i.e. `fraction` of the total calls to `node`.
+    /// `budget` is the remaining number of nodes which can be added to the
+    /// tree before collapsing calls.
+    fn call_tree_node(
+        &self,
+        node: NodeIndex,
+        count: usize,
+        fraction: f64,
+        path: &mut Vec<NodeIndex>,
+        budget: &mut usize,
+    ) -> CallTree {
+        *budget = budget.saturating_sub(1);
+        path.push(node);
+        let mut children = Vec::new();
+        let mut collapsed: Option<CallTree> = None;
+        for edge in self.graph.edges_directed(node, Direction::Outgoing) {
+            let child = edge.target();
+            if path.contains(&child) {
+                continue;
+            }
+
+            // calls can exist without timing data for the callee, for example
+            // after pruning or with `SpanGraph::increase_call_count`
+            let called = self.graph[child].called;
+            let child_fraction = if called == 0 {
+                0.0
+            } else {
+                fraction * *edge.weight() as f64 / called as f64
+            };
+
+            if *budget == 0 {
+                let collapsed = collapsed.get_or_insert_with(|| CallTree {
+                    span: None,
+                    elapsed: Duration::new(0, 0),
+                    count: 0,
+                    children: Vec::new(),
+                });
+                collapsed.elapsed += self.graph[child].elapsed.mul_f64(child_fraction);
+                collapsed.count += *edge.weight();
+                continue;
+            }
+
+            children.push(self.call_tree_node(child, *edge.weight(), child_fraction, path, budget));
+        }
+        path.pop();
+        children.extend(collapsed);
+
+        return CallTree {
+            span: Some(node.into()),
+            elapsed: self.graph[node].elapsed.mul_f64(fraction),
+            count: count,
+            children: children,
+        };
+    }
+
+    /// Get the call tree of this graph as JSON usable by
+    /// [d3-flame-graph](https://github.com/spiermar/d3-flame-graph), with
+    /// values in microseconds.
+    ///
+    /// Spans called from multiple places appear multiple times in the output,
+    /// their elapsed time being shared between callers proportionally to the
+    /// number of calls coming from each caller. For very large graphs, the
+    /// calls past 100 000 nodes are collapsed into nodes called `…`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_d3_flamegraph(&self) -> String {
+        fn to_json(graph: &SpanGraph, tree: &CallTree) -> json::JsonValue {
+            let mut children = json::JsonValue::new_array();
+            for child in &tree.children {
+                children
+                    .push(to_json(graph, child))
+                    .expect("failed to add flamegraph node to JSON");
+            }
+
+            json::object! {
+                "name" => tree.name(graph),
+                "value" => tree.elapsed.as_secs_f64() * 1e6,
+                "children" => children,
+            }
+        }
+
+        let mut total = Duration::new(0, 0);
+        let mut children = json::JsonValue::new_array();
+        for tree in self.call_tree() {
+            total += tree.elapsed;
+            children
+                .push(to_json(self, &tree))
+                .expect("failed to add flamegraph node to JSON");
+        }
+
+        return json::stringify(json::object! {
+            "name" => "all",
+            "value" => total.as_secs_f64() * 1e6,
+            "children" => children,
+            "metadata" => self.metadata_json(),
+        });
+    }
+
+    /// Get the full graph in the [callgrind] format, which can be opened with
+    /// [KCachegrind] and similar tools. Costs are given in nanoseconds.
+    ///
+    /// The inclusive cost of calls from one span to another is estimated by
+    /// sharing the callee elapsed time between callers proportionally to the
+    /// number of calls coming from each caller. The self cost of a span is its
+    /// elapsed time minus the inclusive cost of all the calls it made.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    ///
+    /// [callgrind]: https://valgrind.org/docs/manual/cl-format.html
+    /// [KCachegrind]: https://kcachegrind.github.io/
+    pub fn as_callgrind(&self) -> String {
+        let mut body = String::new();
+        let mut total = 0;
+        for node in self.graph.node_indices() {
+            let span = &self.graph[node];
+            body += &format!("fn=({}) {}\n", span.id, span.name);
+
+            let mut calls = String::new();
+            let mut callees_cost = 0;
+            for edge in self.graph.edges_directed(node, Direction::Outgoing) {
+                let callee = &self.graph[edge.target()];
+                let count = *edge.weight();
+                let cost = if edge.target() == node {
+                    // recursive calls are already included in the span cost
+                    0
+                } else {
+                    (callee.elapsed.as_nanos() * count as u128)
+                        .checked_div(callee.called as u128)
+                        .unwrap_or(0) as u64
+                };
+                callees_cost += cost;
+
+                calls += &format!("cfn=({}) {}\n", callee.id, callee.name);
+                calls += &format!("calls={} 0\n", count);
+                calls += &format!("0 {}\n", cost);
+            }
+
+            let self_cost = (span.elapsed.as_nanos() as u64).saturating_sub(callees_cost);
+            total += self_cost;
+            body += &format!("0 {}\n", self_cost);
+            body += &calls;
+            body += "\n";
+        }
+
+        let mut output = String::new();
+        output += "# callgrind format\n";
+        output += "version: 1\n";
+        output += "creator: tracing-timing-graph\n";
+        for (key, value) in &self.metadata {
+            output += &format!("desc: {}: {}\n", key, value);
+        }
+        output += "positions: line\n";
+        output += "events: ns\n";
+        output += &format!("summary: {}\n\n", total);
+        output += &body;
+        return output;
+    }
+
+    /// Get the full graph as [Cytoscape.js](https://js.cytoscape.org/)
+    /// elements JSON, which can be passed directly as the `elements` option
+    /// when creating a graph. Times are given in microseconds.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_cytoscape(&self) -> String {
+        let mut nodes = json::JsonValue::new_array();
+        for span in self.spans() {
+            nodes
+                .push(json::object! {
+                    "data" => json::object! {
+                        "id" => format!("n{}", span.id),
+                        "name" => span.name.clone(),
+                        "elapsed" => span.elapsed.as_secs_f64() * 1e6,
+                        "called" => span.called,
+                        "max_depth" => span.max_depth,
+                    }
+                })
+                .expect("failed to add node to JSON");
+        }
+
+        let mut edges = json::JsonValue::new_array();
+        for (i, call) in self.calls().enumerate() {
+            edges
+                .push(json::object! {
+                    "data" => json::object! {
+                        "id" => format!("e{}", i),
+                        "source" => format!("n{}", self.span(call.caller).id),
+                        "target" => format!("n{}", self.span(call.callee).id),
+                        "count" => call.count,
+                    }
+                })
+                .expect("failed to add edge to JSON");
+        }
+
+        return json::stringify(json::object! {
+            "nodes" => nodes,
+            "edges" => edges,
+        });
+    }
+}
+
+/// Escape the XML special characters in `text`
+pub(crate) fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&apos;",
+            c => escaped.push(c),
+        }
+    }
+    return escaped;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Format;
+
+    /// Graph where `root` calls `child`, which has no timing data
+    fn uncalled_child() -> SpanGraph {
+        let mut graph = SpanGraph::new();
+        let root = graph.find_or_create("root");
+        let child = graph.find_or_create("child");
+        graph.increase_timing(root, Duration::from_millis(3));
+        graph.increase_call_count(root, child);
+        return graph;
+    }
+
+    #[test]
+    fn flamegraph_uncalled_child() {
+        let graph = uncalled_child();
+        let flamegraph = json::parse(&graph.as_d3_flamegraph()).unwrap();
+
+        let root = &flamegraph["children"][0];
+        assert_eq!(root["name"], "root");
+        assert_eq!(root["value"], 3000.0);
+        assert_eq!(root["children"][0]["name"], "child");
+        assert_eq!(root["children"][0]["value"], 0.0);
+
+        let _ = graph.render(Format::D3Flamegraph);
+    }
+
+    fn count_nodes(tree: &CallTree) -> usize {
+        1 + tree.children.iter().map(count_nodes).sum::<usize>()
+    }
+
+    fn has_collapsed(tree: &CallTree) -> bool {
+        tree.span.is_none() || tree.children.iter().any(has_collapsed)
+    }
+
+    #[test]
+    fn call_tree_diamonds() {
+        // each layer doubles the number of call paths, giving 2^40 paths
+        let mut graph = SpanGraph::new();
+        let mut previous = graph.find_or_create("layer 0");
+        graph.increase_timing(previous, Duration::from_millis(1));
+        for i in 1..=40 {
+            let left = graph.find_or_create(&format!("left {}", i));
+            let right = graph.find_or_create(&format!("right {}", i));
+            let next = graph.find_or_create(&format!("layer {}", i));
+            for &span in &[left, right, next, next] {
+                graph.increase_timing(span, Duration::from_millis(1));
+            }
+            graph.increase_call_count(previous, left);
+            graph.increase_call_count(previous, right);
+            graph.increase_call_count(left, next);
+            graph.increase_call_count(right, next);
+            previous = next;
+        }
+
+        let roots = graph.call_tree();
+        assert_eq!(roots.len(), 1);
+        let nodes = count_nodes(&roots[0]);
+        assert!(nodes > MAX_CALL_TREE_NODES / 2);
+        assert!(nodes < 2 * MAX_CALL_TREE_NODES);
+        assert!(has_collapsed(&roots[0]));
+
+        let flamegraph = json::parse(&graph.as_d3_flamegraph()).unwrap();
+        assert_eq!(flamegraph["children"][0]["name"], "layer 0");
+    }
+
+    #[test]
+    fn callgrind_uncalled_child() {
+        let graph = uncalled_child();
+        let callgrind = graph.as_callgrind();
+        assert!(callgrind.contains("cfn=(1) child\ncalls=1 0\n0 0\n"));
+    }
+}
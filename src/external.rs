@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use tracing::{Dispatch, Metadata};
+use tracing_subscriber::Registry;
+
+use crate::graph::Invocation;
+use crate::{SpanGraph, SpanIndex, SpanTimingLayer};
+
+/// Handle used to record a duration measured outside of tracing (for
+/// example the execution time of a GPU kernel, obtained asynchronously from
+/// CUDA events) once it becomes available.
+///
+/// The handle captures the span entered when it is created, and the
+/// duration is recorded as a call from this span to a span group with the
+/// given name. Like other spans, the duration is attributed to the nearest
+/// recorded ancestor of the launching span, using the name given by its
+/// `otel.name` or `timing.name` field if any. The handle can be sent to
+/// another thread, and used after the launching span has been closed.
+///
+/// ```
+/// use std::time::Duration;
+/// use tracing_timing_graph::DeferredDuration;
+///
+/// #[tracing::instrument]
+/// fn launch_kernel() -> DeferredDuration {
+///     // ... start the work on the GPU
+///     DeferredDuration::new("gpu::kernel")
+/// }
+///
+/// let deferred = launch_kernel();
+/// // ... later, once the device timing is known
+/// deferred.record(Duration::from_micros(350));
+/// ```
+#[derive(Clone, Debug)]
+pub struct DeferredDuration {
+    name: String,
+    parent: Option<ExternalParent>,
+    dispatch: Dispatch,
+}
+
+/// Span launching the external work of a `DeferredDuration`
+#[derive(Clone, Debug)]
+pub(crate) struct ExternalParent {
+    pub metadata: &'static Metadata<'static>,
+    /// name of the span if overridden by one of its fields
+    pub name: Option<String>,
+}
+
+impl DeferredDuration {
+    /// Create a new handle for a span group called `name`, called from the
+    /// span currently entered on this thread
+    pub fn new(name: &str) -> DeferredDuration {
+        let dispatch = tracing::dispatcher::get_default(Dispatch::clone);
+        let current = tracing::Span::current();
+
+        let layer = dispatch.downcast_ref::<SpanTimingLayer>();
+        let registry = dispatch.downcast_ref::<Registry>();
+        let parent = match (layer, registry, current.id()) {
+            (Some(layer), Some(registry), Some(id)) => layer.external_parent(registry, &id),
+            // without a registry, only the current span is available
+            _ => current.metadata().map(|metadata| ExternalParent {
+                metadata: metadata,
+                name: None,
+            }),
+        };
+
+        DeferredDuration {
+            name: name.into(),
+            parent: parent,
+            dispatch: dispatch,
+        }
+    }
+
+    /// Record the externally measured `duration`. This does nothing if the
+    /// subscriber which was active when creating this handle does not contain
+    /// a `SpanTimingLayer`. If the subscriber contains multiple layers, the
+    /// duration is only recorded in the first one.
+    pub fn record(self, duration: Duration) {
+        if let Some(layer) = self.dispatch.downcast_ref::<SpanTimingLayer>() {
+            layer.record_external(self.parent.as_ref(), &self.name, duration);
+        }
+    }
+}
+
+impl SpanGraph {
+    /// Record an externally measured `duration` for the span group called
+    /// `name`, as a call from the `parent` span if any. This can be used to
+    /// include time measured outside of tracing in the graph, see
+    /// `DeferredDuration` to do this from instrumented code.
+    pub fn add_external_duration(
+        &mut self,
+        parent: Option<SpanIndex>,
+        name: &str,
+        duration: Duration,
+    ) -> SpanIndex {
+        let span = self.find_or_create(name);
+        let depth = parent.map_or(0, |parent| self.span(parent).max_depth) + 1;
+        let invocation = Invocation {
+            elapsed: duration,
+            depth: depth,
+            ..Default::default()
+        };
+
+        if self.record_invocation(span, &invocation) {
+            if let Some(parent) = parent {
+                self.increase_call_count(parent, span);
+            }
+        }
+
+        return span;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::tests::calls;
+    use crate::layer::NOOP;
+    use crate::EvictionPolicy;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn launch(layer: SpanTimingLayer) -> DeferredDuration {
+        let subscriber = Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let _outer = tracing::info_span!(
+                "outer",
+                timed = tracing::field::Empty,
+                otel.name = "request"
+            )
+            .entered();
+            let _inner = tracing::info_span!("inner").entered();
+            DeferredDuration::new("gpu::kernel")
+        })
+    }
+
+    #[test]
+    fn nearest_recorded_parent() {
+        // the layer does not record anything with the `noop` features
+        if NOOP {
+            return;
+        }
+
+        let layer = SpanTimingLayer::new().with_marker_field("timed");
+        let view = layer.view();
+        launch(layer).record(Duration::from_micros(350));
+
+        let graph = view.read();
+        assert_eq!(graph.span_count(), 2);
+        assert_eq!(calls(&graph, "request", "gpu::kernel"), Some(1));
+        let kernel = graph.span(graph.find("gpu::kernel").unwrap());
+        assert_eq!(kernel.elapsed, Duration::from_micros(350));
+    }
+
+    #[test]
+    fn size_limit() {
+        // the layer does not record anything with the `noop` features
+        if NOOP {
+            return;
+        }
+
+        let layer = SpanTimingLayer::new()
+            .with_marker_field("timed")
+            .with_size_limit(1, EvictionPolicy::DropColdest);
+        let view = layer.view();
+        launch(layer).record(Duration::from_micros(350));
+
+        let graph = view.read();
+        assert_eq!(graph.span_count(), 1);
+        assert_eq!(graph.evicted().spans, 1);
+    }
+}
@@ -0,0 +1,649 @@
+use std::time::Duration;
+
+use regex::Regex;
+use tracing::Level;
+
+use crate::{FilterError, SpanTiming};
+
+/// Expression selecting spans in reports, see `ReportOptions::with_filter`.
+///
+/// Filters are written as comparisons between a span field and a value,
+/// combined with `&&`, `||`, `!` and parentheses:
+///
+/// ```
+/// # use tracing_timing_graph::SpanFilter;
+/// let filter: SpanFilter = r#"name ~ "compute" && elapsed > 10ms"#.parse().unwrap();
+/// let filter: SpanFilter = "level <= INFO && !(called < 10 || mean < 1.5us)".parse().unwrap();
+/// ```
+///
+/// The following fields are available:
+///
+/// - `name`, `target` and `module` are compared to strings with `==` and
+///   `!=`, or matched against a regular expression with `~` and `!~`;
+/// - `elapsed`, `mean`, `max`, `stddev`, `idle` and `lifetime` are compared
+///   to durations with a unit of `ns`, `us`, `ms` or `s`;
+/// - `called`, `polls`, `depth`, `errors`, `successes`, `failures` and
+///   `panics` are compared to integers;
+/// - `level` is compared to a verbosity level, more verbose levels being
+///   larger (`TRACE > DEBUG > INFO > WARN > ERROR`).
+///
+/// Comparisons involving a field which is not known for a span (such as the
+/// target or level of spans not created by a `SpanTimingLayer`) are false.
+#[derive(Clone, Debug)]
+pub struct SpanFilter {
+    expression: Expression,
+}
+
+#[derive(Clone, Debug)]
+enum Expression {
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Text(TextField, bool, String),
+    Match(TextField, bool, Regex),
+    Duration(DurationField, Comparison, Duration),
+    Count(CountField, Comparison, usize),
+    Level(Comparison, Level),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TextField {
+    Name,
+    Target,
+    Module,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum DurationField {
+    Elapsed,
+    Mean,
+    Max,
+    Stddev,
+    Idle,
+    Lifetime,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CountField {
+    Called,
+    Polls,
+    Depth,
+    Errors,
+    Successes,
+    Failures,
+    Panics,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Match,
+    NotMatch,
+}
+
+impl Comparison {
+    fn compare<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterEqual => left >= right,
+            Comparison::Match | Comparison::NotMatch => unreachable!(),
+        }
+    }
+}
+
+impl SpanFilter {
+    /// Parse a filter from the given `expression`
+    pub fn parse(expression: &str) -> Result<SpanFilter, FilterError> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            current: 0,
+            end: expression.len(),
+        };
+
+        let expression = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.current) {
+            return Err(FilterError::new(token.position, "expected '&&' or '||'"));
+        }
+
+        return Ok(SpanFilter {
+            expression: expression,
+        });
+    }
+
+    /// Check if `span` is selected by this filter
+    pub fn matches(&self, span: &SpanTiming) -> bool {
+        self.expression.matches(span)
+    }
+}
+
+impl std::str::FromStr for SpanFilter {
+    type Err = FilterError;
+
+    fn from_str(expression: &str) -> Result<SpanFilter, FilterError> {
+        SpanFilter::parse(expression)
+    }
+}
+
+impl Expression {
+    fn matches(&self, span: &SpanTiming) -> bool {
+        match self {
+            Expression::And(left, right) => left.matches(span) && right.matches(span),
+            Expression::Or(left, right) => left.matches(span) || right.matches(span),
+            Expression::Not(expression) => !expression.matches(span),
+            Expression::Text(field, equal, value) => match field.get(span) {
+                Some(text) => (text == value) == *equal,
+                None => !equal,
+            },
+            Expression::Match(field, matching, regex) => match field.get(span) {
+                Some(text) => regex.is_match(text) == *matching,
+                None => !matching,
+            },
+            Expression::Duration(field, comparison, value) => {
+                comparison.compare(field.get(span), *value)
+            }
+            Expression::Count(field, comparison, value) => {
+                comparison.compare(field.get(span), *value)
+            }
+            Expression::Level(comparison, value) => match span.level {
+                Some(level) => comparison.compare(level, *value),
+                None => false,
+            },
+        }
+    }
+}
+
+impl TextField {
+    fn get(self, span: &SpanTiming) -> Option<&str> {
+        match self {
+            TextField::Name => Some(&span.name),
+            TextField::Target => span.target.as_deref(),
+            TextField::Module => span.module_path.as_deref(),
+        }
+    }
+}
+
+impl DurationField {
+    fn get(self, span: &SpanTiming) -> Duration {
+        match self {
+            DurationField::Elapsed => span.elapsed,
+            DurationField::Mean => span.mean(),
+            DurationField::Max => span.max,
+            DurationField::Stddev => span.stddev(),
+            DurationField::Idle => span.idle,
+            DurationField::Lifetime => span.lifetime,
+        }
+    }
+}
+
+impl CountField {
+    fn get(self, span: &SpanTiming) -> usize {
+        match self {
+            CountField::Called => span.called,
+            CountField::Polls => span.polls,
+            CountField::Depth => span.max_depth,
+            CountField::Errors => span.errors,
+            CountField::Successes => span.successes,
+            CountField::Failures => span.failures,
+            CountField::Panics => span.panics,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    /// Field name, or bare value such as a level
+    Identifier(String),
+    /// Quoted string
+    String(String),
+    /// Number, possibly followed by a unit
+    Number(String),
+    /// Operator or parenthesis
+    Symbol(&'static str),
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    /// Byte offset of the token in the expression
+    position: usize,
+}
+
+const SYMBOLS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "!~", "<", ">", "~", "!", "(", ")",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.char_indices().peekable();
+    while let Some(&(position, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    // only unescape quotes and backslashes, keeping regex
+                    // escapes such as `\d` intact
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) if c == '"' || c == '\\' => value.push(c),
+                        Some((_, c)) => {
+                            value.push('\\');
+                            value.push(c);
+                        }
+                        None => return Err(FilterError::new(position, "unterminated string")),
+                    },
+                    Some((_, c)) => value.push(c),
+                    None => return Err(FilterError::new(position, "unterminated string")),
+                }
+            }
+            tokens.push(Token {
+                kind: TokenKind::String(value),
+                position: position,
+            });
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut value = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_alphanumeric() || c == '.' {
+                    value.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token {
+                kind: TokenKind::Number(value),
+                position: position,
+            });
+        } else if c.is_alphabetic() || c == '_' {
+            let mut value = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    value.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token {
+                kind: TokenKind::Identifier(value),
+                position: position,
+            });
+        } else {
+            let rest = &expression[position..];
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(*symbol))
+                .ok_or_else(|| FilterError::new(position, format!("unexpected '{}'", c)))?;
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push(Token {
+                kind: TokenKind::Symbol(symbol),
+                position: position,
+            });
+        }
+    }
+    return Ok(tokens);
+}
+
+/// Recursive descent parser for filter expressions, with the usual
+/// precedence of `!` over `&&` over `||`
+struct Parser {
+    tokens: Vec<Token>,
+    current: usize,
+    /// Length of the expression, used as the position of errors at the end
+    end: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Result<Token, FilterError> {
+        let token = self
+            .tokens
+            .get(self.current)
+            .cloned()
+            .ok_or_else(|| FilterError::new(self.end, "unexpected end of filter"))?;
+        self.current += 1;
+        return Ok(token);
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        match self.tokens.get(self.current) {
+            Some(Token {
+                kind: TokenKind::Symbol(s),
+                ..
+            }) if *s == symbol => {
+                self.current += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expression, FilterError> {
+        let mut expression = self.and()?;
+        while self.eat("||") {
+            expression = Expression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+        return Ok(expression);
+    }
+
+    fn and(&mut self) -> Result<Expression, FilterError> {
+        let mut expression = self.not()?;
+        while self.eat("&&") {
+            expression = Expression::And(Box::new(expression), Box::new(self.not()?));
+        }
+        return Ok(expression);
+    }
+
+    fn not(&mut self) -> Result<Expression, FilterError> {
+        if self.eat("!") {
+            return Ok(Expression::Not(Box::new(self.not()?)));
+        }
+
+        if self.eat("(") {
+            let expression = self.or()?;
+            if !self.eat(")") {
+                let position = self
+                    .tokens
+                    .get(self.current)
+                    .map_or(self.end, |t| t.position);
+                return Err(FilterError::new(position, "expected ')'"));
+            }
+            return Ok(expression);
+        }
+
+        return self.comparison();
+    }
+
+    fn comparison(&mut self) -> Result<Expression, FilterError> {
+        let field = self.next()?;
+        let name = match field.kind {
+            TokenKind::Identifier(ref name) => name.as_str(),
+            _ => return Err(FilterError::new(field.position, "expected a field name")),
+        };
+
+        let operator = self.next()?;
+        let comparison = match operator.kind {
+            TokenKind::Symbol("==") => Comparison::Equal,
+            TokenKind::Symbol("!=") => Comparison::NotEqual,
+            TokenKind::Symbol("<") => Comparison::Less,
+            TokenKind::Symbol("<=") => Comparison::LessEqual,
+            TokenKind::Symbol(">") => Comparison::Greater,
+            TokenKind::Symbol(">=") => Comparison::GreaterEqual,
+            TokenKind::Symbol("~") => Comparison::Match,
+            TokenKind::Symbol("!~") => Comparison::NotMatch,
+            _ => {
+                return Err(FilterError::new(
+                    operator.position,
+                    "expected a comparison operator",
+                ))
+            }
+        };
+
+        let value = self.next()?;
+        let position = value.position;
+
+        let text = match name {
+            "name" => Some(TextField::Name),
+            "target" => Some(TextField::Target),
+            "module" => Some(TextField::Module),
+            _ => None,
+        };
+        if let Some(field) = text {
+            let value = match value.kind {
+                TokenKind::String(value) => value,
+                _ => return Err(FilterError::new(position, "expected a quoted string")),
+            };
+
+            return match comparison {
+                Comparison::Equal | Comparison::NotEqual => Ok(Expression::Text(
+                    field,
+                    comparison == Comparison::Equal,
+                    value,
+                )),
+                Comparison::Match | Comparison::NotMatch => {
+                    let regex = Regex::new(&value).map_err(|error| {
+                        FilterError::new(position, format!("invalid regex: {}", error))
+                    })?;
+                    Ok(Expression::Match(
+                        field,
+                        comparison == Comparison::Match,
+                        regex,
+                    ))
+                }
+                _ => Err(FilterError::new(
+                    operator.position,
+                    format!("'{}' can only be compared with ==, !=, ~ or !~", name),
+                )),
+            };
+        }
+
+        if comparison == Comparison::Match || comparison == Comparison::NotMatch {
+            return Err(FilterError::new(
+                operator.position,
+                format!("'{}' can not be matched against a regex", name),
+            ));
+        }
+
+        let duration = match name {
+            "elapsed" => Some(DurationField::Elapsed),
+            "mean" => Some(DurationField::Mean),
+            "max" => Some(DurationField::Max),
+            "stddev" => Some(DurationField::Stddev),
+            "idle" => Some(DurationField::Idle),
+            "lifetime" => Some(DurationField::Lifetime),
+            _ => None,
+        };
+        if let Some(field) = duration {
+            let value = match value.kind {
+                TokenKind::Number(value) => parse_duration(&value, position)?,
+                _ => return Err(FilterError::new(position, "expected a duration")),
+            };
+            return Ok(Expression::Duration(field, comparison, value));
+        }
+
+        let count = match name {
+            "called" => Some(CountField::Called),
+            "polls" => Some(CountField::Polls),
+            "depth" => Some(CountField::Depth),
+            "errors" => Some(CountField::Errors),
+            "successes" => Some(CountField::Successes),
+            "failures" => Some(CountField::Failures),
+            "panics" => Some(CountField::Panics),
+            _ => None,
+        };
+        if let Some(field) = count {
+            let value = match value.kind {
+                TokenKind::Number(value) => value
+                    .parse()
+                    .map_err(|_| FilterError::new(position, "invalid integer"))?,
+                _ => return Err(FilterError::new(position, "expected an integer")),
+            };
+            return Ok(Expression::Count(field, comparison, value));
+        }
+
+        if name == "level" {
+            let value = match value.kind {
+                TokenKind::Identifier(value) | TokenKind::String(value) => value
+                    .parse()
+                    .map_err(|_| FilterError::new(position, "invalid level"))?,
+                _ => return Err(FilterError::new(position, "expected a level")),
+            };
+            return Ok(Expression::Level(comparison, value));
+        }
+
+        return Err(FilterError::new(
+            field.position,
+            format!("unknown field '{}'", name),
+        ));
+    }
+}
+
+/// Parse a duration such as `500us`, `1.5ms` or `2s`, found at `position` in
+/// the filter expression
+fn parse_duration(value: &str, position: usize) -> Result<Duration, FilterError> {
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| FilterError::new(position, "invalid duration"))?;
+    let scale = match unit {
+        "ns" => 1e-9,
+        "us" | "µs" => 1e-6,
+        "ms" => 1e-3,
+        "s" => 1.0,
+        _ => return Err(FilterError::new(position, "invalid duration unit")),
+    };
+
+    return Duration::try_from_secs_f64(number * scale)
+        .map_err(|_| FilterError::new(position, "duration is too large"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span() -> SpanTiming {
+        let mut span = SpanTiming::new("app::compute".into(), 0);
+        span.target = Some("app".into());
+        span.level = Some(Level::DEBUG);
+        span.elapsed = Duration::from_millis(20);
+        span.called = 4;
+        return span;
+    }
+
+    fn matches(filter: &str) -> bool {
+        SpanFilter::parse(filter).unwrap().matches(&span())
+    }
+
+    fn parse_error(filter: &str) -> FilterError {
+        SpanFilter::parse(filter).unwrap_err()
+    }
+
+    #[test]
+    fn text() {
+        assert!(matches(r#"name == "app::compute""#));
+        assert!(!matches(r#"name != "app::compute""#));
+        assert!(matches(r#"name ~ "comp""#));
+        assert!(matches(r#"name !~ "^load""#));
+        assert!(matches(r#"target == "app""#));
+        assert!(matches(r#"name ~ "\w+::compute$""#));
+        assert!(matches(r#"name ~ "app::\d*compute""#));
+
+        // the module path of this span is unknown
+        assert!(!matches(r#"module == "app""#));
+        assert!(!matches(r#"module ~ "app""#));
+    }
+
+    #[test]
+    fn durations() {
+        assert!(matches("elapsed > 10ms"));
+        assert!(matches("elapsed == 20ms"));
+        assert!(matches("elapsed >= 0.02s"));
+        assert!(matches("elapsed < 20000001ns"));
+        assert!(matches("elapsed <= 20000us"));
+        assert!(matches("elapsed <= 20000µs"));
+        assert!(!matches("elapsed < .5ms"));
+        assert!(matches("mean == 5ms"));
+    }
+
+    #[test]
+    fn counts_and_levels() {
+        assert!(matches("called == 4"));
+        assert!(matches("called > 3 && called <= 4"));
+        assert!(!matches("errors != 0"));
+
+        assert!(matches("level == DEBUG"));
+        assert!(matches(r#"level == "DEBUG""#));
+        assert!(matches("level > INFO"));
+        assert!(!matches("level >= TRACE"));
+    }
+
+    #[test]
+    fn operators() {
+        assert!(matches("called == 4 && elapsed > 1ms"));
+        assert!(!matches("called == 3 && elapsed > 1ms"));
+        assert!(matches("called == 3 || elapsed > 1ms"));
+        assert!(matches("!(called == 3)"));
+        assert!(matches("!!(called == 4)"));
+
+        // `&&` binds tighter than `||`, and `!` tighter than `&&`
+        assert!(matches("called == 4 || called == 3 && called == 2"));
+        assert!(!matches("(called == 4 || called == 3) && called == 2"));
+        assert!(!matches("!called == 4 && called == 4"));
+        assert!(matches("((called == 4))"));
+    }
+
+    #[test]
+    fn errors() {
+        let check = |filter: &str, position: usize, message: &str| {
+            let error = parse_error(filter);
+            assert_eq!(error.position, position, "{}: {}", filter, error);
+            assert_eq!(error.message, message, "{}", filter);
+        };
+
+        check("", 0, "unexpected end of filter");
+        check("called", 6, "unexpected end of filter");
+        check("called ==", 9, "unexpected end of filter");
+        check("called == 4 called == 4", 12, "expected '&&' or '||'");
+        check("(called == 4", 12, "expected ')'");
+        check("called == 4 && )", 15, "expected a field name");
+        check("called 4", 7, "expected a comparison operator");
+        check("called == 4 # 3", 12, "unexpected '#'");
+        check(r#"name == "app"#, 8, "unterminated string");
+        check("name == app", 8, "expected a quoted string");
+        check(
+            r#"name < "app""#,
+            5,
+            "'name' can only be compared with ==, !=, ~ or !~",
+        );
+        check(
+            r#"called ~ "4""#,
+            7,
+            "'called' can not be matched against a regex",
+        );
+        check("called == 1.5", 10, "invalid integer");
+        check(r#"called == "4""#, 10, "expected an integer");
+        check("elapsed > 10", 10, "invalid duration unit");
+        check("elapsed > 10h", 10, "invalid duration unit");
+        check("elapsed > 1.2.3ms", 10, "invalid duration");
+        check(r#"elapsed > "10ms""#, 10, "expected a duration");
+        check("level == LOUD", 9, "invalid level");
+        check("level == 3", 9, "expected a level");
+        check("colour == 3", 0, "unknown field 'colour'");
+
+        let error = parse_error(r#"name ~ "(""#);
+        assert_eq!(error.position, 7);
+        assert!(error.message.starts_with("invalid regex: "));
+    }
+
+    #[test]
+    fn extreme_durations() {
+        let error = parse_error("called == 4 && elapsed > 99999999999999999999999s");
+        assert_eq!(error.position, 25);
+        assert_eq!(error.message, "duration is too large");
+
+        // there are no exponents in durations
+        assert_eq!(
+            parse_error("elapsed > 1e300s").message,
+            "invalid duration unit"
+        );
+
+        let filter = SpanFilter::parse("elapsed < 1000000000000s").unwrap();
+        assert!(filter.matches(&span()));
+    }
+}
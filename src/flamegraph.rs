@@ -0,0 +1,65 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::export::CallTree;
+use crate::SpanGraph;
+
+impl SpanGraph {
+    /// Render the call tree of this graph as a flamegraph in SVG format
+    /// using [inferno](https://github.com/jonhoo/inferno), and write it to
+    /// the file at `path`.
+    ///
+    /// Spans called from multiple places appear multiple times in the
+    /// flamegraph, their elapsed time being shared between callers
+    /// proportionally to the number of calls coming from each caller. For
+    /// very large graphs, the calls past 100 000 nodes are collapsed into
+    /// nodes called `…`.
+    pub fn write_flamegraph_svg<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_flamegraph_svg_to(&mut file)?;
+        return file.flush();
+    }
+
+    /// Render the call tree of this graph as a flamegraph in SVG format,
+    /// and write it to the given `writer`. See
+    /// `SpanGraph::write_flamegraph_svg`.
+    pub fn write_flamegraph_svg_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut lines = Vec::new();
+        let mut stack = Vec::new();
+        for tree in self.call_tree() {
+            self.folded_stacks(&tree, &mut stack, &mut lines);
+        }
+
+        let mut options = inferno::flamegraph::Options::default();
+        options.title = "Span timings".into();
+        options.count_name = "µs".into();
+
+        inferno::flamegraph::from_lines(&mut options, lines.iter().map(String::as_str), writer)
+            .map_err(|error| io::Error::other(error.to_string()))
+    }
+
+    /// Add the lines corresponding to `tree` in the folded stacks format to
+    /// `lines`, with the self time of each call path in microseconds.
+    /// `stack` contains the names of the spans calling `tree`.
+    fn folded_stacks(&self, tree: &CallTree, stack: &mut Vec<String>, lines: &mut Vec<String>) {
+        // ';' separates frames in the folded stacks format
+        stack.push(tree.name(self).replace(';', ":"));
+
+        let children = tree
+            .children
+            .iter()
+            .map(|child| child.elapsed)
+            .sum::<Duration>();
+        let self_time = tree.elapsed.saturating_sub(children).as_micros();
+        if self_time > 0 {
+            lines.push(format!("{} {}", stack.join(";"), self_time));
+        }
+
+        for child in &tree.children {
+            self.folded_stacks(child, stack, lines);
+        }
+        stack.pop();
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::mem::{size_of, size_of_val};
+use std::time::Duration;
+
+use tracing::callsite::Identifier;
+
+use crate::{LatencyObjective, SpanGraph, SpanIndex, SpanTiming};
+
+/// Approximate number of bytes used by each entry of a `BTreeMap`, in
+/// addition to the key and value
+const BTREE_ENTRY_OVERHEAD: usize = 2 * size_of::<usize>();
+
+impl SpanGraph {
+    /// Estimate the number of bytes used by this graph, including the span
+    /// data, calls, names, recorded durations and time series. This allows
+    /// to monitor the memory footprint of the profiler in production, for
+    /// example together with `SpanTimingLayer::with_size_limit`.
+    ///
+    /// This is an estimate: allocator overhead and the internal structure of
+    /// maps are only approximated.
+    pub fn memory_usage(&self) -> usize {
+        let mut size = size_of::<SpanGraph>();
+
+        size += size_of_val(self.graph.raw_nodes());
+        size += size_of_val(self.graph.raw_edges());
+        size += self.spans().map(span_heap_size).sum::<usize>();
+
+        size += self.follows.len() * (size_of::<((usize, usize), usize)>() + BTREE_ENTRY_OVERHEAD);
+        size += string_map_size(&self.metadata, |value| value.capacity());
+        size += string_map_size(&self.settings.budgets, |_| size_of::<Duration>());
+        size += string_map_size(&self.settings.objectives, |objectives| {
+            size_of::<Vec<LatencyObjective>>() + size_of_val(objectives.as_slice())
+        });
+        size += self.callsites.capacity() * size_of::<(Identifier, SpanIndex)>();
+        if let Some(ref timeline) = self.timeline {
+            size += timeline.heap_size();
+        }
+
+        return size;
+    }
+}
+
+/// Get the number of bytes allocated on the heap by `span`
+fn span_heap_size(span: &SpanTiming) -> usize {
+    let string = |value: &Option<String>| value.as_ref().map_or(0, String::capacity);
+
+    let mut size = span.name.capacity();
+    size += string(&span.target) + string(&span.module_path) + string(&span.span_name);
+    size += span.durations.capacity() * size_of::<Duration>();
+    size += span.recent.capacity() * size_of::<Duration>();
+    size += string_map_size(&span.counters, |_| size_of::<f64>());
+    size += string_map_size(&span.metrics, |_| size_of::<f64>());
+    size += span.tasks.len() * (size_of::<(u64, Duration)>() + BTREE_ENTRY_OVERHEAD);
+    size += string_map_size(&span.threads, |_| size_of::<Duration>());
+    if let Some(ref series) = span.series {
+        size += series.heap_size();
+    }
+    if let Some(ref window) = span.window {
+        size += window.heap_size();
+    }
+    return size;
+}
+
+/// Get the number of bytes used by a map with `String` keys, using
+/// `value_size` to get the size of each value
+fn string_map_size<V, F>(map: &BTreeMap<String, V>, value_size: F) -> usize
+where
+    F: Fn(&V) -> usize,
+{
+    map.iter()
+        .map(|(key, value)| {
+            size_of::<String>() + key.capacity() + value_size(value) + BTREE_ENTRY_OVERHEAD
+        })
+        .sum()
+}
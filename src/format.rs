@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{ColorMode, ReportOptions, SpanGraph};
+
+/// Output formats supported by `SpanGraph::render` and `SpanGraph::write_to`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Per span summary table, see `SpanGraph::as_table`
+    Table,
+    /// Indented call tree, see `SpanGraph::as_tree`
+    Tree,
+    /// Spans sorted by self time, see `SpanGraph::as_pareto_table`
+    Pareto,
+    /// JSON, see `SpanGraph::as_json`
+    Json,
+    /// graphviz dot, see `SpanGraph::as_dot`
+    Dot,
+    /// GraphML, see `SpanGraph::as_graphml`
+    GraphML,
+    /// callgrind, see `SpanGraph::as_callgrind`
+    Callgrind,
+    /// d3-flame-graph JSON, see `SpanGraph::as_d3_flamegraph`
+    D3Flamegraph,
+    /// Cytoscape.js JSON, see `SpanGraph::as_cytoscape`
+    Cytoscape,
+    /// mermaid gantt chart of the timeline, see `SpanGraph::as_mermaid_gantt`
+    MermaidGantt,
+    /// plotly JSON of the timeline, see `SpanGraph::as_plotly_timeline`
+    PlotlyTimeline,
+    /// SVG image of the graph, see `SpanGraph::as_svg`
+    Svg,
+}
+
+impl SpanGraph {
+    /// Render this graph in the given `format`. Since the output is usually
+    /// written to a file, tables, trees and pareto tables are never colored.
+    pub fn render(&self, format: Format) -> String {
+        let plain = ReportOptions::new().with_colors(ColorMode::Never);
+        match format {
+            Format::Table => self.as_table_with(&plain),
+            Format::Tree => self.as_tree_with(&plain),
+            Format::Pareto => self.as_pareto_table_with(&plain),
+            Format::Json => self.as_json(),
+            Format::Dot => self.as_dot(),
+            Format::GraphML => self.as_graphml(),
+            Format::Callgrind => self.as_callgrind(),
+            Format::D3Flamegraph => self.as_d3_flamegraph(),
+            Format::Cytoscape => self.as_cytoscape(),
+            Format::MermaidGantt => self.as_mermaid_gantt(),
+            Format::PlotlyTimeline => self.as_plotly_timeline(),
+            Format::Svg => self.as_svg(),
+        }
+    }
+
+    /// Write this graph in the given `format` to `writer`
+    pub fn write_to<W: Write>(&self, format: Format, mut writer: W) -> std::io::Result<()> {
+        if format == Format::Json {
+            self.write_json(&mut writer)?;
+        } else {
+            writer.write_all(self.render(format).as_bytes())?;
+        }
+        writer.flush()
+    }
+
+    /// Write this graph in the given `format` to the file at `path`.
+    ///
+    /// The data is first written to a temporary file next to `path`, which is
+    /// then renamed to `path`. External tools watching the file will never
+    /// see partial output.
+    pub fn write_file<P: AsRef<Path>>(&self, path: P, format: Format) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        let result = File::create(&temporary).and_then(|file| {
+            self.write_to(format, BufWriter::new(&file))?;
+            file.sync_all()
+        });
+
+        if let Err(error) = result {
+            let _ = std::fs::remove_file(&temporary);
+            return Err(error);
+        }
+
+        return std::fs::rename(&temporary, path);
+    }
+
+    /// Write this graph to the file configured with
+    /// `SpanTimingLayer::with_output`, if any.
+    pub fn flush(&self) -> std::io::Result<()> {
+        match self.settings.output {
+            Some((ref path, format)) => self.write_file(path, format),
+            None => Ok(()),
+        }
+    }
+
+    /// Write this graph in the given `format` to `writer`, compressing the
+    /// output with gzip. This is useful for large graphs, where the JSON
+    /// output can take multiple megabytes.
+    ///
+    /// ```no_run
+    /// # use tracing_timing_graph::{Format, SpanGraph};
+    /// # let graph = SpanGraph::new();
+    /// let file = std::fs::File::create("timings.json.gz")?;
+    /// graph.write_gzip_to(Format::Json, file)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(feature = "gzip")]
+    pub fn write_gzip_to<W: Write>(&self, format: Format, writer: W) -> std::io::Result<()> {
+        let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+        self.write_to(format, &mut encoder)?;
+        encoder.finish()?.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn rendered_reports_are_plain() {
+        let mut graph = SpanGraph::new();
+        let root = graph.find_or_create("root");
+        let child = graph.find_or_create("child");
+        graph.increase_timing(root, Duration::from_millis(10));
+        graph.increase_timing(child, Duration::from_millis(8));
+        graph.increase_call_count(root, child);
+
+        let colored = ReportOptions::new().with_colors(ColorMode::Always);
+        assert!(graph.as_table_with(&colored).contains("\x1b["));
+        assert!(graph.as_tree_with(&colored).contains("\x1b["));
+        assert!(graph.as_pareto_table_with(&colored).contains("\x1b["));
+
+        for format in [Format::Table, Format::Tree, Format::Pareto] {
+            assert!(!graph.render(format).contains("\x1b["), "{:?}", format);
+        }
+    }
+}
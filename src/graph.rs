@@ -1,11 +1,22 @@
-use petgraph::dot::Dot;
 use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
 use petgraph::Direction;
 
+use tracing::callsite::Identifier;
+use tracing::Level;
+
 use term_table::row::Row;
 use term_table::table_cell::{Alignment, TableCell};
 
-use std::time::Duration;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::report::ReportOptions;
+use crate::series::{Bucket, MovingAverage, RollingWindow, TimeSeries};
+use crate::timeline::{Timeline, TimelineEntry};
+use crate::{EvictionPolicy, EvictionStats, Format, HardwareCounters, LatencyObjective};
 
 /// Data associated with a set of span sharing the same name.
 ///
@@ -21,10 +32,99 @@ pub struct SpanTiming {
     pub id: usize,
     /// Full span name, including the module path or span target
     pub name: String,
+    /// Verbosity level of the spans in this group, `None` for spans which
+    /// were not created by a `SpanTimingLayer`
+    pub level: Option<Level>,
+    /// Target of the first span in this group, `None` for spans which were
+    /// not created by a `SpanTimingLayer`
+    pub target: Option<String>,
+    /// Module path of the first span in this group, if known
+    pub module_path: Option<String>,
+    /// Bare name of the first span in this group, without target or module
+    /// path, if known
+    pub span_name: Option<String>,
     /// Total elapsed time in all spans sharing this name
     pub elapsed: Duration,
     /// Number of time a span with this name have been called
     pub called: usize,
+    /// Calls and elapsed time bucketed in fixed time windows, if enabled with
+    /// `SpanTimingLayer::with_time_series`
+    pub series: Option<TimeSeries>,
+    /// Number of initial invocations of this span which where not included
+    /// in the data, see `SpanTimingLayer::with_warmup`
+    pub warmup: usize,
+    /// Longest duration of a single invocation of this span
+    pub max: Duration,
+    /// Total time between the creation and closing of spans with this name,
+    /// regardless of whether they were entered or not. This is always larger
+    /// than `elapsed`, which only counts the time spent inside the spans.
+    pub lifetime: Duration,
+    /// Total time during which spans with this name existed without being
+    /// entered, i.e. the time between creation and closing not counted in
+    /// `elapsed`. For spans attached to futures, this is the time spent
+    /// waiting to be polled, while `elapsed` is the busy time.
+    pub idle: Duration,
+    /// Number of times spans with this name have been entered. For spans
+    /// attached to futures, this is the number of times the futures were
+    /// polled.
+    pub polls: usize,
+    /// Longest time spent in a span with this name between entering and
+    /// exiting it, i.e. the longest single poll for spans attached to futures
+    pub max_poll: Duration,
+    /// Maximal nesting depth at which a span with this name has been called,
+    /// counting this span. Root spans have a depth of 1.
+    pub max_depth: usize,
+    /// Duration of individual invocations of this span, if enabled with
+    /// `SpanTimingLayer::with_invocation_durations`. Only the first
+    /// invocations are recorded, up to the configured capacity.
+    pub durations: Vec<Duration>,
+    /// Duration of the most recent invocations of this span, oldest first,
+    /// if enabled with `SpanTimingLayer::with_recent_durations`.
+    pub recent: VecDeque<Duration>,
+    /// Sum of the numeric fields recorded on spans with this name, if enabled
+    /// with `SpanTimingLayer::with_counters`
+    pub counters: BTreeMap<String, f64>,
+    /// Sum of the custom metrics added to this span with
+    /// `SpanGraph::add_metric`
+    pub metrics: BTreeMap<String, f64>,
+    /// Elapsed time of this span in each tokio task, indexed by task id, if
+    /// enabled with `SpanTimingLayer::with_task_breakdown`
+    pub tasks: BTreeMap<u64, Duration>,
+    /// Elapsed time of this span in each thread, indexed by thread name, if
+    /// enabled with `SpanTimingLayer::with_thread_breakdown`
+    pub threads: BTreeMap<String, Duration>,
+    /// Hardware performance counters accumulated over all invocations of
+    /// this span, if enabled with `SpanTimingLayer::with_hardware_counters`
+    pub hardware: HardwareCounters,
+    /// Estimated energy consumed in this span over all invocations, in
+    /// joules, if enabled with `SpanTimingLayer::with_energy_measurement`
+    pub energy: f64,
+    /// Largest resident set size of the process observed while a span with
+    /// this name was running, in bytes, if enabled with
+    /// `SpanTimingLayer::with_rss_sampling`. This is zero otherwise.
+    pub max_rss: u64,
+    /// Number of `ERROR` events emitted while a span with this name was the
+    /// innermost recorded span
+    pub errors: usize,
+    /// Number of calls to spans with this name recording a successful
+    /// outcome, with either `ok = true` or `error = false`
+    pub successes: usize,
+    /// Number of calls to spans with this name recording a failed outcome,
+    /// with either `ok = false` or an `error` field
+    pub failures: usize,
+    /// Number of calls to spans with this name which were exited or closed
+    /// while unwinding from a panic
+    pub panics: usize,
+    /// Calls over a rolling time window, see `SpanGraph::rolling_window`
+    pub(crate) window: Option<RollingWindow>,
+    /// Moving average of invocation durations, see
+    /// `SpanTiming::moving_average`
+    pub(crate) moving_average: Option<MovingAverage>,
+    /// Running mean of invocation durations, in seconds
+    pub(crate) mean: f64,
+    /// Running sum of squared differences to the mean of invocation
+    /// durations, in seconds squared (see Welford's algorithm)
+    pub(crate) m2: f64,
 }
 
 impl std::fmt::Display for SpanTiming {
@@ -42,10 +142,266 @@ impl SpanTiming {
         SpanTiming {
             id: id,
             name: name,
+            level: None,
+            target: None,
+            module_path: None,
+            span_name: None,
             elapsed: Duration::new(0, 0),
             called: 0,
+            warmup: 0,
+            max: Duration::new(0, 0),
+            lifetime: Duration::new(0, 0),
+            idle: Duration::new(0, 0),
+            polls: 0,
+            max_poll: Duration::new(0, 0),
+            series: None,
+            max_depth: 0,
+            durations: Vec::new(),
+            recent: VecDeque::new(),
+            counters: BTreeMap::new(),
+            metrics: BTreeMap::new(),
+            tasks: BTreeMap::new(),
+            threads: BTreeMap::new(),
+            hardware: HardwareCounters::default(),
+            energy: 0.0,
+            max_rss: 0,
+            errors: 0,
+            successes: 0,
+            failures: 0,
+            panics: 0,
+            window: None,
+            moving_average: None,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    /// Add a single invocation taking `elapsed` time to the running mean and
+    /// variance. This must be called after incrementing `called`.
+    fn update_variance(&mut self, elapsed: Duration) {
+        let elapsed = elapsed.as_secs_f64();
+        let delta = elapsed - self.mean;
+        self.mean += delta / self.called as f64;
+        self.m2 += delta * (elapsed - self.mean);
+    }
+
+    /// Get an identifier for this span which is stable across runs, derived
+    /// from the full span name. Contrary to `SpanTiming::id`, which depends on
+    /// the order in which spans are closed, this can be used to match spans
+    /// between runs.
+    pub fn stable_id(&self) -> u64 {
+        // 64-bit FNV-1a, which is guaranteed to give the same result across
+        // platforms and compiler versions
+        let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+        for byte in self.name.bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
         }
+        return hash;
+    }
+
+    /// Get the mean duration of invocations of this span
+    pub fn mean(&self) -> Duration {
+        if self.called == 0 {
+            return Duration::new(0, 0);
+        }
+        divide(self.elapsed, self.called)
+    }
+
+    /// Get the fraction of calls to this span recording a failed outcome
+    /// among the calls recording any outcome, or `None` if no call recorded
+    /// an outcome
+    pub fn error_rate(&self) -> Option<f64> {
+        let outcomes = self.successes + self.failures;
+        if outcomes == 0 {
+            return None;
+        }
+        return Some(self.failures as f64 / outcomes as f64);
+    }
+
+    /// Get the exponentially weighted moving average of invocation durations
+    /// of this span, or `None` if it is not enabled (see
+    /// `SpanTimingLayer::with_moving_average`). Contrary to `SpanTiming::mean`,
+    /// this follows the current behavior of the span in long running
+    /// processes.
+    pub fn moving_average(&self) -> Option<Duration> {
+        self.moving_average.as_ref().map(MovingAverage::value)
     }
+
+    /// Get the mean time between the creation and closing of this span
+    pub fn mean_lifetime(&self) -> Duration {
+        if self.called == 0 {
+            return Duration::new(0, 0);
+        }
+        divide(self.lifetime, self.called)
+    }
+
+    /// Get the mean time spent in this span between entering and exiting it,
+    /// i.e. the mean poll duration for spans attached to futures
+    pub fn mean_poll(&self) -> Duration {
+        if self.polls == 0 {
+            return Duration::new(0, 0);
+        }
+        divide(self.elapsed, self.polls)
+    }
+
+    /// Get the variance of the duration of invocations of this span, in
+    /// seconds squared
+    pub fn variance(&self) -> f64 {
+        if self.called < 2 {
+            return 0.0;
+        }
+        self.m2 / (self.called - 1) as f64
+    }
+
+    /// Get the standard deviation of the duration of invocations of this span
+    pub fn stddev(&self) -> Duration {
+        let variance = self.variance();
+        if !variance.is_finite() || variance <= 0.0 {
+            // this can only happen with inconsistent imported data
+            return Duration::new(0, 0);
+        }
+        Duration::try_from_secs_f64(variance.sqrt()).unwrap_or(Duration::MAX)
+    }
+
+    /// Add all the data from `other` to this span.
+    ///
+    /// The recent durations of `other` are considered older than the ones of
+    /// this span, and only the `recent_capacity` most recent ones are kept.
+    /// If `recent_capacity` is 0, the size of the largest of the two buffers
+    /// is used instead.
+    pub(crate) fn merge(&mut self, other: &SpanTiming, recent_capacity: usize) {
+        if other.called != 0 {
+            // combine the running variances, see Chan et al. parallel algorithm
+            let count = (self.called + other.called) as f64;
+            let delta = other.mean - self.mean;
+            self.mean += delta * other.called as f64 / count;
+            self.m2 += other.m2 + delta * delta * (self.called * other.called) as f64 / count;
+        }
+
+        // keep the least verbose level, so that filtering by level keeps the
+        // merged span if any of the original spans would have been kept
+        self.level = match (self.level, other.level) {
+            (Some(level), Some(other)) => Some(std::cmp::min(level, other)),
+            (level, other) => level.or(other),
+        };
+
+        if self.target.is_none() {
+            self.target = other.target.clone();
+            self.module_path = other.module_path.clone();
+            self.span_name = other.span_name.clone();
+        }
+
+        self.elapsed += other.elapsed;
+        self.called += other.called;
+        self.warmup += other.warmup;
+        self.max = std::cmp::max(self.max, other.max);
+        self.lifetime += other.lifetime;
+        self.idle += other.idle;
+        self.polls += other.polls;
+        self.max_poll = std::cmp::max(self.max_poll, other.max_poll);
+        self.max_depth = std::cmp::max(self.max_depth, other.max_depth);
+        self.durations.extend_from_slice(&other.durations);
+
+        let capacity = match recent_capacity {
+            0 => std::cmp::max(self.recent.len(), other.recent.len()),
+            capacity => capacity,
+        };
+        let mut recent = other.recent.clone();
+        recent.extend(&self.recent);
+        while recent.len() > capacity {
+            recent.pop_front();
+        }
+        self.recent = recent;
+        for (name, value) in &other.counters {
+            *self.counters.entry(name.clone()).or_insert(0.0) += value;
+        }
+        for (name, value) in &other.metrics {
+            *self.metrics.entry(name.clone()).or_insert(0.0) += value;
+        }
+        for (&task, &elapsed) in &other.tasks {
+            *self.tasks.entry(task).or_default() += elapsed;
+        }
+        for (thread, &elapsed) in &other.threads {
+            *self.threads.entry(thread.clone()).or_default() += elapsed;
+        }
+        self.hardware += other.hardware;
+        self.energy += other.energy;
+        self.max_rss = std::cmp::max(self.max_rss, other.max_rss);
+        self.errors += other.errors;
+        self.successes += other.successes;
+        self.failures += other.failures;
+        self.panics += other.panics;
+
+        match (&mut self.series, &other.series) {
+            (Some(series), Some(other)) if series.width() == other.width() => series.merge(other),
+            (series @ None, Some(other)) => *series = Some(other.clone()),
+            _ => {}
+        }
+
+        if self.moving_average.is_none() {
+            self.moving_average = other.moving_average;
+        }
+    }
+
+    /// Check if the longest invocation of this span took more than `factor`
+    /// times the mean duration of invocations.
+    pub fn is_outlier(&self, factor: f64) -> bool {
+        self.called > 1 && self.max.as_secs_f64() > factor * self.mean().as_secs_f64()
+    }
+
+    /// Get the module containing this span. This is the module path of the
+    /// span if known, and the full span name without the last component
+    /// otherwise.
+    pub fn module(&self) -> &str {
+        if let Some(ref module_path) = self.module_path {
+            return module_path;
+        }
+
+        if self.name.ends_with('}') {
+            if let Some(start) = self.name.find("::{") {
+                return &self.name[..start];
+            }
+        }
+
+        match self.name.rfind("::") {
+            Some(end) => &self.name[..end],
+            None => &self.name,
+        }
+    }
+}
+
+/// Data about a single invocation of a span, as collected by the layer
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Invocation {
+    /// total time spent inside the span
+    pub elapsed: Duration,
+    /// nesting depth of the span, or 0 if unknown
+    pub depth: usize,
+    /// numeric fields recorded on the span
+    pub counters: Vec<(&'static str, f64)>,
+    /// id of the tokio task running the span, if known
+    pub task: Option<u64>,
+    /// name of the thread running the span, if known
+    pub thread: Option<String>,
+    /// hardware counters accumulated while the span was entered
+    pub hardware: HardwareCounters,
+    /// energy consumed while the span was entered, in joules
+    pub energy: f64,
+    /// largest resident set size sampled during the span, in bytes
+    pub max_rss: u64,
+    /// number of `ERROR` events emitted inside the span
+    pub errors: usize,
+    /// outcome of the span if it recorded one, `true` for success
+    pub outcome: Option<bool>,
+    /// was the span exited or closed while unwinding from a panic?
+    pub panicked: bool,
+    /// time between the creation and closing of the span
+    pub lifetime: Duration,
+    /// number of times the span was entered
+    pub polls: usize,
+    /// longest time spent in the span in a single enter/exit pair
+    pub max_poll: Duration,
 }
 
 /// Full span graph including execution time and number of calls
@@ -86,11 +442,57 @@ impl SpanTiming {
 ///                     \      |
 ///                  | inner, called 3 |
 /// ```
+#[derive(Clone)]
 pub struct SpanGraph {
-    graph: Graph<SpanTiming, usize>,
-    last_id: usize,
+    pub(crate) graph: Graph<SpanTiming, usize>,
+    pub(crate) last_id: usize,
+    /// reference point for all time-dependent data
+    epoch: Instant,
+    pub(crate) settings: Settings,
+    pub(crate) timeline: Option<Timeline>,
+    /// number of `follows_from` relationships between spans, indexed by the
+    /// node index of the cause and the effect
+    pub(crate) follows: BTreeMap<(usize, usize), usize>,
+    /// user-provided metadata describing the run
+    pub(crate) metadata: BTreeMap<String, String>,
+    /// span group corresponding to each callsite, used by the layer to skip
+    /// span name lookups. This must be cleared when nodes are removed.
+    pub(crate) callsites: HashMap<Identifier, SpanIndex>,
+    /// data removed to keep the graph under the size limit
+    pub(crate) evicted: EvictionStats,
+}
+
+/// Settings controlling which data is accumulated in a `SpanGraph`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Settings {
+    /// width of the time windows used for per-span time series
+    pub time_series: Option<Duration>,
+    /// maximal number of individual invocation durations to record per span
+    pub durations_capacity: usize,
+    /// number of recent invocation durations to keep per span
+    pub recent_capacity: usize,
+    /// number of initial invocations to ignore for each span
+    pub warmup: usize,
+    /// width of the rolling time window
+    pub rolling_window: Option<Duration>,
+    /// half-life of the moving average of invocation durations
+    pub moving_average: Option<Duration>,
+    /// file and format used by `SpanGraph::flush`
+    pub output: Option<(PathBuf, Format)>,
+    /// maximal total elapsed time allowed for spans, indexed by span name
+    pub budgets: BTreeMap<String, Duration>,
+    /// latency objectives of spans, indexed by span name
+    pub objectives: BTreeMap<String, Vec<LatencyObjective>>,
+    /// maximal number of span groups, see `SpanTimingLayer::with_max_spans`
+    pub max_spans: Option<usize>,
+    /// hard limit on the number of span groups, and how to get under it
+    pub size_limit: Option<(usize, EvictionPolicy)>,
 }
 
+/// Name of the synthetic span collecting the spans created after reaching
+/// the maximal number of span groups
+pub(crate) const OVERFLOW_SPAN: &str = "<other>";
+
 /// A set of calls from one span to another
 pub struct Calls {
     /// the outer/calling span/function
@@ -101,9 +503,33 @@ pub struct Calls {
     pub count: usize,
 }
 
+/// A set of `follows_from` relationships between two spans, representing
+/// causality which is not a strict parent/child relationship (for example a
+/// task spawned from another one).
+pub struct FollowsFrom {
+    /// the span causing the other one
+    pub cause: SpanIndex,
+    /// the span following from `cause`
+    pub effect: SpanIndex,
+    /// number of time `effect` has been marked as following from `cause`
+    pub count: usize,
+}
+
+/// A path in the call graph, going from one span to another through a set of
+/// intermediary spans
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallPath {
+    /// All spans on this path, starting with the outer span and finishing
+    /// with the inner one
+    pub spans: Vec<SpanIndex>,
+    /// Number of calls between successive spans on this path: `calls[i]` is
+    /// the number of time `spans[i]` called `spans[i + 1]`
+    pub calls: Vec<usize>,
+}
+
 /// Opaque span identifier inside a `SpanGraph`
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct SpanIndex(usize);
+pub struct SpanIndex(pub(crate) usize);
 
 impl From<NodeIndex> for SpanIndex {
     fn from(index: NodeIndex) -> SpanIndex {
@@ -123,9 +549,29 @@ impl SpanGraph {
         SpanGraph {
             graph: Graph::new(),
             last_id: 0,
+            epoch: Instant::now(),
+            settings: Settings::default(),
+            timeline: None,
+            follows: BTreeMap::new(),
+            metadata: BTreeMap::new(),
+            callsites: HashMap::new(),
+            evicted: EvictionStats::default(),
         }
     }
 
+    /// Attach metadata describing this run (for example the run name, git
+    /// commit or hostname) to this graph. The metadata is included in the
+    /// JSON, flamegraph, GraphML and callgrind outputs, to keep archived
+    /// profiles identifiable. Setting an existing `key` overwrites its value.
+    pub fn set_metadata(&mut self, key: &str, value: &str) {
+        self.metadata.insert(key.into(), value.into());
+    }
+
+    /// Get the metadata attached to this graph with `SpanGraph::set_metadata`
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
     /// Find a span in the graph, given its name
     pub fn find(&self, name: &str) -> Option<SpanIndex> {
         for id in self.graph.node_indices() {
@@ -152,29 +598,313 @@ impl SpanGraph {
         }
     }
 
+    /// Find a span in the graph given its name, or create a new empty span
+    /// with the given name if the maximal number of span groups is not
+    /// reached yet. Otherwise, this returns the overflow span for `parent`,
+    /// creating it if needed.
+    pub(crate) fn find_or_create_limited(
+        &mut self,
+        name: &str,
+        parent: Option<SpanIndex>,
+    ) -> SpanIndex {
+        if let Some(span) = self.find(name) {
+            return span;
+        }
+
+        match self.settings.max_spans {
+            Some(max) if self.graph.node_count() >= max => {
+                let overflow = match parent {
+                    Some(parent) => {
+                        let parent = &self.span(parent).name;
+                        if parent.ends_with(OVERFLOW_SPAN) {
+                            parent.clone()
+                        } else {
+                            format!("{}::{}", parent, OVERFLOW_SPAN)
+                        }
+                    }
+                    None => OVERFLOW_SPAN.into(),
+                };
+                return self.find_or_create(&overflow);
+            }
+            _ => return self.find_or_create(name),
+        }
+    }
+
     /// Increase the timing associated with a span by `time`, and the number of
-    /// time this span has been called by one.
+    /// time this span has been called by one. If warm-up invocations are
+    /// configured (see `SpanTimingLayer::with_warmup`), this will instead be
+    /// ignored for the first invocations.
     pub fn increase_timing(&mut self, span: SpanIndex, time: Duration) {
+        let _ = self.record_invocation(
+            span,
+            &Invocation {
+                elapsed: time,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Add all the data from a single `invocation` of `span` to this graph.
+    ///
+    /// This returns `false` if the invocation was ignored as part of the
+    /// warm-up invocations of this span, and `true` otherwise.
+    pub(crate) fn record_invocation(&mut self, span: SpanIndex, invocation: &Invocation) -> bool {
         let id = NodeIndex::from(span);
-        self.graph[id].elapsed += time;
-        self.graph[id].called += 1;
+        let timing = &mut self.graph[id];
+        if timing.warmup < self.settings.warmup {
+            timing.warmup += 1;
+            return false;
+        }
+
+        timing.elapsed += invocation.elapsed;
+        timing.called += 1;
+        timing.update_variance(invocation.elapsed);
+        timing.max = std::cmp::max(timing.max, invocation.elapsed);
+        timing.lifetime += invocation.lifetime;
+        timing.idle += invocation.lifetime.saturating_sub(invocation.elapsed);
+        timing.polls += invocation.polls;
+        timing.max_poll = std::cmp::max(timing.max_poll, invocation.max_poll);
+        timing.max_depth = std::cmp::max(timing.max_depth, invocation.depth);
+
+        if timing.durations.len() < self.settings.durations_capacity {
+            timing.durations.push(invocation.elapsed);
+        }
+
+        if self.settings.recent_capacity > 0 {
+            // the buffer can be larger than the capacity after merging or
+            // importing data recorded with a larger capacity
+            while timing.recent.len() >= self.settings.recent_capacity {
+                timing.recent.pop_front();
+            }
+            timing.recent.push_back(invocation.elapsed);
+        }
+
+        for &(name, value) in &invocation.counters {
+            *timing.counters.entry(name.into()).or_insert(0.0) += value;
+        }
+
+        if let Some(task) = invocation.task {
+            *timing.tasks.entry(task).or_default() += invocation.elapsed;
+        }
+        if let Some(ref thread) = invocation.thread {
+            *timing.threads.entry(thread.clone()).or_default() += invocation.elapsed;
+        }
+        timing.hardware += invocation.hardware;
+        timing.energy += invocation.energy;
+        timing.max_rss = std::cmp::max(timing.max_rss, invocation.max_rss);
+        timing.errors += invocation.errors;
+        match invocation.outcome {
+            Some(true) => timing.successes += 1,
+            Some(false) => timing.failures += 1,
+            None => {}
+        }
+        if invocation.panicked {
+            timing.panics += 1;
+        }
+
+        if let Some(width) = self.settings.time_series {
+            timing
+                .series
+                .get_or_insert_with(|| TimeSeries::new(width))
+                .record(self.epoch.elapsed(), invocation.elapsed);
+        }
+
+        if let Some(width) = self.settings.rolling_window {
+            timing
+                .window
+                .get_or_insert_with(|| RollingWindow::new(width))
+                .record(self.epoch.elapsed(), invocation.elapsed);
+        }
+
+        if let Some(half_life) = self.settings.moving_average {
+            timing
+                .moving_average
+                .get_or_insert_with(|| MovingAverage::new(half_life))
+                .record(self.epoch.elapsed(), invocation.elapsed);
+        }
+
+        return true;
+    }
+
+    /// Add `elapsed` time spent in `calls` layer callbacks to the synthetic
+    /// `span` used to report the layer overhead
+    pub(crate) fn add_overhead(&mut self, span: SpanIndex, elapsed: Duration, calls: usize) {
+        let timing = &mut self.graph[NodeIndex::from(span)];
+        timing.elapsed += elapsed;
+        timing.called += calls;
+        timing.max_depth = 1;
+    }
+
+    /// Get the number of calls and elapsed time of `span` over the rolling
+    /// time window ending now, or `None` if rolling windows are not enabled
+    /// (see `SpanTimingLayer::with_rolling_window`).
+    ///
+    /// The window is discretized in 60 slots, the oldest slot being partially
+    /// outside the window.
+    pub fn rolling_window(&self, span: SpanIndex) -> Option<Bucket> {
+        self.settings.rolling_window?;
+        let now = self.epoch.elapsed();
+        let timing = &self.graph[NodeIndex::from(span)];
+        return Some(
+            timing
+                .window
+                .as_ref()
+                .map_or_else(Bucket::default, |window| window.total(now)),
+        );
+    }
+
+    /// Add `value` to the custom metric called `name` for the given `span`.
+    /// Metrics can be used to attach arbitrary numeric data (such as cache
+    /// misses, bytes processed, ...) to span groups. They are summed over
+    /// all calls to this function, and included in reports.
+    pub fn add_metric(&mut self, span: SpanIndex, name: &str, value: f64) {
+        let timing = &mut self.graph[NodeIndex::from(span)];
+        *timing.metrics.entry(name.into()).or_insert(0.0) += value;
+    }
+
+    /// Get the names of all custom metrics in this graph, see
+    /// `SpanGraph::add_metric`
+    pub fn metric_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for span in self.spans() {
+            for name in span.metrics.keys() {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names.sort();
+        return names;
+    }
+
+    /// Set a time budget for the span with the given full `name`: the total
+    /// elapsed time in this span should not exceed `budget`. Spans exceeding
+    /// their budget are marked in reports.
+    pub fn set_budget(&mut self, name: &str, budget: Duration) {
+        self.settings.budgets.insert(name.into(), budget);
+    }
+
+    /// Get the time budget of `span`, if any was set with
+    /// `SpanGraph::set_budget`
+    pub fn budget(&self, span: SpanIndex) -> Option<Duration> {
+        self.settings.budgets.get(&self.span(span).name).copied()
+    }
+
+    /// Check if the total elapsed time of `span` exceeds its budget (see
+    /// `SpanGraph::set_budget`)
+    pub fn is_over_budget(&self, span: SpanIndex) -> bool {
+        self.budget(span)
+            .is_some_and(|budget| self.span(span).elapsed > budget)
+    }
+
+    /// Get the elapsed time of `span` in each tokio task, as pairs of task id
+    /// and elapsed time sorted by decreasing elapsed time. This is empty
+    /// unless enabled with `SpanTimingLayer::with_task_breakdown`.
+    pub fn task_breakdown(&self, span: SpanIndex) -> Vec<(u64, Duration)> {
+        let mut tasks = self
+            .span(span)
+            .tasks
+            .iter()
+            .map(|(&task, &elapsed)| (task, elapsed))
+            .collect::<Vec<_>>();
+        tasks.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        return tasks;
+    }
+
+    /// Get the elapsed time of `span` in each thread, as pairs of thread name
+    /// and elapsed time sorted by decreasing elapsed time. This is empty
+    /// unless enabled with `SpanTimingLayer::with_thread_breakdown`.
+    pub fn thread_breakdown(&self, span: SpanIndex) -> Vec<(String, Duration)> {
+        let mut threads = self
+            .span(span)
+            .threads
+            .iter()
+            .map(|(thread, &elapsed)| (thread.clone(), elapsed))
+            .collect::<Vec<_>>();
+        threads.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        return threads;
+    }
+
+    /// Get the maximal nesting depth of all spans in this graph
+    pub fn max_depth(&self) -> usize {
+        self.spans().map(|span| span.max_depth).max().unwrap_or(0)
+    }
+
+    /// Get the number of span groups in this graph
+    pub fn span_count(&self) -> usize {
+        self.graph.node_count()
+    }
+
+    /// Get the total number of calls to all spans in this graph
+    pub fn total_calls(&self) -> usize {
+        self.spans().map(|span| span.called).sum()
+    }
+
+    /// Get the total elapsed time in this graph, counting only the time spent
+    /// in spans called outside of any other span. Nested spans are not counted
+    /// twice, making this the reference to compute the fraction of time spent
+    /// in a given span.
+    pub fn total_elapsed(&self) -> Duration {
+        let mut total = Duration::new(0, 0);
+        for node in self.graph.node_indices() {
+            let span = &self.graph[node];
+            let from_callers = self
+                .graph
+                .edges_directed(node, Direction::Incoming)
+                .map(|edge| *edge.weight())
+                .sum::<usize>();
+
+            if span.called > from_callers {
+                let fraction = (span.called - from_callers) as f64 / span.called as f64;
+                total += span.elapsed.mul_f64(fraction);
+            }
+        }
+        return total;
+    }
+
+    /// Get the self time of `span`, i.e. the time spent in this span but
+    /// outside of any of the spans it called. The elapsed time of spans
+    /// called from multiple places is shared between callers proportionally
+    /// to the number of calls coming from each caller, and recursive calls
+    /// are ignored.
+    pub fn self_time(&self, span: SpanIndex) -> Duration {
+        let node = NodeIndex::from(span);
+        let mut children = Duration::new(0, 0);
+        for edge in self.graph.edges_directed(node, Direction::Outgoing) {
+            let callee = &self.graph[edge.target()];
+            if edge.target() == node || callee.called == 0 {
+                continue;
+            }
+
+            let fraction = *edge.weight() as f64 / callee.called as f64;
+            children += callee.elapsed.mul_f64(fraction);
+        }
+        return self.graph[node].elapsed.saturating_sub(children);
+    }
+
+    /// Record an invocation of `span` which started at `start` and just ended
+    /// in the timeline, if the timeline is enabled.
+    pub(crate) fn add_to_timeline(&mut self, span: SpanIndex, start: Instant) {
+        let epoch = self.epoch;
+        if let Some(ref mut timeline) = self.timeline {
+            timeline.push(TimelineEntry {
+                span: span,
+                start: start.saturating_duration_since(epoch),
+                end: epoch.elapsed(),
+            });
+        }
     }
 
     /// Increase the number of time the `parent` span called the `child` span
     /// by one.
     pub fn increase_call_count(&mut self, parent: SpanIndex, child: SpanIndex) {
-        let parent = NodeIndex::from(parent);
-        let child = NodeIndex::from(child);
-        if let Some(edge) = self.graph.find_edge(parent, child) {
-            let count = self
-                .graph
-                .edge_weight_mut(edge)
-                .expect("failed to get edge weights");
-            *count += 1;
-        } else {
-            // initialize edge count to 1
-            self.graph.add_edge(parent, child, 1);
-        }
+        self.add_calls(parent.into(), child.into(), 1);
+    }
+
+    /// Increase the number of time the `effect` span has been marked as
+    /// following from the `cause` span by one.
+    pub fn increase_follows_from_count(&mut self, cause: SpanIndex, effect: SpanIndex) {
+        *self.follows.entry((cause.0, effect.0)).or_insert(0) += 1;
     }
 
     /// Get a single span knowing its `SpanIndex`
@@ -190,46 +920,563 @@ impl SpanGraph {
     /// Get the list of calls between spans in this graph
     pub fn calls(&self) -> impl Iterator<Item = Calls> + '_ {
         self.graph.raw_edges().iter().map(|edge| Calls {
-            caller: edge.target().into(),
-            callee: edge.source().into(),
+            caller: edge.source().into(),
+            callee: edge.target().into(),
             count: edge.weight,
         })
     }
 
+    /// Get the list of `follows_from` relationships between spans in this
+    /// graph. These are not included in the calls between spans.
+    pub fn follows_from(&self) -> impl Iterator<Item = FollowsFrom> + '_ {
+        self.follows
+            .iter()
+            .map(|(&(cause, effect), &count)| FollowsFrom {
+                cause: SpanIndex(cause),
+                effect: SpanIndex(effect),
+                count: count,
+            })
+    }
+
+    /// Get all the spans which called `span`, together with the number of
+    /// time they called it.
+    pub fn callers(&self, span: SpanIndex) -> impl Iterator<Item = (SpanIndex, usize)> + '_ {
+        self.graph
+            .edges_directed(span.into(), Direction::Incoming)
+            .map(|edge| (edge.source().into(), *edge.weight()))
+    }
+
+    /// Get all the spans called by `span`, together with the number of time
+    /// they have been called by it.
+    pub fn callees(&self, span: SpanIndex) -> impl Iterator<Item = (SpanIndex, usize)> + '_ {
+        self.graph
+            .edges_directed(span.into(), Direction::Outgoing)
+            .map(|edge| (edge.target().into(), *edge.weight()))
+    }
+
+    /// Get all the spans which are never called by another span, i.e. the
+    /// entry points of the graph. Recursive calls of a span to itself are
+    /// ignored.
+    pub fn roots(&self) -> impl Iterator<Item = SpanIndex> + '_ {
+        self.graph
+            .node_indices()
+            .filter(move |&node| {
+                self.graph
+                    .neighbors_directed(node, Direction::Incoming)
+                    .all(|other| other == node)
+            })
+            .map(SpanIndex::from)
+    }
+
+    /// Get all the spans which never call another span, i.e. the innermost
+    /// spans of the graph. Recursive calls of a span to itself are ignored.
+    pub fn leaves(&self) -> impl Iterator<Item = SpanIndex> + '_ {
+        self.graph
+            .node_indices()
+            .filter(move |&node| {
+                self.graph
+                    .neighbors_directed(node, Direction::Outgoing)
+                    .all(|other| other == node)
+            })
+            .map(SpanIndex::from)
+    }
+
+    /// Get all the groups of mutually recursive spans in this graph.
+    ///
+    /// Each group is a strongly connected component of the graph, containing
+    /// either multiple spans calling each other, or a single span calling
+    /// itself.
+    pub fn cycles(&self) -> Vec<Vec<SpanIndex>> {
+        petgraph::algo::kosaraju_scc(&self.graph)
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1 || self.graph.contains_edge(component[0], component[0])
+            })
+            .map(|component| component.into_iter().map(SpanIndex::from).collect())
+            .collect()
+    }
+
+    /// Get all the call paths going from the `from` span to the `to` span,
+    /// i.e. all the ways in which `from` can end up calling `to`. Paths going
+    /// more than once through the same span are not included.
+    pub fn paths(&self, from: SpanIndex, to: SpanIndex) -> Vec<CallPath> {
+        petgraph::algo::all_simple_paths::<Vec<_>, _>(&self.graph, from.into(), to.into(), 0, None)
+            .map(|nodes| {
+                let calls = nodes
+                    .windows(2)
+                    .map(|pair| {
+                        let edge = self
+                            .graph
+                            .find_edge(pair[0], pair[1])
+                            .expect("missing edge on a call path");
+                        self.graph[edge]
+                    })
+                    .collect();
+
+                CallPath {
+                    spans: nodes.into_iter().map(SpanIndex::from).collect(),
+                    calls: calls,
+                }
+            })
+            .collect()
+    }
+
+    /// Remove all spans for which `predicate` returns `true` from this graph.
+    ///
+    /// The calls going through a removed span are re-attached to its callers:
+    /// if `a` calls `b` which calls `c`, removing `b` results in `a` calling
+    /// `c`. When a removed span has multiple callers, the calls it made are
+    /// shared between them proportionally to the number of time each of them
+    /// called the removed span. The re-attached call counts are thus
+    /// approximate, and rounded to the nearest integer: calls which amount to
+    /// less than half a call for a given caller are dropped.
+    ///
+    /// Any `SpanIndex` obtained before calling this function is invalidated.
+    pub fn prune<F>(&mut self, mut predicate: F)
+    where
+        F: FnMut(&SpanTiming) -> bool,
+    {
+        let removed = self
+            .graph
+            .node_indices()
+            .filter(|&node| predicate(&self.graph[node]))
+            .collect::<Vec<_>>();
+
+        self.bypass(&removed);
+
+        let mut keep = vec![true; self.graph.node_count()];
+        for node in removed {
+            keep[node.index()] = false;
+        }
+        self.retain_nodes(&keep);
+    }
+
+    /// Remove all spans with a total elapsed time below `threshold` from this
+    /// graph, re-attaching the calls going through them to their callers. See
+    /// `SpanGraph::prune` for more information.
+    pub fn prune_below(&mut self, threshold: Duration) {
+        self.prune(|span| span.elapsed < threshold)
+    }
+
+    /// Get the part of this graph starting at `root`, containing `root` and
+    /// all the spans it called, directly or indirectly.
+    pub fn subgraph(&self, root: SpanIndex) -> SpanGraph {
+        let mut keep = vec![false; self.graph.node_count()];
+        let mut dfs = petgraph::visit::Dfs::new(&self.graph, root.into());
+        while let Some(node) = dfs.next(&self.graph) {
+            keep[node.index()] = true;
+        }
+
+        let mut subgraph = self.clone();
+        subgraph.retain_nodes(&keep);
+        return subgraph;
+    }
+
+    /// Only keep the nodes for which `keep[node.index()]` is `true` in this
+    /// graph, removing all other nodes and the corresponding edges.
+    pub(crate) fn retain_nodes(&mut self, keep: &[bool]) {
+        let mut graph = Graph::with_capacity(self.graph.node_count(), self.graph.edge_count());
+        let mut new_index = vec![None; self.graph.node_count()];
+        for node in self.graph.node_indices() {
+            if keep[node.index()] {
+                new_index[node.index()] = Some(graph.add_node(self.graph[node].clone()));
+            }
+        }
+
+        for edge in self.graph.raw_edges() {
+            let source = new_index[edge.source().index()];
+            let target = new_index[edge.target().index()];
+            if let (Some(source), Some(target)) = (source, target) {
+                graph.add_edge(source, target, edge.weight);
+            }
+        }
+
+        if let Some(ref mut timeline) = self.timeline {
+            timeline.remap(|span| new_index[span.0].map(SpanIndex::from));
+        }
+
+        self.follows = std::mem::take(&mut self.follows)
+            .into_iter()
+            .filter_map(|((cause, effect), count)| {
+                let cause = new_index[cause]?.index();
+                let effect = new_index[effect]?.index();
+                Some(((cause, effect), count))
+            })
+            .collect();
+
+        self.graph = graph;
+        self.callsites.clear();
+    }
+
+    /// Add edges going around all the `removed` nodes, from the remaining
+    /// callers of these nodes to their remaining callees (see
+    /// `SpanGraph::prune`). The removed nodes are not removed from the graph,
+    /// this should be done with `SpanGraph::retain_nodes` afterward.
+    ///
+    /// Removed nodes are eliminated one after the other, keeping fractional
+    /// call counts until the end, so that calls going through a chain of
+    /// removed nodes are correctly re-attached.
+    pub(crate) fn bypass(&mut self, removed: &[NodeIndex]) {
+        let count = self.graph.node_count();
+        let mut incoming = vec![BTreeMap::<usize, f64>::new(); count];
+        let mut outgoing = vec![BTreeMap::<usize, f64>::new(); count];
+        for edge in self.graph.raw_edges() {
+            let (source, target) = (edge.source().index(), edge.target().index());
+            outgoing[source].insert(target, edge.weight as f64);
+            incoming[target].insert(source, edge.weight as f64);
+        }
+
+        for node in removed {
+            let node = node.index();
+            let callers = std::mem::take(&mut incoming[node]);
+            let callees = std::mem::take(&mut outgoing[node]);
+            let total = callers
+                .iter()
+                .filter(|&(&caller, _)| caller != node)
+                .map(|(_, &count)| count)
+                .sum::<f64>();
+
+            for caller in callers.keys() {
+                outgoing[*caller].remove(&node);
+            }
+            for callee in callees.keys() {
+                incoming[*callee].remove(&node);
+            }
+
+            if total == 0.0 {
+                continue;
+            }
+
+            for (&caller, &caller_count) in callers.iter().filter(|&(&c, _)| c != node) {
+                for (&callee, &callee_count) in callees.iter().filter(|&(&c, _)| c != node) {
+                    let count = callee_count * caller_count / total;
+                    *outgoing[caller].entry(callee).or_insert(0.0) += count;
+                    *incoming[callee].entry(caller).or_insert(0.0) += count;
+                }
+            }
+        }
+
+        let mut is_removed = vec![false; count];
+        for node in removed {
+            is_removed[node.index()] = true;
+        }
+
+        for (caller, callees) in outgoing.iter().enumerate() {
+            if is_removed[caller] {
+                continue;
+            }
+            for (&callee, &count) in callees {
+                if is_removed[callee] {
+                    continue;
+                }
+                let (caller, callee) = (NodeIndex::new(caller), NodeIndex::new(callee));
+                let count = count.round() as usize;
+                if let Some(edge) = self.graph.find_edge(caller, callee) {
+                    self.graph[edge] = count;
+                } else if count != 0 {
+                    self.graph.add_edge(caller, callee, count);
+                }
+            }
+        }
+    }
+
+    /// Add `count` calls from `parent` to `child`
+    pub(crate) fn add_calls(&mut self, parent: NodeIndex, child: NodeIndex, count: usize) {
+        if let Some(edge) = self.graph.find_edge(parent, child) {
+            self.graph[edge] += count;
+        } else {
+            self.graph.add_edge(parent, child, count);
+        }
+    }
+
+    /// Get a coarser version of this graph, where all the spans sharing the
+    /// same module (see `SpanTiming::module`) are collapsed into a single
+    /// span named after the module.
+    ///
+    /// The elapsed time and call count of the collapsed spans are summed, and
+    /// calls between spans in different modules become calls between the
+    /// corresponding modules. Calls inside a single module are not included.
+    pub fn by_module(&self) -> SpanGraph {
+        let mut modules = SpanGraph::new();
+        modules.metadata = self.metadata.clone();
+        let mut module_of = Vec::with_capacity(self.graph.node_count());
+        for span in self.spans() {
+            let module = NodeIndex::from(modules.find_or_create(span.module()));
+            modules.graph[module].merge(span, self.settings.recent_capacity);
+            modules.graph[module].span_name = None;
+            module_of.push(module);
+        }
+
+        for edge in self.graph.raw_edges() {
+            let caller = module_of[edge.source().index()];
+            let callee = module_of[edge.target().index()];
+            if caller != callee {
+                modules.add_calls(caller, callee, edge.weight);
+            }
+        }
+
+        for (&(cause, effect), &count) in &self.follows {
+            let cause = module_of[cause].index();
+            let effect = module_of[effect].index();
+            if cause != effect {
+                *modules.follows.entry((cause, effect)).or_insert(0) += count;
+            }
+        }
+
+        return modules;
+    }
+
+    /// Get a copy of this graph only containing the spans for which `keep`
+    /// returns `true`, for example to remove spans from dependencies before
+    /// exporting the graph. Calls going through the removed spans are
+    /// re-attached to their callers, see `SpanGraph::prune`.
+    ///
+    /// ```
+    /// # use tracing_timing_graph::SpanGraph;
+    /// # let graph = SpanGraph::new();
+    /// let own = graph.retain(|span| span.name.starts_with("my_crate::"));
+    /// ```
+    pub fn retain<F>(&self, mut keep: F) -> SpanGraph
+    where
+        F: FnMut(&SpanTiming) -> bool,
+    {
+        let mut retained = self.clone();
+        retained.prune(|span| !keep(span));
+        return retained;
+    }
+
+    /// Get a copy of this graph where each span is renamed to the result of
+    /// calling `map` with its current name, for example to remove generic
+    /// parameters or request identifiers from span names.
+    ///
+    /// Spans mapped to the same name are merged into a single span, with the
+    /// same rules as `SpanGraph::merge`. Calls between spans merged together
+    /// are not included, except for recursive calls of a single span.
+    ///
+    /// ```
+    /// # use tracing_timing_graph::SpanGraph;
+    /// # let graph = SpanGraph::new();
+    /// let renamed = graph.map_names(|name| name.replace("my_crate::", ""));
+    /// ```
+    pub fn map_names<F>(&self, mut map: F) -> SpanGraph
+    where
+        F: FnMut(&str) -> String,
+    {
+        let mut mapped = self.clone();
+        let mut first = HashMap::<String, NodeIndex>::new();
+        let mut merged_into = Vec::with_capacity(self.graph.node_count());
+        for node in self.graph.node_indices() {
+            let name = map(&self.graph[node].name);
+            match first.get(&name) {
+                Some(&other) => {
+                    mapped.graph[other].merge(&self.graph[node], self.settings.recent_capacity);
+                    merged_into.push(other);
+                }
+                None => {
+                    mapped.graph[node].name = name.clone();
+                    first.insert(name, node);
+                    merged_into.push(node);
+                }
+            }
+        }
+
+        // redirect calls and relationships from the merged spans before
+        // removing them
+        for edge in self.graph.raw_edges() {
+            let caller = merged_into[edge.source().index()];
+            let callee = merged_into[edge.target().index()];
+            let moved = caller != edge.source() || callee != edge.target();
+            if moved && (caller != callee || edge.source() == edge.target()) {
+                mapped.add_calls(caller, callee, edge.weight);
+            }
+        }
+
+        let mut follows = BTreeMap::new();
+        for (&(cause, effect), &count) in &self.follows {
+            let cause = merged_into[cause].index();
+            let effect = merged_into[effect].index();
+            *follows.entry((cause, effect)).or_insert(0) += count;
+        }
+        mapped.follows = follows;
+
+        if let Some(ref mut timeline) = mapped.timeline {
+            timeline.remap(|span| Some(merged_into[span.0].into()));
+        }
+
+        let keep = self
+            .graph
+            .node_indices()
+            .map(|node| merged_into[node.index()] == node)
+            .collect::<Vec<_>>();
+        mapped.retain_nodes(&keep);
+        return mapped;
+    }
+
+    /// Get all the spans with a maximal invocation duration larger than
+    /// `factor` times their mean invocation duration. Such spans usually
+    /// correspond to intermittent stalls.
+    pub fn outliers(&self, factor: f64) -> Vec<SpanIndex> {
+        self.graph
+            .node_indices()
+            .filter(|&node| self.graph[node].is_outlier(factor))
+            .map(SpanIndex::from)
+            .collect()
+    }
+
+    /// Get the underlying [`petgraph`] graph, to run custom graph algorithms
+    /// on the span graph. Nodes contain the span data, and edges the number
+    /// of calls from the source to the target span. `SpanIndex` can be
+    /// converted to and from `petgraph::graph::NodeIndex`.
+    ///
+    /// The `follows_from` relationships are not part of this graph.
+    pub fn as_petgraph(&self) -> &Graph<SpanTiming, usize> {
+        &self.graph
+    }
+
+    /// Convert this graph into the underlying [`petgraph`] graph, see
+    /// `SpanGraph::as_petgraph`.
+    pub fn into_petgraph(self) -> Graph<SpanTiming, usize> {
+        self.graph
+    }
+
     /// Get the full graph in [graphviz](https://graphviz.org/) dot format.
+    /// Calls between spans are drawn as plain edges, and `follows_from`
+    /// relationships as dashed edges.
     ///
     /// The exact output is unstable and should not be relied on.
     pub fn as_dot(&self) -> String {
-        Dot::new(&self.graph).to_string()
+        let mut output = String::from("digraph {\n");
+        for node in self.graph.node_indices() {
+            output += &format!(
+                "    {} [ label = {:?} ]\n",
+                node.index(),
+                self.graph[node].to_string()
+            );
+        }
+
+        for edge in self.graph.raw_edges() {
+            output += &format!(
+                "    {} -> {} [ label = \"{}\" ]\n",
+                edge.source().index(),
+                edge.target().index(),
+                edge.weight
+            );
+        }
+
+        for (&(cause, effect), count) in &self.follows {
+            output += &format!(
+                "    {} -> {} [ label = \"{}\", style = dashed ]\n",
+                cause, effect, count
+            );
+        }
+
+        output += "}\n";
+        return output;
     }
 
     /// Get a per span summary table of this graph.
     ///
-    /// The exact output is unstable and should not be relied on.
+    /// Spans are sorted so that callers come before callees. Mutually
+    /// recursive spans (see `SpanGraph::cycles`) are kept together, and sorted
+    /// by decreasing elapsed time.
     ///
-    /// # Panic
+    /// Rows are colored by hotness when the standard output is a terminal,
+    /// see `ReportOptions::with_colors` to override this.
     ///
-    /// This function will panic if the graph is cyclical, i.e. if two or more
-    /// span are mutually recursive.
+    /// The exact output is unstable and should not be relied on.
     pub fn as_table(&self) -> String {
+        self.as_table_with(&ReportOptions::default())
+    }
+
+    /// Get a per span summary table of this graph, using the given `options`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_table_with(&self, options: &ReportOptions) -> String {
+        if options.per_root {
+            let options = ReportOptions {
+                per_root: false,
+                ..options.clone()
+            };
+
+            let mut output = String::new();
+            for root in self.roots() {
+                output += &format!("{}\n", self.span(root).name);
+                output += &self.subgraph(root).as_table_with(&options);
+                output += "\n";
+            }
+            return output;
+        }
+
         let mut table = term_table::Table::new();
-        table.style = term_table::TableStyle::extended();
 
-        table.add_row(Row::new(vec![
+        let mut header = vec![
             "id",
             // pad "span name" to make the table look nicer with short names
             "span name                                   ",
             "call count",
             "called by",
+            "max depth",
             "duration",
-        ]));
+            "std dev",
+        ];
+        let moving_average = self.spans().any(|span| span.moving_average.is_some());
+        if moving_average {
+            header.push("moving avg");
+        }
+        if options.outlier_factor.is_some() {
+            header.push("outlier");
+        }
+        let budgets = !self.settings.budgets.is_empty();
+        if budgets {
+            header.push("budget");
+        }
+        let objectives = !self.settings.objectives.is_empty();
+        if objectives {
+            header.push("SLO");
+        }
+        let busy_idle = options.busy_idle || options.sort_by_idle;
+        if busy_idle {
+            header.push("busy");
+            header.push("idle");
+        }
+        let metrics = self.metric_names();
+        for name in &metrics {
+            header.push(name);
+        }
+        let energy = self.spans().any(|span| span.energy != 0.0);
+        if energy {
+            header.push("energy");
+        }
+        let rss = self.spans().any(|span| span.max_rss != 0);
+        if rss {
+            header.push("peak RSS");
+        }
+        let errors = self.spans().any(|span| span.errors != 0);
+        if errors {
+            header.push("errors");
+        }
+        let outcomes = self.spans().any(|span| span.error_rate().is_some());
+        if outcomes {
+            header.push("ok / failed");
+        }
+        let panics = self.spans().any(|span| span.panics != 0);
+        if panics {
+            header.push("panics");
+        }
+        table.add_row(Row::new(header));
 
-        for &node_id in petgraph::algo::kosaraju_scc(&self.graph)
-            .iter()
-            .rev()
-            .flatten()
-        {
+        let total = self.total_elapsed().as_secs_f64();
+        for node_id in self.report_nodes(options) {
             let data = &self.graph[node_id];
+            if !options.includes(data) {
+                continue;
+            }
+
+            let fraction = if total > 0.0 {
+                data.elapsed.as_secs_f64() / total
+            } else {
+                0.0
+            };
 
             let mut called_by = vec![];
             for other in self.graph.neighbors_directed(node_id, Direction::Incoming) {
@@ -241,20 +1488,168 @@ impl SpanGraph {
                 "—".into()
             };
 
+            let mut row = vec![
+                right_aligned(self.graph[node_id].id),
+                TableCell::new(options.colorize(&data.name, fraction)),
+                right_aligned(data.called),
+                right_aligned(called_by),
+                right_aligned(data.max_depth),
+                right_aligned(options.colorize(&format!("{:.2?}", data.elapsed), fraction)),
+                right_aligned(format!("{:.2?}", data.stddev())),
+            ];
+
+            if moving_average {
+                row.push(right_aligned(
+                    data.moving_average()
+                        .map_or_else(String::new, |value| format!("{:.2?}", value)),
+                ));
+            }
+
+            if let Some(factor) = options.outlier_factor {
+                if data.is_outlier(factor) {
+                    let ratio = data.max.as_secs_f64() / data.mean().as_secs_f64();
+                    row.push(right_aligned(format!(
+                        "max {:.2?} ({:.1}× mean)",
+                        data.max, ratio
+                    )));
+                } else {
+                    row.push(TableCell::new(""));
+                }
+            }
+
+            if budgets {
+                let span = SpanIndex::from(node_id);
+                let budget = match self.budget(span) {
+                    Some(budget) if self.is_over_budget(span) => {
+                        format!("{:.2?} (exceeded)", budget)
+                    }
+                    Some(budget) => format!("{:.2?}", budget),
+                    None => String::new(),
+                };
+                row.push(right_aligned(budget));
+            }
+
+            if objectives {
+                let objectives = self
+                    .objectives(node_id.into())
+                    .iter()
+                    .map(|objective| {
+                        if objective.is_violated(data) {
+                            format!("{} (violated)", objective)
+                        } else {
+                            objective.to_string()
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                row.push(right_aligned(objectives.join(", ")));
+            }
+
+            if busy_idle {
+                row.push(right_aligned(format!("{:.2?}", data.elapsed)));
+                row.push(right_aligned(format!("{:.2?}", data.idle)));
+            }
+
+            for name in &metrics {
+                row.push(match data.metrics.get(name) {
+                    Some(value) => right_aligned(value),
+                    None => TableCell::new(""),
+                });
+            }
+
+            if energy {
+                row.push(right_aligned(format!("{:.3} J", data.energy)));
+            }
+
+            if rss {
+                let mib = data.max_rss as f64 / (1024.0 * 1024.0);
+                row.push(right_aligned(format!("{:.1} MiB", mib)));
+            }
+
+            if errors {
+                row.push(right_aligned(data.errors));
+            }
+
+            if outcomes {
+                row.push(right_aligned(match data.error_rate() {
+                    Some(rate) => format!(
+                        "{} / {} ({:.1}%)",
+                        data.successes,
+                        data.failures,
+                        100.0 * rate
+                    ),
+                    None => String::new(),
+                }));
+            }
+
+            if panics {
+                row.push(right_aligned(data.panics));
+            }
+
+            table.add_row(Row::new(row));
+        }
+
+        return options.render_table(table);
+    }
+
+    /// Get a table with the elapsed time of each span broken down by thread
+    /// (see `SpanGraph::thread_breakdown`), with one row for each thread
+    /// under the row of the span. Only spans with per-thread data are
+    /// included.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_thread_table(&self) -> String {
+        self.as_thread_table_with(&ReportOptions::default())
+    }
+
+    /// Get a table with the elapsed time of each span broken down by thread,
+    /// using the given `options`, see `SpanGraph::as_thread_table`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_thread_table_with(&self, options: &ReportOptions) -> String {
+        let mut table = term_table::Table::new();
+
+        table.add_row(Row::new(vec![
+            // pad "span name" to make the table look nicer with short names
+            "span name / thread                          ",
+            "duration",
+            "fraction",
+        ]));
+
+        let total = self.total_elapsed().as_secs_f64();
+        for node_id in self.report_nodes(options) {
+            let data = &self.graph[node_id];
+            if data.threads.is_empty() || !options.includes(data) {
+                continue;
+            }
+
+            let fraction = if total > 0.0 {
+                data.elapsed.as_secs_f64() / total
+            } else {
+                0.0
+            };
+
             table.add_row(Row::new(vec![
-                TableCell::new_with_alignment(self.graph[node_id].id, 1, Alignment::Right),
-                TableCell::new(&data.name),
-                TableCell::new_with_alignment(data.called, 1, Alignment::Right),
-                TableCell::new_with_alignment(called_by, 1, Alignment::Right),
-                TableCell::new_with_alignment(
-                    &format!("{:.2?}", data.elapsed),
-                    1,
-                    Alignment::Right,
-                ),
+                TableCell::new(options.colorize(&data.name, fraction)),
+                right_aligned(format!("{:.2?}", data.elapsed)),
+                TableCell::new(""),
             ]));
+
+            for (thread, elapsed) in self.thread_breakdown(node_id.into()) {
+                let fraction = if data.elapsed.as_nanos() != 0 {
+                    elapsed.as_secs_f64() / data.elapsed.as_secs_f64()
+                } else {
+                    0.0
+                };
+
+                table.add_row(Row::new(vec![
+                    TableCell::new(format!("    {}", thread)),
+                    right_aligned(format!("{:.2?}", elapsed)),
+                    right_aligned(format!("{:.1}%", 100.0 * fraction)),
+                ]));
+            }
         }
 
-        return table.render();
+        return options.render_table(table);
     }
 
     /// Get all the data in this graph in JSON.
@@ -262,33 +1657,436 @@ impl SpanGraph {
     /// The exact output is unstable and should not be relied on.
     pub fn as_json(&self) -> String {
         let mut spans = json::JsonValue::new_object();
-        for span in self.spans() {
-            spans[&span.name] = json::object! {
-                "id" => span.id,
-                "elapsed" => format!("{} µs", span.elapsed.as_micros()),
-                "called" => span.called,
-            };
+        for node in self.graph.node_indices() {
+            spans[&self.graph[node].name] = self.span_json(node);
         }
 
         let mut all_calls = json::JsonValue::new_array();
         for call in self.calls() {
             all_calls
-                .push(json::object! {
-                    "caller" => self.span(call.caller).id,
-                    "callee" => self.span(call.caller).id,
-                    "count" => call.count,
-                })
+                .push(self.call_json(&call))
                 .expect("failed to add edge information to JSON");
         }
 
+        let mut follows = json::JsonValue::new_array();
+        for relationship in self.follows_from() {
+            follows
+                .push(self.follows_json(&relationship))
+                .expect("failed to add follows_from information to JSON");
+        }
+
         return json::stringify(json::object! {
+            "metadata" => self.metadata_json(),
             "timings" => spans,
             "calls" => all_calls,
+            "follows_from" => follows,
         });
     }
 
+    /// Write all the data in this graph in JSON to `writer`, producing the
+    /// same output as `SpanGraph::as_json`. The JSON is written one span at a
+    /// time instead of building the whole document in memory first, which
+    /// is more efficient for graphs with many spans.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"{\"metadata\":")?;
+        self.metadata_json().write(writer)?;
+
+        writer.write_all(b",\"timings\":{")?;
+        for (i, node) in self.graph.node_indices().enumerate() {
+            if i != 0 {
+                writer.write_all(b",")?;
+            }
+            json::JsonValue::from(self.graph[node].name.as_str()).write(writer)?;
+            writer.write_all(b":")?;
+            self.span_json(node).write(writer)?;
+        }
+
+        writer.write_all(b"},\"calls\":[")?;
+        for (i, call) in self.calls().enumerate() {
+            if i != 0 {
+                writer.write_all(b",")?;
+            }
+            self.call_json(&call).write(writer)?;
+        }
+
+        writer.write_all(b"],\"follows_from\":[")?;
+        for (i, relationship) in self.follows_from().enumerate() {
+            if i != 0 {
+                writer.write_all(b",")?;
+            }
+            self.follows_json(&relationship).write(writer)?;
+        }
+
+        return writer.write_all(b"]}");
+    }
+
+    /// Get the data associated with a single span as a JSON object
+    fn span_json(&self, node: NodeIndex) -> json::JsonValue {
+        let span = &self.graph[node];
+        let mut data = json::object! {
+            "id" => span.id,
+            "stable_id" => format!("{:016x}", span.stable_id()),
+            "level" => span.level.map(|level| level.to_string()),
+            "target" => span.target.clone(),
+            "module_path" => span.module_path.clone(),
+            "span_name" => span.span_name.clone(),
+            "elapsed" => format!("{} µs", span.elapsed.as_micros()),
+            "called" => span.called,
+            "max_depth" => span.max_depth,
+            "warmup" => span.warmup,
+            "stddev" => format!("{} µs", span.stddev().as_micros()),
+            "max" => format!("{} µs", span.max.as_micros()),
+            "lifetime" => format!("{} µs", span.lifetime.as_micros()),
+            "busy" => format!("{} µs", span.elapsed.as_micros()),
+            "idle" => format!("{} µs", span.idle.as_micros()),
+            "polls" => span.polls,
+            "max_poll" => format!("{} µs", span.max_poll.as_micros()),
+        };
+
+        if let Some(ref moving_average) = span.moving_average {
+            data["moving_average"] = format!("{} µs", moving_average.value().as_micros()).into();
+            data["moving_average_state"] = json::object! {
+                "half_life" => format!("{} µs", moving_average.half_life().as_micros()),
+                "sum" => moving_average.sum(),
+                "weight" => moving_average.weight(),
+            };
+        }
+
+        if !span.durations.is_empty() {
+            let mut durations = json::JsonValue::new_array();
+            for duration in &span.durations {
+                durations
+                    .push(duration.as_micros() as u64)
+                    .expect("failed to add durations to JSON");
+            }
+            data["durations"] = durations;
+        }
+
+        if !span.recent.is_empty() {
+            let mut recent = json::JsonValue::new_array();
+            for duration in &span.recent {
+                recent
+                    .push(duration.as_micros() as u64)
+                    .expect("failed to add recent durations to JSON");
+            }
+            data["recent"] = recent;
+        }
+
+        if let Some(ref series) = span.series {
+            let mut called = json::JsonValue::new_array();
+            let mut elapsed = json::JsonValue::new_array();
+            for bucket in series.buckets() {
+                called
+                    .push(bucket.called)
+                    .expect("failed to add time series to JSON");
+                elapsed
+                    .push(bucket.elapsed.as_micros() as u64)
+                    .expect("failed to add time series to JSON");
+            }
+
+            data["series"] = json::object! {
+                "width" => format!("{} µs", series.width().as_micros()),
+                "first" => series.first(),
+                "called" => called,
+                "elapsed" => elapsed,
+            };
+        }
+
+        if let Some(budget) = self.budget(node.into()) {
+            data["budget"] = format!("{} µs", budget.as_micros()).into();
+            data["over_budget"] = self.is_over_budget(node.into()).into();
+        }
+
+        let objectives = self.objectives(node.into());
+        if !objectives.is_empty() {
+            let mut array = json::JsonValue::new_array();
+            for objective in objectives {
+                array
+                    .push(json::object! {
+                        "objective" => objective.to_string(),
+                        "violated" => objective.is_violated(span),
+                    })
+                    .expect("failed to add objectives to JSON");
+            }
+            data["objectives"] = array;
+        }
+
+        if !span.tasks.is_empty() {
+            let mut tasks = json::JsonValue::new_object();
+            for (task, elapsed) in &span.tasks {
+                tasks[task.to_string().as_str()] = (elapsed.as_micros() as u64).into();
+            }
+            data["tasks"] = tasks;
+        }
+
+        if !span.threads.is_empty() {
+            let mut threads = json::JsonValue::new_object();
+            for (thread, elapsed) in &span.threads {
+                threads[thread.as_str()] = (elapsed.as_micros() as u64).into();
+            }
+            data["threads"] = threads;
+        }
+
+        if span.energy != 0.0 {
+            data["energy"] = span.energy.into();
+        }
+
+        if span.max_rss != 0 {
+            data["max_rss"] = span.max_rss.into();
+        }
+
+        if span.errors != 0 {
+            data["errors"] = span.errors.into();
+        }
+
+        if span.successes != 0 || span.failures != 0 {
+            data["successes"] = span.successes.into();
+            data["failures"] = span.failures.into();
+        }
+
+        if span.panics != 0 {
+            data["panics"] = span.panics.into();
+        }
+
+        if !span.hardware.is_empty() {
+            data["hardware"] = json::object! {
+                "instructions" => span.hardware.instructions,
+                "cycles" => span.hardware.cycles,
+                "cache_misses" => span.hardware.cache_misses,
+            };
+        }
+
+        if !span.counters.is_empty() {
+            let mut counters = json::JsonValue::new_object();
+            for (name, &value) in &span.counters {
+                counters[name.as_str()] = value.into();
+            }
+            data["counters"] = counters;
+        }
+
+        if !span.metrics.is_empty() {
+            let mut metrics = json::JsonValue::new_object();
+            for (name, &value) in &span.metrics {
+                metrics[name.as_str()] = value.into();
+            }
+            data["metrics"] = metrics;
+        }
+
+        return data;
+    }
+
+    /// Get a single call edge as a JSON object
+    fn call_json(&self, call: &Calls) -> json::JsonValue {
+        json::object! {
+            "caller" => self.span(call.caller).id,
+            "callee" => self.span(call.callee).id,
+            "count" => call.count,
+        }
+    }
+
+    /// Get a single `follows_from` relationship as a JSON object
+    fn follows_json(&self, relationship: &FollowsFrom) -> json::JsonValue {
+        json::object! {
+            "cause" => self.span(relationship.cause).id,
+            "effect" => self.span(relationship.effect).id,
+            "count" => relationship.count,
+        }
+    }
+
+    /// Get the metadata attached to this graph as a JSON object
+    pub(crate) fn metadata_json(&self) -> json::JsonValue {
+        let mut metadata = json::JsonValue::new_object();
+        for (key, value) in &self.metadata {
+            metadata[key.as_str()] = value.as_str().into();
+        }
+        return metadata;
+    }
+
+    /// Get all nodes in the graph, in the order used by reports generated
+    /// with the given `options`
+    pub(crate) fn report_nodes(&self, options: &ReportOptions) -> Vec<NodeIndex> {
+        let mut nodes = self.sorted_nodes();
+        if options.sort_by_idle {
+            // stable sort, spans with the same idle time stay in call order
+            nodes.sort_by(|&a, &b| self.graph[b].idle.cmp(&self.graph[a].idle));
+        }
+        return nodes;
+    }
+
+    /// Get all nodes in the graph, sorted with callers before callees. Nodes
+    /// in the same strongly connected component are sorted by decreasing
+    /// elapsed time.
+    pub(crate) fn sorted_nodes(&self) -> Vec<NodeIndex> {
+        // kosaraju_scc returns the components in reverse topological order
+        let mut components = petgraph::algo::kosaraju_scc(&self.graph);
+        components.reverse();
+
+        let mut nodes = Vec::with_capacity(self.graph.node_count());
+        for mut component in components {
+            component.sort_by(|&a, &b| {
+                let a = &self.graph[a];
+                let b = &self.graph[b];
+                b.elapsed.cmp(&a.elapsed).then(a.id.cmp(&b.id))
+            });
+            nodes.extend(component);
+        }
+
+        return nodes;
+    }
+
+    /// Take all the data out of this graph, leaving an empty graph with the
+    /// same settings and metadata
+    pub(crate) fn take(&mut self) -> SpanGraph {
+        let mut empty = SpanGraph::new();
+        empty.settings = self.settings.clone();
+        empty.metadata = self.metadata.clone();
+        empty.timeline = self
+            .timeline
+            .as_ref()
+            .map(|timeline| Timeline::new(timeline.capacity()));
+        return std::mem::replace(self, empty);
+    }
+
     pub fn clear(&mut self) {
         self.graph.clear();
         self.last_id = 0;
+        self.epoch = Instant::now();
+        self.follows.clear();
+        self.callsites.clear();
+        self.evicted = EvictionStats::default();
+        if let Some(ref mut timeline) = self.timeline {
+            *timeline = Timeline::new(timeline.capacity());
+        }
+    }
+}
+
+/// Divide `duration` by a non-zero `count`, which can be larger than
+/// `u32::MAX` for imported graphs
+fn divide(duration: Duration, count: usize) -> Duration {
+    Duration::from_nanos((duration.as_nanos() / count as u128) as u64)
+}
+
+pub(crate) fn right_aligned<T: ToString>(data: T) -> TableCell {
+    TableCell::builder(data).alignment(Alignment::Right).build()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Create a graph where each span in `calls` calls the next one,
+    /// `calls[i].1` times per call to `calls[i].0`
+    fn chain(calls: &[(&str, usize)]) -> SpanGraph {
+        let mut graph = SpanGraph::new();
+        let spans = calls
+            .iter()
+            .map(|&(name, _)| graph.find_or_create(name))
+            .collect::<Vec<_>>();
+
+        let mut called = 1;
+        for (i, &span) in spans.iter().enumerate() {
+            for _ in 0..called {
+                graph.increase_timing(span, Duration::from_millis(1));
+            }
+            if let Some(&next) = spans.get(i + 1) {
+                called *= calls[i].1;
+                for _ in 0..called {
+                    graph.increase_call_count(span, next);
+                }
+            }
+        }
+        return graph;
+    }
+
+    pub(crate) fn calls(graph: &SpanGraph, caller: &str, callee: &str) -> Option<usize> {
+        let caller = graph.find(caller)?;
+        let callee = graph.find(callee)?;
+        graph
+            .callees(caller)
+            .find(|&(span, _)| span == callee)
+            .map(|(_, count)| count)
+    }
+
+    fn recent(graph: &SpanGraph, name: &str) -> Vec<u64> {
+        let span = graph.span(graph.find(name).unwrap());
+        return span.recent.iter().map(|d| d.as_millis() as u64).collect();
+    }
+
+    #[test]
+    fn recent_capacity() {
+        let mut graph = SpanGraph::new();
+        graph.settings.recent_capacity = 3;
+        let span = graph.find_or_create("span");
+        for i in 1..=5 {
+            graph.increase_timing(span, Duration::from_millis(i));
+        }
+        assert_eq!(recent(&graph, "span"), [3, 4, 5]);
+
+        // durations from the other graph are older than the existing ones
+        let mut other = graph.clone();
+        let span = other.find("span").unwrap();
+        other.increase_timing(span, Duration::from_millis(6));
+        graph.merge(&other);
+        assert_eq!(recent(&graph, "span"), [3, 4, 5]);
+
+        let mut previous = SpanGraph::new();
+        previous.settings.recent_capacity = 2;
+        let span = previous.find_or_create("span");
+        for i in 10..=11 {
+            previous.increase_timing(span, Duration::from_millis(i));
+        }
+        let mut graph = SpanGraph::new();
+        graph.settings.recent_capacity = 3;
+        let span = graph.find_or_create("span");
+        graph.increase_timing(span, Duration::from_millis(1));
+        graph.increase_timing(span, Duration::from_millis(2));
+        graph.merge(&previous);
+        assert_eq!(recent(&graph, "span"), [11, 1, 2]);
+
+        // data recorded with a larger capacity
+        graph.graph[NodeIndex::from(span)].recent = (1..=6).map(Duration::from_millis).collect();
+        graph.increase_timing(span, Duration::from_millis(7));
+        assert_eq!(recent(&graph, "span"), [5, 6, 7]);
+        graph.increase_timing(span, Duration::from_millis(8));
+        assert_eq!(recent(&graph, "span"), [6, 7, 8]);
+    }
+
+    #[test]
+    fn stddev_invalid_variance() {
+        let mut span = SpanTiming::new("span".into(), 0);
+        span.called = 3;
+        span.m2 = -1.0;
+        assert_eq!(span.stddev(), Duration::new(0, 0));
+        span.m2 = f64::NAN;
+        assert_eq!(span.stddev(), Duration::new(0, 0));
+    }
+
+    #[test]
+    fn prune_chain() {
+        let mut graph = chain(&[("A", 2), ("B", 3), ("C", 2), ("D", 1)]);
+        graph.prune(|span| span.name == "B" || span.name == "C");
+
+        assert_eq!(graph.span_count(), 2);
+        assert_eq!(calls(&graph, "A", "D"), Some(12));
+    }
+
+    #[test]
+    fn prune_does_not_create_calls() {
+        let mut graph = SpanGraph::new();
+        let a = graph.find_or_create("A");
+        let b = graph.find_or_create("B");
+        let c = graph.find_or_create("C");
+        let d = graph.find_or_create("D");
+        graph.increase_call_count(a, b);
+        for _ in 0..99 {
+            graph.increase_call_count(d, b);
+        }
+        graph.increase_call_count(b, c);
+
+        graph.prune(|span| span.name == "B");
+        assert_eq!(calls(&graph, "A", "C"), None);
+        assert_eq!(calls(&graph, "D", "C"), Some(1));
     }
 }
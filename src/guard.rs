@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use crate::{Format, GraphView, SpanTimingLayer};
+
+/// Guard writing the span graph to a file when dropped, created by
+/// `SpanTimingLayer::dump_at_exit`.
+///
+/// This is intended to live until the end of `main`.
+#[must_use = "the span graph is written when this guard is dropped"]
+pub struct DumpGuard {
+    view: GraphView,
+    path: PathBuf,
+    format: Format,
+}
+
+impl Drop for DumpGuard {
+    fn drop(&mut self) {
+        if let Err(error) = self.view.read().write_file(&self.path, self.format) {
+            eprintln!(
+                "failed to write span timing report to '{}': {}",
+                self.path.display(),
+                error
+            );
+        }
+    }
+}
+
+impl SpanTimingLayer {
+    /// Get a guard writing the span graph in the given `format` to the file
+    /// at `path` when dropped. This allows to get a report at the end of the
+    /// program, even when the layer itself is installed as the global default
+    /// subscriber and never dropped.
+    ///
+    /// ```no_run
+    /// # use tracing_subscriber::layer::SubscriberExt;
+    /// # use tracing_timing_graph::{Format, SpanTimingLayer};
+    /// let layer = SpanTimingLayer::new();
+    /// let _guard = layer.dump_at_exit("timings.json", Format::Json);
+    ///
+    /// let subscriber = tracing_subscriber::registry().with(layer);
+    /// tracing::subscriber::set_global_default(subscriber).unwrap();
+    ///
+    /// // run the program, the report is written when `_guard` goes out of
+    /// // scope at the end of main
+    /// ```
+    pub fn dump_at_exit<P: Into<PathBuf>>(&self, path: P, format: Format) -> DumpGuard {
+        DumpGuard {
+            view: self.view(),
+            path: path.into(),
+            format: format,
+        }
+    }
+}
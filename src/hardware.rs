@@ -0,0 +1,165 @@
+/// Values of hardware performance counters, accumulated over the time spent
+/// inside a span. These are only recorded on Linux with the `perf` feature,
+/// see `SpanTimingLayer::with_hardware_counters`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HardwareCounters {
+    /// Number of instructions retired
+    pub instructions: u64,
+    /// Number of CPU cycles
+    pub cycles: u64,
+    /// Number of last level cache misses
+    pub cache_misses: u64,
+}
+
+impl HardwareCounters {
+    /// Get the number of instructions per cycle. Low values usually indicate
+    /// memory-bound code, and high values compute-bound code.
+    pub fn instructions_per_cycle(&self) -> f64 {
+        if self.cycles == 0 {
+            return 0.0;
+        }
+        return self.instructions as f64 / self.cycles as f64;
+    }
+
+    /// Check if all the counters are zero
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == HardwareCounters::default()
+    }
+
+    /// Get the difference between this value of the counters and an
+    /// `earlier` value
+    #[cfg(all(target_os = "linux", feature = "perf"))]
+    pub(crate) fn since(&self, earlier: &HardwareCounters) -> HardwareCounters {
+        HardwareCounters {
+            instructions: self.instructions.saturating_sub(earlier.instructions),
+            cycles: self.cycles.saturating_sub(earlier.cycles),
+            cache_misses: self.cache_misses.saturating_sub(earlier.cache_misses),
+        }
+    }
+}
+
+impl std::ops::AddAssign for HardwareCounters {
+    fn add_assign(&mut self, other: HardwareCounters) {
+        self.instructions += other.instructions;
+        self.cycles += other.cycles;
+        self.cache_misses += other.cache_misses;
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "perf"))]
+mod perf {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    use perf_event_open_sys::bindings::{self, perf_event_attr};
+
+    use super::HardwareCounters;
+
+    /// Group of perf_event counters measuring the current thread
+    struct CounterGroup {
+        /// group leader, used to read all counters at once
+        leader: File,
+        /// other counters in the group, kept open until the group is dropped
+        _others: Vec<File>,
+    }
+
+    thread_local! {
+        /// Counters for the current thread, opened on first use. This is
+        /// `None` if the counters could not be opened.
+        static COUNTERS: Option<CounterGroup> = CounterGroup::open().ok();
+    }
+
+    /// Open a single hardware counter for the current thread, in the group
+    /// with the given leader (or as a new group leader)
+    fn open_counter(config: u32, leader: Option<&File>) -> io::Result<File> {
+        let mut attributes = perf_event_attr {
+            type_: bindings::perf_type_id_PERF_TYPE_HARDWARE,
+            size: std::mem::size_of::<perf_event_attr>() as u32,
+            config: config as u64,
+            read_format: bindings::perf_event_read_format_PERF_FORMAT_GROUP as u64,
+            ..Default::default()
+        };
+        attributes.set_disabled(leader.is_none() as u64);
+        // only count user space events, which does not require special
+        // permissions
+        attributes.set_exclude_kernel(1);
+        attributes.set_exclude_hv(1);
+
+        let group = leader.map_or(-1, |leader| leader.as_raw_fd());
+        let fd = unsafe {
+            perf_event_open_sys::perf_event_open(
+                &mut attributes,
+                0,
+                -1,
+                group,
+                bindings::PERF_FLAG_FD_CLOEXEC as _,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        return Ok(unsafe { File::from_raw_fd(fd) });
+    }
+
+    impl CounterGroup {
+        fn open() -> io::Result<CounterGroup> {
+            let leader = open_counter(bindings::perf_hw_id_PERF_COUNT_HW_INSTRUCTIONS, None)?;
+            let others = vec![
+                open_counter(bindings::perf_hw_id_PERF_COUNT_HW_CPU_CYCLES, Some(&leader))?,
+                open_counter(
+                    bindings::perf_hw_id_PERF_COUNT_HW_CACHE_MISSES,
+                    Some(&leader),
+                )?,
+            ];
+
+            let status = unsafe {
+                perf_event_open_sys::ioctls::ENABLE(
+                    leader.as_raw_fd(),
+                    bindings::perf_event_ioc_flags_PERF_IOC_FLAG_GROUP,
+                )
+            };
+            if status < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            return Ok(CounterGroup {
+                leader: leader,
+                _others: others,
+            });
+        }
+
+        fn read(&self) -> io::Result<HardwareCounters> {
+            // with PERF_FORMAT_GROUP, the data contains the number of
+            // counters followed by the value of each counter
+            let mut data = [0u8; 4 * 8];
+            (&self.leader).read_exact(&mut data)?;
+
+            let value = |i: usize| {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&data[8 * i..8 * (i + 1)]);
+                u64::from_ne_bytes(bytes)
+            };
+
+            return Ok(HardwareCounters {
+                instructions: value(1),
+                cycles: value(2),
+                cache_misses: value(3),
+            });
+        }
+    }
+
+    /// Check that hardware counters can be opened on the current thread
+    pub(crate) fn check() -> io::Result<()> {
+        CounterGroup::open().map(|_| ())
+    }
+
+    /// Read the hardware counters for the current thread, if available
+    pub(crate) fn read() -> Option<HardwareCounters> {
+        COUNTERS.with(|counters| counters.as_ref().and_then(|counters| counters.read().ok()))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "perf"))]
+pub(crate) use self::perf::{check, read};
@@ -0,0 +1,429 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use json::JsonValue;
+use petgraph::graph::NodeIndex;
+
+use crate::series::{Bucket, MovingAverage, TimeSeries};
+use crate::{HardwareCounters, ImportError, SpanGraph, SpanTiming};
+
+impl SpanGraph {
+    /// Load a graph previously saved with `SpanGraph::as_json`.
+    ///
+    /// Times are stored with microsecond resolution in the JSON output, and
+    /// the data is rounded accordingly. Settings from the layer (such as the
+    /// configured warm-up invocations or rolling window) are not saved and
+    /// can not be restored.
+    pub fn from_json(data: &str) -> Result<SpanGraph, ImportError> {
+        let root = json::parse(data).map_err(|error| ImportError::Invalid(error.to_string()))?;
+
+        let mut graph = SpanGraph::new();
+        for (key, value) in root["metadata"].entries() {
+            let value = value
+                .as_str()
+                .ok_or_else(|| invalid(&format!("metadata '{}' should be a string", key)))?;
+            graph.set_metadata(key, value);
+        }
+
+        let mut spans = Vec::new();
+        for (name, data) in root["timings"].entries() {
+            spans.push(span_from_json(name, data)?);
+        }
+        spans.sort_by_key(|span| span.id);
+
+        let mut nodes = BTreeMap::new();
+        for span in spans {
+            graph.last_id = std::cmp::max(graph.last_id, span.id.saturating_add(1));
+            let id = span.id;
+            if nodes.insert(id, graph.graph.add_node(span)).is_some() {
+                return Err(invalid(&format!("duplicated span id {}", id)));
+            }
+        }
+
+        let node = |data: &JsonValue, key: &str| -> Result<NodeIndex, ImportError> {
+            let id = usize_from_json(&data[key], key)?;
+            return nodes
+                .get(&id)
+                .copied()
+                .ok_or_else(|| invalid(&format!("unknown span id {} in '{}'", id, key)));
+        };
+
+        for call in root["calls"].members() {
+            let caller = node(call, "caller")?;
+            let callee = node(call, "callee")?;
+            let count = usize_from_json(&call["count"], "count")?;
+            graph.graph.add_edge(caller, callee, count);
+        }
+
+        for follows in root["follows_from"].members() {
+            let cause = node(follows, "cause")?.index();
+            let effect = node(follows, "effect")?.index();
+            let count = usize_from_json(&follows["count"], "count")?;
+            graph.follows.insert((cause, effect), count);
+        }
+
+        return Ok(graph);
+    }
+
+    /// Load a graph previously saved to the file at `path` with
+    /// `SpanGraph::as_json` or `Format::Json`, see `SpanGraph::from_json`.
+    pub fn read_json_file<P: AsRef<Path>>(path: P) -> Result<SpanGraph, ImportError> {
+        let data = std::fs::read_to_string(path).map_err(ImportError::Io)?;
+        return SpanGraph::from_json(&data);
+    }
+}
+
+fn invalid(message: &str) -> ImportError {
+    ImportError::Invalid(message.into())
+}
+
+fn usize_from_json(value: &JsonValue, key: &str) -> Result<usize, ImportError> {
+    value
+        .as_usize()
+        .ok_or_else(|| invalid(&format!("'{}' should be a positive integer", key)))
+}
+
+fn u64_from_json(value: &JsonValue, key: &str) -> Result<u64, ImportError> {
+    value
+        .as_u64()
+        .ok_or_else(|| invalid(&format!("'{}' should be a positive integer", key)))
+}
+
+/// Parse durations saved as "<N> µs" strings
+fn duration_from_json(value: &JsonValue, key: &str) -> Result<Duration, ImportError> {
+    value
+        .as_str()
+        .and_then(|value| value.strip_suffix(" µs"))
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_micros)
+        .ok_or_else(|| invalid(&format!("'{}' should be a duration in µs", key)))
+}
+
+/// Parse a list of durations saved as integer numbers of µs
+fn durations_from_json(value: &JsonValue, key: &str) -> Result<Vec<Duration>, ImportError> {
+    value
+        .members()
+        .map(|value| {
+            value
+                .as_u64()
+                .map(Duration::from_micros)
+                .ok_or_else(|| invalid(&format!("'{}' should contain integers", key)))
+        })
+        .collect()
+}
+
+fn span_from_json(name: &str, data: &JsonValue) -> Result<SpanTiming, ImportError> {
+    let mut span = SpanTiming::new(name.into(), usize_from_json(&data["id"], "id")?);
+    if let Some(level) = data["level"].as_str() {
+        let level = level
+            .parse()
+            .map_err(|_| invalid(&format!("invalid level '{}'", level)))?;
+        span.level = Some(level);
+    }
+    span.target = data["target"].as_str().map(Into::into);
+    span.module_path = data["module_path"].as_str().map(Into::into);
+    span.span_name = data["span_name"].as_str().map(Into::into);
+    span.elapsed = duration_from_json(&data["elapsed"], "elapsed")?;
+    span.called = usize_from_json(&data["called"], "called")?;
+    span.max_depth = usize_from_json(&data["max_depth"], "max_depth")?;
+    span.warmup = usize_from_json(&data["warmup"], "warmup")?;
+    span.max = duration_from_json(&data["max"], "max")?;
+    span.lifetime = duration_from_json(&data["lifetime"], "lifetime")?;
+    span.idle = duration_from_json(&data["idle"], "idle")?;
+    span.polls = usize_from_json(&data["polls"], "polls")?;
+    span.max_poll = duration_from_json(&data["max_poll"], "max_poll")?;
+
+    let stddev = duration_from_json(&data["stddev"], "stddev")?.as_secs_f64();
+    span.mean = span.mean().as_secs_f64();
+    if span.called > 1 {
+        span.m2 = stddev * stddev * (span.called - 1) as f64;
+    }
+
+    span.durations = durations_from_json(&data["durations"], "durations")?;
+    span.recent = durations_from_json(&data["recent"], "recent")?.into();
+
+    for (key, value) in data["counters"].entries() {
+        let value = value
+            .as_f64()
+            .ok_or_else(|| invalid(&format!("counter '{}' should be a number", key)))?;
+        span.counters.insert(key.into(), value);
+    }
+
+    for (key, value) in data["metrics"].entries() {
+        let value = value
+            .as_f64()
+            .ok_or_else(|| invalid(&format!("metric '{}' should be a number", key)))?;
+        span.metrics.insert(key.into(), value);
+    }
+
+    for (key, value) in data["tasks"].entries() {
+        let task = key
+            .parse()
+            .map_err(|_| invalid(&format!("invalid task id '{}'", key)))?;
+        let elapsed = value
+            .as_u64()
+            .ok_or_else(|| invalid(&format!("task '{}' should contain an integer", key)))?;
+        span.tasks.insert(task, Duration::from_micros(elapsed));
+    }
+
+    for (key, value) in data["threads"].entries() {
+        let elapsed = value
+            .as_u64()
+            .ok_or_else(|| invalid(&format!("thread '{}' should contain an integer", key)))?;
+        span.threads
+            .insert(key.into(), Duration::from_micros(elapsed));
+    }
+
+    if !data["energy"].is_null() {
+        span.energy = data["energy"]
+            .as_f64()
+            .ok_or_else(|| invalid("'energy' should be a number"))?;
+    }
+
+    if !data["max_rss"].is_null() {
+        span.max_rss = u64_from_json(&data["max_rss"], "max_rss")?;
+    }
+
+    if !data["errors"].is_null() {
+        span.errors = usize_from_json(&data["errors"], "errors")?;
+    }
+
+    if !data["successes"].is_null() {
+        span.successes = usize_from_json(&data["successes"], "successes")?;
+    }
+
+    if !data["failures"].is_null() {
+        span.failures = usize_from_json(&data["failures"], "failures")?;
+    }
+
+    if !data["panics"].is_null() {
+        span.panics = usize_from_json(&data["panics"], "panics")?;
+    }
+
+    let hardware = &data["hardware"];
+    if !hardware.is_null() {
+        span.hardware = HardwareCounters {
+            instructions: u64_from_json(&hardware["instructions"], "instructions")?,
+            cycles: u64_from_json(&hardware["cycles"], "cycles")?,
+            cache_misses: u64_from_json(&hardware["cache_misses"], "cache_misses")?,
+        };
+    }
+
+    let series = &data["series"];
+    if !series.is_null() {
+        let width = duration_from_json(&series["width"], "width")?;
+        if width == Duration::new(0, 0) {
+            return Err(invalid("time series width can not be zero"));
+        }
+        let first = if series["first"].is_null() {
+            0
+        } else {
+            usize_from_json(&series["first"], "first")?
+        };
+        let called = series["called"]
+            .members()
+            .map(|called| usize_from_json(called, "called"));
+        let elapsed = durations_from_json(&series["elapsed"], "elapsed")?;
+        if called.len() != elapsed.len() {
+            return Err(invalid(
+                "time series 'called' and 'elapsed' should have the same length",
+            ));
+        }
+        let buckets = called
+            .zip(elapsed)
+            .map(|(called, elapsed)| {
+                Ok(Bucket {
+                    called: called?,
+                    elapsed: elapsed,
+                })
+            })
+            .collect::<Result<Vec<_>, ImportError>>()?;
+        span.series = Some(TimeSeries::from_buckets(width, first, buckets));
+    }
+
+    let moving_average = &data["moving_average_state"];
+    if !moving_average.is_null() {
+        let half_life = duration_from_json(&moving_average["half_life"], "half_life")?;
+        if half_life == Duration::new(0, 0) {
+            return Err(invalid("moving average half-life can not be zero"));
+        }
+        let valid = |value: f64| value.is_finite() && value >= 0.0;
+        let sum = moving_average["sum"].as_f64();
+        let weight = moving_average["weight"].as_f64();
+        match (sum, weight) {
+            (Some(sum), Some(weight)) if valid(sum) && valid(weight) => {
+                span.moving_average = Some(MovingAverage::from_parts(half_life, sum, weight));
+            }
+            _ => {
+                return Err(invalid(
+                    "moving average 'sum' and 'weight' should be positive numbers",
+                ))
+            }
+        }
+    }
+
+    return Ok(span);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_invalid(data: &str) -> bool {
+        matches!(SpanGraph::from_json(data), Err(ImportError::Invalid(_)))
+    }
+
+    /// JSON for a graph containing a single span with the given `fields`
+    fn single_span(fields: &str) -> String {
+        let mut span = json::object! {
+            "id" => 0,
+            "elapsed" => "10 µs",
+            "called" => 2,
+            "max_depth" => 1,
+            "warmup" => 0,
+            "stddev" => "1 µs",
+            "max" => "6 µs",
+            "lifetime" => "12 µs",
+            "idle" => "2 µs",
+            "polls" => 2,
+            "max_poll" => "6 µs",
+        };
+        for (key, value) in json::parse(fields).unwrap().entries() {
+            span[key] = value.clone();
+        }
+        return json::stringify(json::object! {
+            "timings" => json::object! { "span" => span },
+        });
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut graph = SpanGraph::new();
+        graph.set_metadata("version", "1.0");
+        let root = graph.find_or_create("root");
+        let child = graph.find_or_create("child");
+        graph.increase_timing(root, Duration::from_micros(300));
+        graph.increase_timing(child, Duration::from_micros(100));
+        graph.increase_timing(child, Duration::from_micros(150));
+        graph.increase_call_count(root, child);
+        graph.increase_call_count(root, child);
+        graph.add_metric(child, "bytes", 42.0);
+
+        let imported = SpanGraph::from_json(&graph.as_json()).unwrap();
+        assert_eq!(imported.as_json(), graph.as_json());
+        assert_eq!(imported.metadata()["version"], "1.0");
+
+        let child = imported.span(imported.find("child").unwrap());
+        assert_eq!(child.called, 2);
+        assert_eq!(child.elapsed, Duration::from_micros(250));
+        assert_eq!(child.metrics["bytes"], 42.0);
+        assert_eq!(imported.total_calls(), graph.total_calls());
+    }
+
+    #[test]
+    fn minimal_span() {
+        let graph = SpanGraph::from_json(&single_span("{}")).unwrap();
+        let span = graph.span(graph.find("span").unwrap());
+        assert_eq!(span.called, 2);
+        assert_eq!(span.mean(), Duration::from_micros(5));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(is_invalid(""));
+        assert!(is_invalid("{"));
+        assert!(is_invalid("not json"));
+        assert!(is_invalid(r#"{"metadata": {"key": 3}}"#));
+    }
+
+    #[test]
+    fn missing_fields() {
+        assert!(is_invalid(r#"{"timings": {"span": {}}}"#));
+        for field in &["id", "elapsed", "called", "max", "stddev", "polls"] {
+            let mut data = json::parse(&single_span("{}")).unwrap();
+            data["timings"]["span"].remove(field);
+            assert!(is_invalid(&data.dump()), "missing '{}' was accepted", field);
+        }
+
+        let calls = r#"{"timings": {}, "calls": [{"caller": 0, "callee": 1, "count": 1}]}"#;
+        assert!(is_invalid(calls));
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(is_invalid(&single_span(r#"{"called": -1}"#)));
+        assert!(is_invalid(&single_span(r#"{"id": -3}"#)));
+        assert!(is_invalid(&single_span(r#"{"elapsed": "-10 µs"}"#)));
+        assert!(is_invalid(&single_span(r#"{"elapsed": 10}"#)));
+        assert!(is_invalid(&single_span(r#"{"max": "10 ms"}"#)));
+        assert!(is_invalid(&single_span(r#"{"errors": -1}"#)));
+        assert!(is_invalid(&single_span(r#"{"durations": [1, -2]}"#)));
+        assert!(is_invalid(&single_span(r#"{"level": "LOUD"}"#)));
+        assert!(is_invalid(&single_span(r#"{"tasks": {"abc": 3}}"#)));
+        assert!(is_invalid(&single_span(r#"{"energy": "a lot"}"#)));
+        assert!(is_invalid(&single_span(
+            r#"{"series": {"width": "0 µs", "called": [], "elapsed": []}}"#
+        )));
+        assert!(is_invalid(&single_span(
+            r#"{"series": {"width": "10 µs", "called": [1, 2], "elapsed": [3]}}"#
+        )));
+        assert!(is_invalid(&single_span(
+            r#"{"series": {"width": "10 µs", "called": [1], "elapsed": [3, 4]}}"#
+        )));
+
+        let state = |state: &str| single_span(&format!(r#"{{"moving_average_state": {}}}"#, state));
+        assert!(!is_invalid(&state(
+            r#"{"half_life": "1 µs", "sum": 1.5, "weight": 2}"#
+        )));
+        assert!(is_invalid(&state(
+            r#"{"half_life": "0 µs", "sum": 1.5, "weight": 2}"#
+        )));
+        assert!(is_invalid(&state(
+            r#"{"half_life": "1 µs", "sum": -1.5, "weight": 2}"#
+        )));
+        assert!(is_invalid(&state(r#"{"half_life": "1 µs", "sum": 1.5}"#)));
+        assert!(is_invalid(&state(
+            r#"{"half_life": "1 µs", "sum": "a", "weight": 2}"#
+        )));
+    }
+
+    #[test]
+    fn moving_average() {
+        let mut graph = SpanGraph::new();
+        graph.settings.moving_average = Some(Duration::from_secs(10));
+        let span = graph.find_or_create("span");
+        graph.increase_timing(span, Duration::from_micros(300));
+        graph.increase_timing(span, Duration::from_micros(100));
+        let expected = graph.span(span).moving_average().unwrap();
+        let weight = graph.span(span).moving_average.unwrap().weight();
+
+        let imported = SpanGraph::from_json(&graph.as_json()).unwrap();
+        let span = imported.span(imported.find("span").unwrap());
+        let moving_average = span.moving_average.unwrap();
+        assert_eq!(moving_average.half_life(), Duration::from_secs(10));
+        // floating point values can lose their last digit in JSON
+        assert!((moving_average.weight() - weight).abs() < 1e-9);
+        let value = span.moving_average().unwrap();
+        assert!(value.as_nanos().abs_diff(expected.as_nanos()) <= 1);
+
+        // resuming continues from the imported average
+        let mut resumed = SpanGraph::new();
+        resumed.settings.moving_average = Some(Duration::from_secs(10));
+        resumed.resume(&imported);
+        let span = resumed.find("span").unwrap();
+        resumed.increase_timing(span, Duration::from_micros(200));
+        let weight = resumed.span(span).moving_average.unwrap().weight();
+        assert!(weight > 2.9 && weight <= 3.0);
+    }
+
+    #[test]
+    fn extreme_values() {
+        let data = single_span(r#"{"called": 4294967296, "id": 18446744073709551615}"#);
+        let graph = SpanGraph::from_json(&data).unwrap();
+        let span = graph.span(graph.find("span").unwrap());
+        let _ = span.mean();
+        let _ = span.stddev();
+        let _ = graph.as_table();
+    }
+}
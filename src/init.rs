@@ -0,0 +1,45 @@
+use tracing::subscriber::SetGlobalDefaultError;
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+use crate::{GraphView, SpanTimingLayer};
+
+/// Install a default `SpanTimingLayer` as the global default subscriber, and
+/// get a handle to the corresponding span graph.
+///
+/// ```no_run
+/// #[tracing::instrument]
+/// fn compute(n: u64) -> u64 {
+///     (0..n).sum()
+/// }
+///
+/// let timings = tracing_timing_graph::init();
+/// compute(10);
+/// println!("{}", timings.read().as_table());
+/// ```
+///
+/// # Panic
+///
+/// This function panics if a global default subscriber was already set.
+pub fn init() -> GraphView {
+    init_with(SpanTimingLayer::new())
+}
+
+/// Install the given `layer` as the global default subscriber, and get a
+/// handle to the corresponding span graph. This allows to configure the layer
+/// before installing it, see `init`.
+///
+/// # Panic
+///
+/// This function panics if a global default subscriber was already set.
+pub fn init_with(layer: SpanTimingLayer) -> GraphView {
+    try_init_with(layer).expect("failed to set the global default subscriber")
+}
+
+/// Try to install the given `layer` as the global default subscriber, and get
+/// a handle to the corresponding span graph, see `init_with`.
+pub fn try_init_with(layer: SpanTimingLayer) -> Result<GraphView, SetGlobalDefaultError> {
+    let view = layer.view();
+    let subscriber = Registry::default().with(layer);
+    tracing::subscriber::set_global_default(subscriber)?;
+    return Ok(view);
+}
@@ -1,54 +1,1082 @@
 use tracing::{
-    span::{Attributes, Id},
-    Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Level, Metadata, Subscriber,
 };
-use tracing_subscriber::layer::{Context, Layer};
-use tracing_subscriber::registry::{LookupSpan, SpanRef};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, Registry, SpanRef};
 
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
+use petgraph::graph::NodeIndex;
 use quanta::Clock;
+use regex::Regex;
 
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::SpanGraph;
+#[cfg(target_os = "linux")]
+use crate::energy::EnergySampler;
+use crate::external::ExternalParent;
+use crate::graph::Invocation;
+use crate::region::Regions;
+use crate::series::ROLLING_WINDOW_SLOTS;
+use crate::timeline::Timeline;
+use crate::{
+    EvictionPolicy, Format, GraphView, HardwareCounters, ImportError, LatencyObjective, LayerError,
+    SpanGraph, SpanIndex,
+};
 
 /// Extension to store timing data on spans
 struct SpanTimingExtension {
+    /// Creation of this span, as given by `quanta::Clock::start()`
+    created: u64,
     /// Last start of this span, as given by `quanta::Clock::start()`
     start: Option<u64>,
     /// Total elapsed time on this span, counting all enter/exit pairs
     elapsed: Duration,
+    /// Number of enter/exit pairs on this span
+    polls: usize,
+    /// Longest duration of a single enter/exit pair on this span
+    max_poll: Duration,
+    /// First time this span was entered, only recorded for the timeline
+    first_enter: Option<Instant>,
+    /// Id of the tokio task in which this span was first entered
+    task: Option<u64>,
+    /// Name of the thread in which this span was first entered
+    thread: Option<String>,
+    /// Hardware counters at the last start of this span
+    #[cfg(all(target_os = "linux", feature = "perf"))]
+    hardware_start: Option<HardwareCounters>,
+    /// Hardware counters accumulated over all enter/exit pairs on this span
+    hardware: HardwareCounters,
+    /// Energy counters at the last start of this span
+    #[cfg(target_os = "linux")]
+    energy_start: Option<Vec<u64>>,
+    /// Energy consumed over all enter/exit pairs on this span, in joules
+    energy: f64,
+    /// Largest resident set size sampled when entering or exiting this span
+    max_rss: u64,
+    /// Last value of the numeric fields recorded on this span
+    counters: Vec<(&'static str, f64)>,
+    /// Number of `ERROR` events emitted inside this span
+    errors: usize,
+    /// Outcome of this span, taken from one of the `OUTCOME_FIELDS`: `true`
+    /// for success and `false` for failure
+    outcome: Option<bool>,
+    /// Was this span exited while the thread was unwinding from a panic?
+    panicked: bool,
+    /// Name used to group this span instead of the static span name, taken
+    /// from one of the `NAME_FIELDS`
+    name: Option<String>,
 }
 
 impl SpanTimingExtension {
-    fn new() -> SpanTimingExtension {
+    fn new(created: u64) -> SpanTimingExtension {
         SpanTimingExtension {
+            created: created,
             start: None,
             elapsed: Duration::new(0, 0),
+            polls: 0,
+            max_poll: Duration::new(0, 0),
+            first_enter: None,
+            task: None,
+            thread: None,
+            #[cfg(all(target_os = "linux", feature = "perf"))]
+            hardware_start: None,
+            hardware: HardwareCounters::default(),
+            #[cfg(target_os = "linux")]
+            energy_start: None,
+            energy: 0.0,
+            max_rss: 0,
+            counters: Vec::new(),
+            errors: 0,
+            outcome: None,
+            panicked: false,
+            name: None,
+        }
+    }
+}
+
+/// Extension storing the timing data of all the `SpanTimingLayer` attached to
+/// the same subscriber on a span, together with the corresponding layer id
+struct SpanTimingExtensions(Vec<(usize, SpanTimingExtension)>);
+
+impl SpanTimingExtensions {
+    fn get(&self, layer: usize) -> Option<&SpanTimingExtension> {
+        self.0
+            .iter()
+            .find(|(id, _)| *id == layer)
+            .map(|(_, timing)| timing)
+    }
+
+    fn get_mut(&mut self, layer: usize) -> Option<&mut SpanTimingExtension> {
+        self.0
+            .iter_mut()
+            .find(|(id, _)| *id == layer)
+            .map(|(_, timing)| timing)
+    }
+}
+
+/// Fields overriding the name used to group spans, following the
+/// OpenTelemetry convention of `otel.name`
+const NAME_FIELDS: &[&str] = &["otel.name", "timing.name"];
+
+/// Fields recording the outcome of a span, either `ok = <bool>` or
+/// `error = <bool or error value>`
+const OUTCOME_FIELDS: &[&str] = &["ok", "error"];
+
+/// Check if spans with the given `metadata` have one of the given `fields`
+fn has_any_field(metadata: &Metadata<'_>, fields: &[&str]) -> bool {
+    metadata
+        .fields()
+        .iter()
+        .any(|field| fields.contains(&field.name()))
+}
+
+/// Name of the synthetic span used to report the layer overhead
+const OVERHEAD_SPAN: &str = "tracing_timing_graph::overhead";
+
+/// When the `noop` feature is enabled (or the `release_noop` feature in release
+/// builds), the layer does not record anything, removing all the overhead of
+/// the instrumentation while keeping the same API.
+pub(crate) const NOOP: bool = cfg!(any(
+    feature = "noop",
+    all(feature = "release_noop", not(debug_assertions))
+));
+
+/// Counter used to give a unique id to each layer
+static NEXT_LAYER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Time spent by a layer in its own callbacks, since the last time it was
+/// added to the span graph
+#[derive(Default)]
+struct Overhead {
+    /// total time spent in callbacks, in nanoseconds
+    elapsed: AtomicU64,
+    /// number of callbacks
+    calls: AtomicUsize,
+}
+
+/// Guard measuring the time spent in a layer callback until it is dropped
+struct OverheadGuard<'a> {
+    overhead: &'a Overhead,
+    clock: &'a Clock,
+    start: u64,
+}
+
+impl Drop for OverheadGuard<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.clock.delta(self.start, self.clock.end());
+        self.overhead
+            .elapsed
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        self.overhead.calls.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Field visitor collecting the value of numeric fields
+struct CountersVisitor<'a>(&'a mut Vec<(&'static str, f64)>);
+
+impl CountersVisitor<'_> {
+    fn set(&mut self, field: &Field, value: f64) {
+        let name = field.name();
+        match self.0.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, existing)) => *existing = value,
+            None => self.0.push((name, value)),
+        }
+    }
+}
+
+impl Visit for CountersVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.set(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set(field, value as f64);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set(field, value as f64);
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+}
+
+/// Field visitor collecting the value of the `NAME_FIELDS`
+struct NameVisitor<'a>(&'a mut Option<String>);
+
+impl Visit for NameVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if NAME_FIELDS.contains(&field.name()) {
+            *self.0 = Some(value.into());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if NAME_FIELDS.contains(&field.name()) {
+            *self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Field visitor collecting the outcome of a span from the `OUTCOME_FIELDS`.
+/// `ok` is a success if `true`, while `error` is a failure if `true` or if it
+/// contains any other value (typically the error itself).
+struct OutcomeVisitor<'a>(&'a mut Option<bool>);
+
+impl Visit for OutcomeVisitor<'_> {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        match field.name() {
+            "ok" => *self.0 = Some(value),
+            "error" => *self.0 = Some(!value),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, _: &dyn std::fmt::Debug) {
+        if field.name() == "error" {
+            *self.0 = Some(false);
         }
     }
 }
 
 /// `tracing_subscriber` Layer that add timing information to spans,
 /// accounting for the full span graph.
+///
+/// Spans are grouped by their full name, made of the module path (or target)
+/// and the span name. Spans with an `otel.name` or `timing.name` field are
+/// instead grouped by the value of this field, which can also be recorded
+/// after the span creation:
+///
+/// ```no_run
+/// let span = tracing::info_span!("request", otel.name = tracing::field::Empty);
+/// span.record("otel.name", "GET /users");
+/// ```
+///
+/// Spans recording an `ok = <bool>` or `error = ...` field are counted as
+/// successes or failures (see `SpanTiming::successes` and
+/// `SpanTiming::failures`), giving the error rate of each span group:
+///
+/// ```no_run
+/// let span = tracing::info_span!("query", ok = tracing::field::Empty);
+/// // ...
+/// span.record("ok", true);
+/// ```
 pub struct SpanTimingLayer {
+    /// unique id of this layer, used to find the corresponding extension
+    id: usize,
     clock: Clock,
-    timings: Arc<Mutex<SpanGraph>>,
+    pub(crate) timings: Arc<RwLock<SpanGraph>>,
+    /// should we record the first enter time of spans for the timeline?
+    timeline: bool,
+    /// should we record the tokio task in which spans are entered?
+    #[cfg(feature = "tokio")]
+    tasks: bool,
+    /// should we record the thread in which spans are entered?
+    threads: bool,
+    /// should we publish span data through the `metrics` crate?
+    #[cfg(feature = "metrics")]
+    metrics: bool,
+    /// should we record hardware performance counters?
+    #[cfg(all(target_os = "linux", feature = "perf"))]
+    hardware: bool,
+    /// RAPL energy counters, if measuring energy
+    #[cfg(target_os = "linux")]
+    energy: Option<Arc<EnergySampler>>,
+    /// should we sample the resident set size when entering/exiting spans?
+    #[cfg(target_os = "linux")]
+    rss: bool,
+    /// should we sum numeric fields recorded on spans?
+    counters: bool,
+    /// should we add nodes for named events?
+    events: bool,
+    /// should we remove generic parameters and closures from span names?
+    normalize_names: bool,
+    /// rules used to rename spans before grouping them
+    renames: Vec<(Regex, String)>,
+    /// only record spans with a target starting with this prefix
+    target_prefix: Option<String>,
+    /// only record spans with a field with this name
+    marker_field: Option<String>,
+    /// emit a warning for invocations taking longer than this
+    slow_threshold: Option<Duration>,
+    /// per span override of `slow_threshold`, indexed by span name
+    slow_thresholds: BTreeMap<String, Duration>,
+    /// estimated measurement overhead, removed from each enter/exit pair
+    compensation: Duration,
+    /// data from finished profiling regions
+    pub(crate) regions: Arc<Mutex<Regions>>,
+    /// time spent in this layer callbacks, if measuring it
+    overhead: Option<Arc<Overhead>>,
+    /// user-provided callback for errors
+    pub(crate) on_error: Option<Arc<dyn Fn(LayerError) + Send + Sync>>,
+    /// shared by all the clones of this layer, to write the output file only
+    /// when the last clone is dropped
+    owners: Arc<()>,
 }
 
 impl SpanTimingLayer {
     /// Create a new empty `SpanTimingLayer`
     pub fn new() -> SpanTimingLayer {
         SpanTimingLayer {
+            id: NEXT_LAYER_ID.fetch_add(1, Ordering::Relaxed),
             clock: Clock::new(),
-            timings: Arc::new(Mutex::new(SpanGraph::new())),
+            timings: Arc::new(RwLock::new(SpanGraph::new())),
+            timeline: false,
+            #[cfg(feature = "tokio")]
+            tasks: false,
+            threads: false,
+            #[cfg(feature = "metrics")]
+            metrics: false,
+            #[cfg(all(target_os = "linux", feature = "perf"))]
+            hardware: false,
+            #[cfg(target_os = "linux")]
+            energy: None,
+            #[cfg(target_os = "linux")]
+            rss: false,
+            counters: false,
+            events: false,
+            normalize_names: false,
+            renames: Vec::new(),
+            target_prefix: None,
+            marker_field: None,
+            slow_threshold: None,
+            slow_thresholds: BTreeMap::new(),
+            compensation: Duration::new(0, 0),
+            regions: Arc::new(Mutex::new(Regions::default())),
+            overhead: None,
+            on_error: None,
+            owners: Arc::new(()),
         }
     }
 
-    /// Get a reference to the span graph in this layer
-    pub fn graph(&self) -> Arc<Mutex<SpanGraph>> {
-        Arc::clone(&self.timings)
+    /// Accumulate the number of calls and elapsed time of each span in time
+    /// windows of the given `width`, in addition to the totals over the whole
+    /// run. The resulting time series are available in `SpanTiming::series`.
+    /// Only the most recent 4096 windows are kept for each span.
+    ///
+    /// # Panic
+    ///
+    /// This function panics if `width` is zero.
+    pub fn with_time_series(self, width: Duration) -> SpanTimingLayer {
+        assert!(
+            width > Duration::new(0, 0),
+            "time series width can not be zero"
+        );
+        self.timings.write().settings.time_series = Some(width);
+        self
+    }
+
+    /// Record the duration of individual invocations of each span, keeping
+    /// at most `capacity` of them per span. The recorded durations are
+    /// available in `SpanTiming::durations`.
+    ///
+    /// This is disabled by default to keep memory usage independent of the
+    /// number of span invocations.
+    pub fn with_invocation_durations(self, capacity: usize) -> SpanTimingLayer {
+        self.timings.write().settings.durations_capacity = capacity;
+        self
+    }
+
+    /// Ignore the first `count` invocations of each span, so that warm-up
+    /// effects such as lazy initialization or cold caches do not skew the
+    /// timing data. The number of ignored invocations is available in
+    /// `SpanTiming::warmup`.
+    pub fn with_warmup(self, count: usize) -> SpanTimingLayer {
+        self.timings.write().settings.warmup = count;
+        self
+    }
+
+    /// Also accumulate the number of calls and elapsed time of each span over
+    /// a rolling time window of the given `width` (for example the last
+    /// minute), in addition to the totals over the whole run. This data is
+    /// available with `SpanGraph::rolling_window`.
+    ///
+    /// # Panic
+    ///
+    /// This function panics if `width` is too small to be split in 60 slots,
+    /// i.e. if it is shorter than 60ns.
+    pub fn with_rolling_window(self, width: Duration) -> SpanTimingLayer {
+        assert!(
+            width / ROLLING_WINDOW_SLOTS as u32 > Duration::new(0, 0),
+            "rolling window is too small"
+        );
+        self.timings.write().settings.rolling_window = Some(width);
+        self
+    }
+
+    /// Maintain an exponentially weighted moving average of the invocation
+    /// durations of each span, where the weight of an invocation is halved
+    /// every `half_life`. Contrary to the mean over the whole run, this
+    /// reflects the current behavior of spans in long running processes. The
+    /// average is available with `SpanTiming::moving_average`.
+    pub fn with_moving_average(self, half_life: Duration) -> SpanTimingLayer {
+        assert!(
+            half_life > Duration::new(0, 0),
+            "moving average half-life must be positive"
+        );
+        self.timings.write().settings.moving_average = Some(half_life);
+        self
+    }
+
+    /// Keep the duration of the `capacity` most recent invocations of each
+    /// span in a ring buffer, available in `SpanTiming::recent`. This allows
+    /// to look at the recent behavior of spans in long running processes.
+    pub fn with_recent_durations(self, capacity: usize) -> SpanTimingLayer {
+        self.timings.write().settings.recent_capacity = capacity;
+        self
+    }
+
+    /// Record the start and end time of individual span invocations, keeping
+    /// at most `capacity` of them. The recorded timeline is available with
+    /// `SpanGraph::timeline`, and can be exported as a gantt chart.
+    pub fn with_timeline(mut self, capacity: usize) -> SpanTimingLayer {
+        self.timings.write().timeline = Some(Timeline::new(capacity));
+        self.timeline = true;
+        self
+    }
+
+    /// Sum the numeric fields recorded on spans (for example
+    /// `bytes = buffer.len()`) over all invocations, making the totals
+    /// available in `SpanTiming::counters`. Values recorded after the span
+    /// creation with `Span::record` are included, the last recorded value of
+    /// each field being used for a given invocation.
+    pub fn with_counters(mut self) -> SpanTimingLayer {
+        self.counters = true;
+        self
+    }
+
+    /// Record named events (created with `tracing::event!(name: "...", ...)`)
+    /// as spans with zero duration, called by the span in which the event
+    /// occurred. The call count of these spans is the number of times the
+    /// event occurred, making important markers such as cache misses visible
+    /// in the graph.
+    ///
+    /// Events without an explicit name, such as the ones created by
+    /// `tracing::info!`, are ignored.
+    pub fn with_events(mut self) -> SpanTimingLayer {
+        self.events = true;
+        self
+    }
+
+    /// Write the span graph in the given `format` to the file at `path` when
+    /// this layer and all its clones are dropped, or when calling
+    /// `GraphView::flush`. The file is
+    /// replaced atomically, see `SpanGraph::write_file`.
+    ///
+    /// Layers installed as the global default subscriber are never dropped,
+    /// and `GraphView::flush` should be called explicitly in this case.
+    pub fn with_output<P: Into<PathBuf>>(self, path: P, format: Format) -> SpanTimingLayer {
+        self.timings.write().settings.output = Some((path.into(), format));
+        self
+    }
+
+    /// Load the span graph saved as JSON by a previous run in the file at
+    /// `path`, and continue accumulating data into it. This allows a job
+    /// restarted many times (for example a batch job resuming from a
+    /// checkpoint) to produce a single cumulative profile.
+    ///
+    /// The number of accumulated runs is stored in the graph metadata, and
+    /// available with `SpanGraph::runs`. If the file does not exist, this
+    /// starts from an empty graph as the first run.
+    ///
+    /// ```no_run
+    /// # use tracing_timing_graph::{Format, SpanTimingLayer};
+    /// let layer = SpanTimingLayer::new()
+    ///     .with_resume("timings.json")?
+    ///     .with_output("timings.json", Format::Json);
+    /// # Ok::<(), tracing_timing_graph::ImportError>(())
+    /// ```
+    pub fn with_resume<P: AsRef<Path>>(self, path: P) -> Result<SpanTimingLayer, ImportError> {
+        let previous = match SpanGraph::read_json_file(path) {
+            Ok(previous) => previous,
+            Err(ImportError::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(self);
+            }
+            Err(error) => return Err(error),
+        };
+
+        self.timings.write().resume(&previous);
+        return Ok(self);
+    }
+
+    /// Install a panic hook printing the span graph as a table on the standard
+    /// error when the program panics, so that the data accumulated before a
+    /// crash is not lost. The graph is also written to the file configured
+    /// with `SpanTimingLayer::with_output`, if any.
+    ///
+    /// The previous panic hook is still called before printing the table.
+    pub fn with_dump_on_panic(self) -> SpanTimingLayer {
+        let view = self.view();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            // the panic could come from inside the layer, while the graph is
+            // locked for writing
+            match view.try_read() {
+                Some(graph) => {
+                    eprintln!("{}", graph.render(Format::Table));
+                    if let Err(error) = graph.flush() {
+                        eprintln!("failed to write span timing report: {}", error);
+                    }
+                }
+                None => eprintln!("span timing graph is locked, can not print it"),
+            }
+        }));
+        self
+    }
+
+    /// Set a time budget for spans with the given full `name`: the total
+    /// elapsed time in these spans should not exceed `budget`. When the budget
+    /// is exceeded, this layer emits a `WARN` event, and the span is marked in
+    /// the reports.
+    ///
+    /// ```no_run
+    /// # use tracing_timing_graph::SpanTimingLayer;
+    /// # use std::time::Duration;
+    /// let layer = SpanTimingLayer::new()
+    ///     .with_budget("my_app::db::query", Duration::from_millis(500));
+    /// ```
+    pub fn with_budget(self, name: &str, budget: Duration) -> SpanTimingLayer {
+        self.timings.write().set_budget(name, budget);
+        self
+    }
+
+    /// Emit a `WARN` event when closing a span whose invocation took longer
+    /// than `threshold`, with the span name and duration. This turns the
+    /// layer into a lightweight detector of slow calls. The threshold can be
+    /// overridden for specific spans with `with_slow_threshold_for`.
+    ///
+    /// ```no_run
+    /// # use tracing_timing_graph::SpanTimingLayer;
+    /// # use std::time::Duration;
+    /// let layer = SpanTimingLayer::new()
+    ///     .with_slow_threshold(Duration::from_millis(100))
+    ///     .with_slow_threshold_for("my_app::db::query", Duration::from_millis(10));
+    /// ```
+    pub fn with_slow_threshold(mut self, threshold: Duration) -> SpanTimingLayer {
+        self.slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Emit a `WARN` event when closing a span with the given full `name`
+    /// whose invocation took longer than `threshold`, overriding the global
+    /// threshold set with `with_slow_threshold`.
+    pub fn with_slow_threshold_for(mut self, name: &str, threshold: Duration) -> SpanTimingLayer {
+        self.slow_thresholds.insert(name.into(), threshold);
+        self
+    }
+
+    /// Register a latency `objective` for spans with the given full `name`,
+    /// see `SpanGraph::set_objective`. Percentile objectives need individual
+    /// invocation durations, enabled with `with_recent_durations` or
+    /// `with_invocation_durations`.
+    ///
+    /// ```no_run
+    /// # use tracing_timing_graph::{LatencyObjective, SpanTimingLayer};
+    /// # use std::time::Duration;
+    /// let layer = SpanTimingLayer::new()
+    ///     .with_recent_durations(1000)
+    ///     .with_objective(
+    ///         "my_app::db::query",
+    ///         LatencyObjective::Percentile(99.0, Duration::from_millis(10)),
+    ///     )
+    ///     .with_objective("my_app::db::query", LatencyObjective::Mean(Duration::from_millis(1)));
+    /// ```
+    pub fn with_objective(self, name: &str, objective: LatencyObjective) -> SpanTimingLayer {
+        self.timings.write().set_objective(name, objective);
+        self
+    }
+
+    /// Measure the time spent by this layer in its own callbacks (including
+    /// the construction of span names and waiting for the span graph lock),
+    /// and report it as a synthetic span named
+    /// `tracing_timing_graph::overhead`. The call count of this span is the
+    /// number of callbacks.
+    ///
+    /// This allows to check whether the instrumentation overhead is
+    /// distorting the timing data. The overhead is added to the graph when
+    /// spans are closed.
+    pub fn with_overhead_measurement(mut self) -> SpanTimingLayer {
+        self.overhead = Some(Arc::new(Overhead::default()));
+        self
+    }
+
+    /// Estimate the measurement overhead of this layer by timing empty spans,
+    /// and subtract this estimate from the duration of each span. This makes
+    /// the data more accurate for very short spans, where the measurement
+    /// overhead can dominate the actual execution time.
+    ///
+    /// The estimate only accounts for the overhead of the span itself, not
+    /// for the overhead of spans nested inside it. The calibration takes a few
+    /// milliseconds.
+    pub fn with_overhead_compensation(mut self) -> SpanTimingLayer {
+        self.compensation = calibrate_overhead();
+        self
+    }
+
+    /// Start measuring the overhead of a layer callback, if enabled
+    fn measure_overhead(&self) -> Option<OverheadGuard<'_>> {
+        self.overhead.as_ref().map(|overhead| OverheadGuard {
+            overhead: overhead,
+            clock: &self.clock,
+            start: self.clock.start(),
+        })
+    }
+
+    /// Attach `key`/`value` metadata describing this run to the span graph,
+    /// see `SpanGraph::set_metadata`.
+    pub fn with_metadata(self, key: &str, value: &str) -> SpanTimingLayer {
+        self.timings.write().set_metadata(key, value);
+        self
+    }
+
+    /// Tag the span graph with the `rank` of the current process in a
+    /// parallel run, such as the MPI rank, see `SpanGraph::set_rank`.
+    pub fn with_rank(self, rank: usize) -> SpanTimingLayer {
+        self.timings.write().set_rank(rank);
+        self
+    }
+
+    /// Break down the elapsed time of each span by the tokio task in which
+    /// the span was entered, to find out whether a single task dominates the
+    /// cost of a span shared between tasks. The data is available with
+    /// `SpanGraph::task_breakdown`.
+    ///
+    /// Spans entered outside of any task (for example inside `block_on`) are
+    /// not included in the breakdown.
+    #[cfg(feature = "tokio")]
+    pub fn with_task_breakdown(mut self) -> SpanTimingLayer {
+        self.tasks = true;
+        self
+    }
+
+    /// Break down the elapsed time of each span by the thread in which the
+    /// span was entered, to find imbalance between the threads executing a
+    /// parallel section. Threads are identified by their name, or by their
+    /// `ThreadId` for unnamed threads. The data is available with
+    /// `SpanGraph::thread_breakdown` and `SpanGraph::as_thread_table`.
+    pub fn with_thread_breakdown(mut self) -> SpanTimingLayer {
+        self.threads = true;
+        self
+    }
+
+    /// Record the number of instructions, CPU cycles and cache misses spent
+    /// inside each span, using Linux `perf_event` hardware counters. The data
+    /// is available in `SpanTiming::hardware`, and can be used to distinguish
+    /// memory-bound from compute-bound spans.
+    ///
+    /// Only user space events are counted. This returns an error if the
+    /// counters are not available, for example in virtual machines or when
+    /// restricted by `/proc/sys/kernel/perf_event_paranoid`.
+    #[cfg(all(target_os = "linux", feature = "perf"))]
+    pub fn with_hardware_counters(mut self) -> std::io::Result<SpanTimingLayer> {
+        crate::hardware::check()?;
+        self.hardware = true;
+        Ok(self)
+    }
+
+    /// Estimate the energy consumed inside each span, using the RAPL energy
+    /// counters of the CPU packages exposed in `/sys/class/powercap`. The
+    /// data is available in `SpanTiming::energy`.
+    ///
+    /// RAPL counters measure the whole CPU packages, so the energy recorded
+    /// for a span includes the energy consumed by other threads and
+    /// processes running at the same time. Reading the counters is also
+    /// relatively slow, so this should be used for spans taking at least a
+    /// few milliseconds.
+    ///
+    /// This returns an error if the counters are not available or not
+    /// readable, reading them usually requires root permissions.
+    #[cfg(target_os = "linux")]
+    pub fn with_energy_measurement(mut self) -> std::io::Result<SpanTimingLayer> {
+        self.energy = Some(Arc::new(EnergySampler::new()?));
+        Ok(self)
+    }
+
+    /// Sample the resident set size (RSS) of the process when entering and
+    /// exiting spans, and record the largest value observed for each span
+    /// group in `SpanTiming::max_rss`. This allows to correlate memory spikes
+    /// with specific functions.
+    ///
+    /// Memory allocated and released between two samples is not visible, and
+    /// sampling requires reading `/proc/self/status`, so this should be used
+    /// with relatively long spans. This returns an error if the RSS can not
+    /// be read.
+    #[cfg(target_os = "linux")]
+    pub fn with_rss_sampling(mut self) -> std::io::Result<SpanTimingLayer> {
+        crate::memory::resident_set_size()?;
+        self.rss = true;
+        Ok(self)
+    }
+
+    /// Publish the data of each span through the [`metrics`] facade when the
+    /// span is closed, so that existing exporters (Prometheus, statsd, ...)
+    /// can pick up span timings. All metrics use a `span` label containing
+    /// the full span name:
+    ///
+    /// - `tracing_timing_graph.calls` is a counter with the number of calls;
+    /// - `tracing_timing_graph.elapsed_nanoseconds` is a counter with the
+    ///   total time spent in the span;
+    /// - `tracing_timing_graph.duration_seconds` is a histogram with the
+    ///   duration of each invocation.
+    ///
+    /// [`metrics`]: https://docs.rs/metrics
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self) -> SpanTimingLayer {
+        self.metrics = true;
+        self
+    }
+
+    /// Rename spans matching the `pattern` regular expression using
+    /// `replacement` before grouping them, allowing to merge spans with
+    /// embedded identifiers or to remove noisy prefixes.
+    ///
+    /// The rules are applied in the order they were added to the full span
+    /// name (including the module path), replacing all matches. The
+    /// replacement string can refer to capture groups, see
+    /// [`regex::Regex::replace_all`] for the syntax.
+    ///
+    /// ```no_run
+    /// # use tracing_timing_graph::SpanTimingLayer;
+    /// // group all `request{id=...}` spans together
+    /// let layer = SpanTimingLayer::new().with_rename(r"\{id=\d+\}", "");
+    /// ```
+    ///
+    /// # Panic
+    ///
+    /// This function panics if `pattern` is not a valid regular expression.
+    pub fn with_rename(mut self, pattern: &str, replacement: &str) -> SpanTimingLayer {
+        let regex = Regex::new(pattern).expect("invalid regex in span rename rule");
+        self.renames.push((regex, replacement.into()));
+        self
+    }
+
+    /// Remove generic type parameters, closure fragments (`{{closure}}`) and
+    /// symbol hash suffixes from span names before grouping them, so that
+    /// the monomorphized copies of a generic function end up in the same
+    /// span group. For example `my_crate::Parser<R>::parse::<u32>::{{closure}}`
+    /// becomes `my_crate::Parser::parse`.
+    ///
+    /// This is applied before the rules from `SpanTimingLayer::with_rename`.
+    pub fn with_normalized_names(mut self) -> SpanTimingLayer {
+        self.normalize_names = true;
+        self
+    }
+
+    /// Limit the number of distinct span groups in the graph to `max`,
+    /// protecting long-running services using dynamic span names (see
+    /// `SpanTimingLayer::with_rename` or the `otel.name` field) from
+    /// unbounded memory usage.
+    ///
+    /// Once the limit is reached, new spans are folded into a synthetic
+    /// `<other>` span for each caller, named `<caller name>::<other>`, or in
+    /// a single top-level `<other>` span when the caller is not known. The
+    /// overflow spans are created even if the limit is already reached.
+    pub fn with_max_spans(self, max: usize) -> SpanTimingLayer {
+        self.timings.write().settings.max_spans = Some(max);
+        self
+    }
+
+    /// Limit the number of span groups in the graph to `max`, for
+    /// memory-constrained deployments. When the graph grows larger than
+    /// this, the spans with the smallest elapsed time are removed according
+    /// to `policy` until the graph is 10% below the limit.
+    ///
+    /// The amount of removed data is available with `SpanGraph::evicted`.
+    /// Contrary to `SpanTimingLayer::with_max_spans`, this also applies to
+    /// spans which were already recorded.
+    pub fn with_size_limit(self, max: usize, policy: EvictionPolicy) -> SpanTimingLayer {
+        self.timings.write().settings.size_limit = Some((max, policy));
+        self
+    }
+
+    /// Only record spans with a target starting with `prefix`, ignoring all
+    /// other spans. This allows to use multiple independent layers, each one
+    /// recording a different subsystem of a large application.
+    ///
+    /// Calls going through ignored spans are attributed to the closest
+    /// recorded ancestor.
+    ///
+    /// ```no_run
+    /// # use tracing_subscriber::layer::SubscriberExt;
+    /// # use tracing_timing_graph::SpanTimingLayer;
+    /// let database = SpanTimingLayer::new().with_target_prefix("my_app::db");
+    /// let network = SpanTimingLayer::new().with_target_prefix("my_app::net");
+    ///
+    /// let subscriber = tracing_subscriber::registry().with(database).with(network);
+    /// ```
+    pub fn with_target_prefix(mut self, prefix: &str) -> SpanTimingLayer {
+        self.target_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Only record spans declaring a field called `name`, ignoring all other
+    /// spans. The field can be left empty, and is then only used to mark the
+    /// spans, e.g. `info_span!("query", db = tracing::field::Empty)`.
+    ///
+    /// Calls going through ignored spans are attributed to the closest
+    /// recorded ancestor.
+    pub fn with_marker_field(mut self, name: &str) -> SpanTimingLayer {
+        self.marker_field = Some(name.into());
+        self
+    }
+
+    /// Check if spans with the given metadata should be recorded by this layer
+    fn in_scope(&self, metadata: &Metadata<'_>) -> bool {
+        if let Some(ref prefix) = self.target_prefix {
+            if !metadata.target().starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+
+        if let Some(ref name) = self.marker_field {
+            if metadata.fields().field(name).is_none() {
+                return false;
+            }
+        }
+
+        return true;
+    }
+
+    /// Call `handler` whenever this layer encounters an error while recording
+    /// span data. Such errors are otherwise silently ignored, and the
+    /// corresponding data is skipped.
+    pub fn with_error_handler<F>(mut self, handler: F) -> SpanTimingLayer
+    where
+        F: Fn(LayerError) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(handler));
+        self
+    }
+
+    /// Report an error to the user-provided error handler, if any
+    fn error(&self, error: LayerError) {
+        if let Some(ref on_error) = self.on_error {
+            on_error(error);
+        }
+    }
+
+    /// Get the name used to group spans with the given metadata
+    fn span_name(&self, metadata: &Metadata<'_>) -> String {
+        let mut name = if let Some(path) = metadata.module_path() {
+            path.to_string()
+        } else {
+            metadata.target().to_string()
+        };
+        name += "::";
+
+        if metadata.name().contains(' ') {
+            name += "{";
+            name += metadata.name();
+            name += "}";
+        } else {
+            name += metadata.name();
+        }
+
+        return self.rename(name);
+    }
+
+    /// Apply the normalization from `SpanTimingLayer::with_normalized_names`
+    /// and the rules from `SpanTimingLayer::with_rename` to `name`
+    fn rename(&self, mut name: String) -> String {
+        if self.normalize_names {
+            name = crate::names::normalize_name(&name);
+        }
+
+        for (regex, replacement) in &self.renames {
+            if let std::borrow::Cow::Owned(renamed) = regex.replace_all(&name, replacement.as_str())
+            {
+                name = renamed;
+            }
+        }
+
+        return name;
+    }
+
+    /// Get the name overriding the static span name for `span`, if any
+    fn name_override<S>(&self, span: &SpanRef<'_, S>) -> Option<String>
+    where
+        S: for<'a> LookupSpan<'a>,
+    {
+        span.extensions()
+            .get::<SpanTimingExtensions>()
+            .and_then(|timings| timings.get(self.id))
+            .and_then(|timing| timing.name.clone())
+    }
+
+    /// Find the span group corresponding to spans with the given `metadata`
+    /// (or the given `name` override) and called from `parent` in the
+    /// `graph`, creating it if needed. The group is cached by callsite, to
+    /// avoid re-creating and comparing span names every time a span is
+    /// closed.
+    fn find_or_create(
+        &self,
+        graph: &mut SpanGraph,
+        metadata: &'static Metadata<'static>,
+        name: Option<&str>,
+        parent: Option<SpanIndex>,
+    ) -> SpanIndex {
+        let span = match name {
+            // spans from the same callsite can have different names
+            Some(name) => graph.find_or_create_limited(&self.rename(name.into()), parent),
+            None => {
+                let callsite = metadata.callsite();
+                if let Some(&span) = graph.callsites.get(&callsite) {
+                    return span;
+                }
+
+                let name = self.span_name(metadata);
+                let span = graph.find_or_create_limited(&name, parent);
+                // spans folded in an overflow span depend on their parent
+                if graph.span(span).name == name {
+                    graph.callsites.insert(callsite, span);
+                }
+                span
+            }
+        };
+
+        let timing = &mut graph.graph[NodeIndex::from(span)];
+        let level = *metadata.level();
+        timing.level = Some(
+            timing
+                .level
+                .map_or(level, |other| std::cmp::min(level, other)),
+        );
+        return span;
+    }
+
+    /// Find the nearest ancestor of the span with the given `id` (including
+    /// the span itself) recorded by this layer, to be used as the parent of
+    /// a `DeferredDuration`.
+    pub(crate) fn external_parent<S>(&self, registry: &S, id: &Id) -> Option<ExternalParent>
+    where
+        S: for<'a> LookupSpan<'a>,
+    {
+        let span = registry.span(id)?;
+        let parent = span.scope().find(|span| self.in_scope(span.metadata()))?;
+        return Some(ExternalParent {
+            metadata: parent.metadata(),
+            name: self.name_override(&parent),
+        });
+    }
+
+    /// Record an externally measured `duration` for the span group called
+    /// `name`, called from the given `parent` span. See `DeferredDuration`.
+    pub(crate) fn record_external(
+        &self,
+        parent: Option<&ExternalParent>,
+        name: &str,
+        duration: Duration,
+    ) {
+        if NOOP {
+            return;
+        }
+
+        let mut graph = self.timings.write();
+        let parent = parent
+            .filter(|parent| self.in_scope(parent.metadata))
+            .map(|parent| {
+                self.find_or_create(&mut graph, parent.metadata, parent.name.as_deref(), None)
+            });
+        graph.add_external_duration(parent, name, duration);
+        graph.enforce_size_limit();
+    }
+
+    /// Get a handle to the span graph in this layer, with the `lock` method
+    /// of previous versions giving exclusive access to the graph.
+    #[deprecated(note = "use SpanTimingLayer::view instead")]
+    pub fn graph(&self) -> GraphView {
+        self.view()
+    }
+
+    /// Get a read-only view of the span graph in this layer
+    pub fn view(&self) -> GraphView {
+        GraphView::new(Arc::clone(&self.timings))
+    }
+}
+
+/// Clones of a layer share the same span graph and clock, allowing to install
+/// the same timing sink on multiple subscribers (for example in tests, or with
+/// multiple async runtimes) and get all the data in a single graph.
+impl Clone for SpanTimingLayer {
+    fn clone(&self) -> SpanTimingLayer {
+        SpanTimingLayer {
+            // clones could be attached to the same subscriber, and need their
+            // own span extensions
+            id: NEXT_LAYER_ID.fetch_add(1, Ordering::Relaxed),
+            clock: self.clock.clone(),
+            timings: Arc::clone(&self.timings),
+            timeline: self.timeline,
+            #[cfg(feature = "tokio")]
+            tasks: self.tasks,
+            threads: self.threads,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
+            #[cfg(all(target_os = "linux", feature = "perf"))]
+            hardware: self.hardware,
+            #[cfg(target_os = "linux")]
+            energy: self.energy.clone(),
+            #[cfg(target_os = "linux")]
+            rss: self.rss,
+            counters: self.counters,
+            events: self.events,
+            normalize_names: self.normalize_names,
+            renames: self.renames.clone(),
+            target_prefix: self.target_prefix.clone(),
+            marker_field: self.marker_field.clone(),
+            slow_threshold: self.slow_threshold,
+            slow_thresholds: self.slow_thresholds.clone(),
+            compensation: self.compensation,
+            regions: Arc::clone(&self.regions),
+            overhead: self.overhead.clone(),
+            on_error: self.on_error.clone(),
+            owners: Arc::clone(&self.owners),
+        }
+    }
+}
+
+/// Number of empty spans used to estimate the measurement overhead
+const CALIBRATION_SPANS: usize = 10_000;
+
+/// Estimate the measurement overhead of a `SpanTimingLayer`, using the median
+/// duration of empty spans
+fn calibrate_overhead() -> Duration {
+    let layer = SpanTimingLayer::new().with_invocation_durations(CALIBRATION_SPANS);
+    let graph = layer.view();
+
+    let subscriber = Registry::default().with(layer);
+    tracing::subscriber::with_default(subscriber, || {
+        for _ in 0..CALIBRATION_SPANS {
+            let _span = tracing::info_span!("calibration").entered();
+        }
+    });
+
+    let graph = graph.read();
+    let mut durations = match graph.spans().next() {
+        Some(span) => span.durations.clone(),
+        // spans can be disabled at compile time
+        None => return Duration::new(0, 0),
+    };
+    durations.sort_unstable();
+    return durations[durations.len() / 2];
+}
+
+impl Drop for SpanTimingLayer {
+    fn drop(&mut self) {
+        // clones of this layer share the same graph, which should only be
+        // written once all of them are done recording
+        if Arc::into_inner(std::mem::take(&mut self.owners)).is_none() {
+            return;
+        }
+
+        if let Err(error) = self.timings.read().flush() {
+            self.error(LayerError::Output { kind: error.kind() });
+        }
     }
 }
 
@@ -56,77 +1084,509 @@ impl<S> Layer<S> for SpanTimingLayer
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
-    fn new_span(&self, _: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
-        let span = ctx.span(id).expect("failed to get newly created span");
+    fn new_span(&self, attributes: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if NOOP {
+            return;
+        }
+
+        let _overhead = self.measure_overhead();
+
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => {
+                return self.error(LayerError::MissingSpan {
+                    callback: "new_span",
+                })
+            }
+        };
+        if !self.in_scope(span.metadata()) {
+            return;
+        }
+        let mut timing = SpanTimingExtension::new(self.clock.start());
+        if self.counters {
+            attributes.record(&mut CountersVisitor(&mut timing.counters));
+        }
+        if has_any_field(span.metadata(), NAME_FIELDS) {
+            attributes.record(&mut NameVisitor(&mut timing.name));
+        }
+        if has_any_field(span.metadata(), OUTCOME_FIELDS) {
+            attributes.record(&mut OutcomeVisitor(&mut timing.outcome));
+        }
+
         let mut extensions = span.extensions_mut();
-        extensions.insert(SpanTimingExtension::new());
+        match extensions.get_mut::<SpanTimingExtensions>() {
+            Some(timings) => timings.0.push((self.id, timing)),
+            None => extensions.insert(SpanTimingExtensions(vec![(self.id, timing)])),
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if NOOP {
+            return;
+        }
+
+        let _overhead = self.measure_overhead();
+
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => {
+                return self.error(LayerError::MissingSpan {
+                    callback: "on_record",
+                })
+            }
+        };
+        let naming = has_any_field(span.metadata(), NAME_FIELDS);
+        let outcome = has_any_field(span.metadata(), OUTCOME_FIELDS);
+        if !(self.counters || naming || outcome) || !self.in_scope(span.metadata()) {
+            return;
+        }
+
+        let mut extensions = span.extensions_mut();
+        let timing = match extensions
+            .get_mut::<SpanTimingExtensions>()
+            .and_then(|timings| timings.get_mut(self.id))
+        {
+            Some(timing) => timing,
+            None => {
+                return self.error(LayerError::MissingExtension {
+                    callback: "on_record",
+                })
+            }
+        };
+
+        if self.counters {
+            values.record(&mut CountersVisitor(&mut timing.counters));
+        }
+        if naming {
+            values.record(&mut NameVisitor(&mut timing.name));
+        }
+        if outcome {
+            values.record(&mut OutcomeVisitor(&mut timing.outcome));
+        }
     }
 
     fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
-        let span = ctx.span(id).expect("on_enter: failed to get span");
+        if NOOP || crate::parallel::is_propagated(id) {
+            return;
+        }
+
+        let _overhead = self.measure_overhead();
+
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => {
+                return self.error(LayerError::MissingSpan {
+                    callback: "on_enter",
+                })
+            }
+        };
+        if !self.in_scope(span.metadata()) {
+            return;
+        }
+
         let mut extensions = span.extensions_mut();
-        let mut timing = extensions
-            .get_mut::<SpanTimingExtension>()
-            .expect("on_enter: failed to get SpanTimingExtension");
-        debug_assert!(timing.start.is_none());
+        let timing = match extensions
+            .get_mut::<SpanTimingExtensions>()
+            .and_then(|timings| timings.get_mut(self.id))
+        {
+            Some(timing) => timing,
+            None => {
+                return self.error(LayerError::MissingExtension {
+                    callback: "on_enter",
+                })
+            }
+        };
+
+        if timing.start.is_some() {
+            return self.error(LayerError::AlreadyEntered);
+        }
+
+        #[cfg(feature = "tokio")]
+        if self.tasks && timing.task.is_none() {
+            // tokio does not expose the numeric value of task ids
+            timing.task = tokio::task::try_id().and_then(|id| id.to_string().parse().ok());
+        }
+
+        if self.threads && timing.thread.is_none() {
+            let thread = std::thread::current();
+            timing.thread = Some(match thread.name() {
+                Some(name) => name.into(),
+                None => format!("{:?}", thread.id()),
+            });
+        }
+
+        if self.timeline && timing.first_enter.is_none() {
+            timing.first_enter = Some(Instant::now());
+        }
+        #[cfg(all(target_os = "linux", feature = "perf"))]
+        if self.hardware {
+            timing.hardware_start = crate::hardware::read();
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(ref energy) = self.energy {
+            timing.energy_start = energy.sample();
+        }
+
+        #[cfg(target_os = "linux")]
+        if self.rss {
+            let rss = crate::memory::resident_set_size().unwrap_or(0);
+            timing.max_rss = std::cmp::max(timing.max_rss, rss);
+        }
+
         timing.start = Some(self.clock.start());
     }
 
     fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
-        let span = ctx.span(id).expect("on_exit: failed to get span");
-        let mut extensions = span.extensions_mut();
-        let mut timing = extensions
-            .get_mut::<SpanTimingExtension>()
-            .expect("on_exit: failed to get SpanTimingExtension");
+        if NOOP || crate::parallel::is_propagated(id) {
+            return;
+        }
 
         let end = self.clock.end();
-        timing.elapsed += self.clock.delta(
-            timing.start.expect("on_exit: failed to get start time"),
-            end,
-        );
-        timing.start = None;
+        #[cfg(all(target_os = "linux", feature = "perf"))]
+        let hardware_end = if self.hardware {
+            crate::hardware::read()
+        } else {
+            None
+        };
+        #[cfg(target_os = "linux")]
+        let energy_end = self.energy.as_ref().and_then(|energy| energy.sample());
+        #[cfg(target_os = "linux")]
+        let rss = if self.rss {
+            crate::memory::resident_set_size().unwrap_or(0)
+        } else {
+            0
+        };
+        let _overhead = self.measure_overhead();
+
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => {
+                return self.error(LayerError::MissingSpan {
+                    callback: "on_exit",
+                })
+            }
+        };
+        if !self.in_scope(span.metadata()) {
+            return;
+        }
+
+        let mut extensions = span.extensions_mut();
+        let timing = match extensions
+            .get_mut::<SpanTimingExtensions>()
+            .and_then(|timings| timings.get_mut(self.id))
+        {
+            Some(timing) => timing,
+            None => {
+                return self.error(LayerError::MissingExtension {
+                    callback: "on_exit",
+                })
+            }
+        };
+
+        // the guard of spans entered in a function which panicked is dropped
+        // while unwinding
+        if std::thread::panicking() {
+            timing.panicked = true;
+        }
+
+        match timing.start.take() {
+            Some(start) => {
+                let elapsed = self
+                    .clock
+                    .delta(start, end)
+                    .saturating_sub(self.compensation);
+                timing.elapsed += elapsed;
+                timing.polls += 1;
+                timing.max_poll = std::cmp::max(timing.max_poll, elapsed);
+
+                #[cfg(all(target_os = "linux", feature = "perf"))]
+                if let (Some(start), Some(end)) = (timing.hardware_start.take(), hardware_end) {
+                    timing.hardware += end.since(&start);
+                }
+
+                #[cfg(target_os = "linux")]
+                if let (Some(energy), Some(start), Some(end)) =
+                    (&self.energy, timing.energy_start.take(), energy_end)
+                {
+                    timing.energy += energy.energy(&start, &end);
+                }
+
+                #[cfg(target_os = "linux")]
+                if self.rss {
+                    timing.max_rss = std::cmp::max(timing.max_rss, rss);
+                }
+            }
+            None => self.error(LayerError::NotEntered),
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let error = *metadata.level() == Level::ERROR;
+        if NOOP || !(self.events || error) {
+            return;
+        }
+
+        let _overhead = self.measure_overhead();
+
+        let parent = ctx
+            .event_span(event)
+            .and_then(|span| span.scope().find(|span| self.in_scope(span.metadata())));
+
+        if error {
+            if let Some(ref parent) = parent {
+                let mut extensions = parent.extensions_mut();
+                if let Some(timing) = extensions
+                    .get_mut::<SpanTimingExtensions>()
+                    .and_then(|timings| timings.get_mut(self.id))
+                {
+                    timing.errors += 1;
+                }
+            }
+        }
+
+        // events without an explicit name are called `event <file>:<line>`
+        if !self.events || metadata.name().starts_with("event ") || !self.in_scope(metadata) {
+            return;
+        }
+        let parent_name = parent
+            .as_ref()
+            .and_then(|parent| self.name_override(parent));
+        let depth = parent.as_ref().map_or(0, |parent| {
+            parent
+                .scope()
+                .filter(|span| self.in_scope(span.metadata()))
+                .count()
+        });
+
+        let mut graph = self.timings.write();
+        let parent = parent.map(|parent| {
+            self.find_or_create(&mut graph, parent.metadata(), parent_name.as_deref(), None)
+        });
+        let current = self.find_or_create(&mut graph, metadata, None, parent);
+
+        let invocation = Invocation {
+            depth: depth + 1,
+            ..Default::default()
+        };
+        if graph.record_invocation(current, &invocation) {
+            if let Some(parent) = parent {
+                graph.increase_call_count(parent, current);
+            }
+        }
+
+        graph.enforce_size_limit();
+    }
+
+    fn on_follows_from(&self, id: &Id, follows: &Id, ctx: Context<'_, S>) {
+        if NOOP {
+            return;
+        }
+
+        let _overhead = self.measure_overhead();
+
+        // the cause span may already be closed, in which case we don't
+        // know its name anymore and can not record the relationship
+        let (span, cause) = match (ctx.span(id), ctx.span(follows)) {
+            (Some(span), Some(cause)) => (span, cause),
+            _ => return,
+        };
+        if !self.in_scope(span.metadata()) || !self.in_scope(cause.metadata()) {
+            return;
+        }
+
+        let cause_name = self.name_override(&cause);
+        let effect_name = self.name_override(&span);
+
+        let mut graph = self.timings.write();
+        let cause = self.find_or_create(&mut graph, cause.metadata(), cause_name.as_deref(), None);
+        let effect = self.find_or_create(&mut graph, span.metadata(), effect_name.as_deref(), None);
+        graph.increase_follows_from_count(cause, effect);
     }
 
     fn on_close(&self, id: Id, ctx: Context<'_, S>) {
-        let span = ctx.span(&id).expect("on_close: failed to get span");
+        if NOOP {
+            return;
+        }
+
+        let _overhead = self.measure_overhead();
+
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => {
+                return self.error(LayerError::MissingSpan {
+                    callback: "on_close",
+                })
+            }
+        };
+        if !self.in_scope(span.metadata()) {
+            return;
+        }
+
         let extensions = span.extensions();
-        let timing = extensions
-            .get::<SpanTimingExtension>()
-            .expect("on_close: failed to get SpanTimingExtension");
-        debug_assert!(timing.start.is_none());
+        let timing = match extensions
+            .get::<SpanTimingExtensions>()
+            .and_then(|timings| timings.get(self.id))
+        {
+            Some(timing) => timing,
+            None => {
+                return self.error(LayerError::MissingExtension {
+                    callback: "on_close",
+                })
+            }
+        };
 
-        let mut graph = self.timings.lock(); // .expect("poisoned lock");
+        if timing.start.is_some() {
+            self.error(LayerError::ClosedWhileEntered);
+        }
 
-        let full_name = |span: &SpanRef<'_, S>| {
-            let mut name = if let Some(path) = span.metadata().module_path() {
-                path.to_string()
-            } else {
-                span.metadata().target().to_string()
-            };
-            name += "::";
+        let parent = span
+            .scope()
+            .skip(1)
+            .find(|parent| self.in_scope(parent.metadata()));
 
-            if span.name().contains(' ') {
-                name += "{";
-                name += span.name();
-                name += "}";
-            } else {
-                name += span.name();
+        // memory used by children also counts as used by the parent, even if
+        // it was released before the parent was sampled again
+        #[cfg(target_os = "linux")]
+        if self.rss {
+            if let Some(ref parent) = parent {
+                let mut extensions = parent.extensions_mut();
+                if let Some(parent) = extensions
+                    .get_mut::<SpanTimingExtensions>()
+                    .and_then(|timings| timings.get_mut(self.id))
+                {
+                    parent.max_rss = std::cmp::max(parent.max_rss, timing.max_rss);
+                }
             }
+        }
 
-            return name;
-        };
+        let parent_name = parent
+            .as_ref()
+            .and_then(|parent| self.name_override(parent));
+
+        let mut graph = self.timings.write();
+
+        if let Some(ref overhead) = self.overhead {
+            let elapsed = overhead.elapsed.swap(0, Ordering::Relaxed);
+            let calls = overhead.calls.swap(0, Ordering::Relaxed);
+            let span = graph.find_or_create(OVERHEAD_SPAN);
+            graph.add_overhead(span, Duration::from_nanos(elapsed), calls);
+        }
 
         // create the parent first to ensure it has a lower node id than the
         // child. This makes the final output looks a bit better
-        let parent = span
-            .parent()
-            .map(|id| graph.find_or_create(&full_name(&id)));
+        let parent = parent.map(|parent| {
+            self.find_or_create(&mut graph, parent.metadata(), parent_name.as_deref(), None)
+        });
 
-        let current = graph.find_or_create(&full_name(&span));
-        graph.increase_timing(current, timing.elapsed);
+        let current =
+            self.find_or_create(&mut graph, span.metadata(), timing.name.as_deref(), parent);
+        let lifetime = self.clock.delta(timing.created, self.clock.end());
+        let invocation = Invocation {
+            elapsed: timing.elapsed,
+            lifetime: lifetime,
+            depth: span
+                .scope()
+                .filter(|span| self.in_scope(span.metadata()))
+                .count(),
+            counters: timing.counters.clone(),
+            task: timing.task,
+            thread: timing.thread.clone(),
+            hardware: timing.hardware,
+            energy: timing.energy,
+            max_rss: timing.max_rss,
+            polls: timing.polls,
+            max_poll: timing.max_poll,
+            errors: timing.errors,
+            outcome: timing.outcome,
+            panicked: timing.panicked || std::thread::panicking(),
+        };
+        if !graph.record_invocation(current, &invocation) {
+            // this was a warm-up invocation
+            graph.enforce_size_limit();
+            return;
+        }
+
+        if let Some(start) = timing.first_enter {
+            graph.add_to_timeline(current, start);
+        }
 
         if let Some(parent) = parent {
             graph.increase_call_count(parent, current);
         }
+
+        // only warn the first time the budget is exceeded
+        let elapsed = graph.span(current).elapsed;
+        let exceeded = graph
+            .budget(current)
+            .filter(|&budget| elapsed > budget && elapsed - timing.elapsed <= budget)
+            .map(|budget| (graph.span(current).name.clone(), budget));
+
+        let slow = self
+            .slow_thresholds
+            .get(&graph.span(current).name)
+            .or(self.slow_threshold.as_ref())
+            .filter(|&&threshold| timing.elapsed > threshold)
+            .map(|&threshold| (graph.span(current).name.clone(), threshold));
+
+        #[cfg(feature = "metrics")]
+        let published = if self.metrics {
+            Some(graph.span(current).name.clone())
+        } else {
+            None
+        };
+
+        graph.enforce_size_limit();
+
+        // release the lock before emitting the event, since handling it could
+        // create and close other spans
+        drop(graph);
+        if let Some((name, budget)) = exceeded {
+            tracing::warn!(
+                span = name.as_str(),
+                budget = ?budget,
+                elapsed = ?elapsed,
+                "span exceeded its time budget"
+            );
+        }
+        if let Some((name, threshold)) = slow {
+            tracing::warn!(
+                span = name.as_str(),
+                threshold = ?threshold,
+                elapsed = ?timing.elapsed,
+                "slow span invocation"
+            );
+        }
+
+        #[cfg(feature = "metrics")]
+        if let Some(name) = published {
+            crate::publish::publish(&name, timing.elapsed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flush_with_last_clone() {
+        let path = std::env::temp_dir().join(format!(
+            "tracing-timing-graph-flush-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let layer = SpanTimingLayer::new().with_output(path.clone(), Format::Json);
+        drop(layer.clone());
+        assert!(!path.exists());
+
+        let view = layer.view();
+        drop(layer);
+        assert!(path.exists());
+        drop(view);
+
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -13,7 +13,124 @@
 #![allow(clippy::needless_return, clippy::redundant_field_names, clippy::new_without_default)]
 
 mod graph;
-pub use self::graph::{SpanGraph, SpanIndex, SpanTiming};
+pub use self::graph::{CallPath, Calls, FollowsFrom, SpanGraph, SpanIndex, SpanTiming};
+
+mod report;
+pub use self::report::{CallReport, ColorMode, Report, ReportOptions, SpanReport, TableStyle};
+
+mod assert;
+
+mod compare;
+pub use self::compare::{RegressionError, SpanComparison};
+
+mod import;
+
+mod merge;
+
+mod footprint;
+
+mod evict;
+pub use self::evict::{EvictionPolicy, EvictionStats};
+
+mod resume;
+
+mod normalize;
+
+#[cfg(feature = "binary")]
+mod snapshot;
+
+mod rank;
+pub use self::rank::RankStatistics;
+
+mod runs;
+pub use self::runs::RunStatistics;
+
+mod slo;
+pub use self::slo::{LatencyObjective, SloViolation};
+
+mod hardware;
+pub use self::hardware::HardwareCounters;
+
+#[cfg(target_os = "linux")]
+mod energy;
+
+#[cfg(target_os = "linux")]
+mod memory;
+
+mod series;
+pub use self::series::{Bucket, TimeSeries};
+
+mod timeline;
+pub use self::timeline::TimelineEntry;
+
+mod export;
+
+mod svg;
+
+mod tree;
+
+mod pareto;
+
+#[cfg(feature = "inferno")]
+mod flamegraph;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "arrow")]
+mod arrow;
+
+mod visit;
+pub use self::visit::VisitedSpan;
+
+mod filter;
+pub use self::filter::SpanFilter;
+
+mod format;
+pub use self::format::Format;
+
+mod error;
+pub use self::error::{FilterError, ImportError, LayerError};
+
+mod names;
 
 mod layer;
 pub use self::layer::SpanTimingLayer;
+
+mod region;
+
+mod external;
+pub use self::external::DeferredDuration;
+
+mod parallel;
+pub use self::parallel::ParentSpan;
+
+mod view;
+pub use self::view::GraphView;
+
+mod guard;
+pub use self::guard::DumpGuard;
+
+#[cfg(all(unix, feature = "signal"))]
+mod signal;
+
+mod init;
+pub use self::init::{init, init_with, try_init_with};
+
+mod profile;
+pub use self::profile::profile;
+
+#[cfg(feature = "metrics")]
+mod publish;
+
+#[cfg(feature = "criterion")]
+mod measurement;
+#[cfg(feature = "criterion")]
+pub use self::measurement::SpanMeasurement;
+
+/// Re-export of the most commonly used types and traits
+pub mod prelude {
+    pub use crate::{assert_called, assert_edge};
+    pub use crate::{Format, GraphView, ReportOptions, SpanGraph, SpanTimingLayer};
+    pub use tracing_subscriber::layer::SubscriberExt as _;
+}
@@ -0,0 +1,123 @@
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::Throughput;
+
+use std::time::Duration;
+
+use crate::GraphView;
+
+/// [`criterion`](https://docs.rs/criterion/0.3) measurement using the time
+/// spent inside a given span instead of the wall-clock time of the whole
+/// benchmark.
+///
+/// The corresponding `SpanTimingLayer` must be installed as the default
+/// subscriber while the benchmarks are running.
+///
+/// ```no_run
+/// use criterion::Criterion;
+/// use tracing_subscriber::{prelude::*, registry::Registry};
+/// use tracing_timing_graph::{SpanMeasurement, SpanTimingLayer};
+///
+/// let layer = SpanTimingLayer::new();
+/// let measurement = SpanMeasurement::new(layer.view(), "my_crate::inner");
+/// tracing::subscriber::set_global_default(Registry::default().with(layer)).unwrap();
+///
+/// let mut criterion = Criterion::default().with_measurement(measurement);
+/// ```
+pub struct SpanMeasurement {
+    graph: GraphView,
+    span: String,
+}
+
+impl SpanMeasurement {
+    /// Create a new measurement of the time spent inside the span with the
+    /// given full `span` name, using the data accumulated in `graph`.
+    pub fn new(graph: GraphView, span: &str) -> SpanMeasurement {
+        SpanMeasurement {
+            graph: graph,
+            span: span.into(),
+        }
+    }
+
+    /// Get the total time spent inside the span until now
+    fn elapsed(&self) -> Duration {
+        let graph = self.graph.read();
+        match graph.find(&self.span) {
+            Some(span) => graph.span(span).elapsed,
+            None => Duration::new(0, 0),
+        }
+    }
+}
+
+impl Measurement for SpanMeasurement {
+    type Intermediate = Duration;
+    type Value = Duration;
+
+    fn start(&self) -> Duration {
+        self.elapsed()
+    }
+
+    fn end(&self, start: Duration) -> Duration {
+        self.elapsed() - start
+    }
+
+    fn add(&self, v1: &Duration, v2: &Duration) -> Duration {
+        *v1 + *v2
+    }
+
+    fn zero(&self) -> Duration {
+        Duration::new(0, 0)
+    }
+
+    fn to_f64(&self, value: &Duration) -> f64 {
+        value.as_nanos() as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &NanosecondsFormatter
+    }
+}
+
+/// Format values given in nanoseconds
+struct NanosecondsFormatter;
+
+impl ValueFormatter for NanosecondsFormatter {
+    fn scale_values(&self, typical: f64, values: &mut [f64]) -> &'static str {
+        let (factor, unit) = if typical < 1e3 {
+            (1.0, "ns")
+        } else if typical < 1e6 {
+            (1e-3, "µs")
+        } else if typical < 1e9 {
+            (1e-6, "ms")
+        } else {
+            (1e-9, "s")
+        };
+
+        for value in values {
+            *value *= factor;
+        }
+
+        return unit;
+    }
+
+    fn scale_throughputs(
+        &self,
+        _typical: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        let (count, unit) = match *throughput {
+            Throughput::Bytes(bytes) => (bytes as f64, "B/s"),
+            Throughput::Elements(elements) => (elements as f64, "elem/s"),
+        };
+
+        for value in values {
+            *value = count * 1e9 / *value;
+        }
+
+        return unit;
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "ns"
+    }
+}
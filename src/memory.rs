@@ -0,0 +1,21 @@
+use std::io;
+
+/// Get the current resident set size (RSS) of this process, in bytes
+pub(crate) fn resident_set_size() -> io::Result<u64> {
+    let status = std::fs::read_to_string("/proc/self/status")?;
+    for line in status.lines() {
+        if let Some(value) = line.strip_prefix("VmRSS:") {
+            let kilobytes = value
+                .trim()
+                .strip_suffix("kB")
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid VmRSS"))?;
+            return Ok(1024 * kilobytes);
+        }
+    }
+
+    return Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "VmRSS is missing from /proc/self/status",
+    ));
+}
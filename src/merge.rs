@@ -0,0 +1,61 @@
+use std::path::Path;
+
+use petgraph::graph::NodeIndex;
+
+use crate::{ImportError, SpanGraph};
+
+impl SpanGraph {
+    /// Add all the data from `other` to this graph. Spans are matched by
+    /// name, and the elapsed time, number of calls and other statistics of
+    /// matching spans are combined. Spans only present in `other` are added
+    /// to this graph. Calls and `follows_from` relationships between spans
+    /// are summed.
+    ///
+    /// This can be used to combine graphs recorded in different processes,
+    /// for example by multiple workers of a server or after a fork/exec.
+    /// Metadata from `other` is only added if this graph does not already
+    /// contain the same key, and the timeline of `other` is not merged. The
+    /// recent durations (see `SpanTiming::recent`) of `other` are considered
+    /// older than the ones in this graph.
+    pub fn merge(&mut self, other: &SpanGraph) {
+        let mut node_of = Vec::with_capacity(other.graph.node_count());
+        for span in other.spans() {
+            let node = NodeIndex::from(self.find_or_create(&span.name));
+            self.graph[node].merge(span, self.settings.recent_capacity);
+            node_of.push(node);
+        }
+
+        for edge in other.graph.raw_edges() {
+            let caller = node_of[edge.source().index()];
+            let callee = node_of[edge.target().index()];
+            self.add_calls(caller, callee, edge.weight);
+        }
+
+        for (&(cause, effect), &count) in &other.follows {
+            let cause = node_of[cause].index();
+            let effect = node_of[effect].index();
+            *self.follows.entry((cause, effect)).or_insert(0) += count;
+        }
+
+        for (key, value) in &other.metadata {
+            if !self.metadata.contains_key(key) {
+                self.metadata.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    /// Load all the graphs saved as JSON in the files at `paths` (see
+    /// `SpanGraph::read_json_file`), and merge them into a single graph with
+    /// `SpanGraph::merge`.
+    pub fn merge_json_files<P, I>(paths: I) -> Result<SpanGraph, ImportError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = P>,
+    {
+        let mut graph = SpanGraph::new();
+        for path in paths {
+            graph.merge(&SpanGraph::read_json_file(path)?);
+        }
+        return Ok(graph);
+    }
+}
@@ -0,0 +1,60 @@
+/// Normalize a span name generated from a Rust path, removing generic type
+/// parameters (`process::<u32>` or `Parser<R>::parse`), closure fragments
+/// (`{{closure}}` or `{closure#0}`) and symbol hash suffixes
+/// (`::h0123456789abcdef`), so that all the monomorphized copies of a
+/// function share the same name.
+pub(crate) fn normalize_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    let mut depth = 0_usize;
+    let mut previous = None;
+    for c in name.chars() {
+        match c {
+            // `->` in function pointer types is not a closing bracket
+            '>' if depth > 0 && previous != Some('-') => depth -= 1,
+            '<' => depth += 1,
+            _ if depth == 0 => normalized.push(c),
+            _ => {}
+        }
+        previous = Some(c);
+    }
+
+    if depth != 0 {
+        // unbalanced brackets, this is not a Rust path
+        return name.into();
+    }
+
+    let segments = normalized
+        .split("::")
+        .filter(|segment| !segment.is_empty() && !is_closure(segment) && !is_hash(segment))
+        .collect::<Vec<_>>();
+
+    if segments.is_empty() {
+        return name.into();
+    }
+    return segments.join("::");
+}
+
+/// Check if `segment` is a closure path segment, `{{closure}}` in the legacy
+/// symbol format or `{closure#N}` in the v0 format
+fn is_closure(segment: &str) -> bool {
+    if segment == "{{closure}}" {
+        return true;
+    }
+
+    match segment
+        .strip_prefix("{closure#")
+        .and_then(|rest| rest.strip_suffix('}'))
+    {
+        Some(index) => !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Check if `segment` is the hash added at the end of legacy symbol names,
+/// i.e. `h` followed by 16 hexadecimal digits
+fn is_hash(segment: &str) -> bool {
+    match segment.strip_prefix('h') {
+        Some(hash) => hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
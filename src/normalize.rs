@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use crate::{LatencyObjective, SpanGraph, SpanIndex, SpanTiming};
+
+impl SpanGraph {
+    /// Get a copy of this graph where all durations are expressed as a
+    /// fraction of the total elapsed time (see `SpanGraph::total_elapsed`),
+    /// one second corresponding to 100% of the total time. A span taking
+    /// 250ms in the normalized graph was running during a quarter of the
+    /// total time.
+    ///
+    /// This makes graphs recorded on machines of different speeds directly
+    /// comparable, for example with `SpanGraph::compare`. Call counts and
+    /// other non-time data are not modified.
+    ///
+    /// If the total elapsed time is zero, this returns an unmodified copy.
+    pub fn normalized(&self) -> SpanGraph {
+        self.scaled(self.total_elapsed())
+    }
+
+    /// Get a copy of this graph where all durations are expressed as a
+    /// fraction of the elapsed time of `root`, one second corresponding to
+    /// 100% of the time spent in `root`. See `SpanGraph::normalized`.
+    pub fn normalized_to(&self, root: SpanIndex) -> SpanGraph {
+        self.scaled(self.span(root).elapsed)
+    }
+
+    /// Get a copy of this graph where all durations are divided by
+    /// `reference`, expressed in seconds
+    fn scaled(&self, reference: Duration) -> SpanGraph {
+        let mut scaled = self.clone();
+        if reference.as_nanos() == 0 {
+            return scaled;
+        }
+
+        let factor = 1.0 / reference.as_secs_f64();
+        for span in scaled.graph.node_weights_mut() {
+            scale_span(span, factor);
+        }
+
+        if let Some(ref mut timeline) = scaled.timeline {
+            timeline.scale(factor);
+        }
+
+        for budget in scaled.settings.budgets.values_mut() {
+            *budget = budget.mul_f64(factor);
+        }
+
+        for objectives in scaled.settings.objectives.values_mut() {
+            for objective in objectives {
+                *objective = match *objective {
+                    LatencyObjective::Mean(threshold) => {
+                        LatencyObjective::Mean(threshold.mul_f64(factor))
+                    }
+                    LatencyObjective::Max(threshold) => {
+                        LatencyObjective::Max(threshold.mul_f64(factor))
+                    }
+                    LatencyObjective::Percentile(percentile, threshold) => {
+                        LatencyObjective::Percentile(percentile, threshold.mul_f64(factor))
+                    }
+                };
+            }
+        }
+
+        return scaled;
+    }
+}
+
+/// Multiply all the durations in `span` by `factor`
+fn scale_span(span: &mut SpanTiming, factor: f64) {
+    span.elapsed = span.elapsed.mul_f64(factor);
+    span.max = span.max.mul_f64(factor);
+    span.lifetime = span.lifetime.mul_f64(factor);
+    span.idle = span.idle.mul_f64(factor);
+    span.max_poll = span.max_poll.mul_f64(factor);
+    for duration in &mut span.durations {
+        *duration = duration.mul_f64(factor);
+    }
+    for duration in &mut span.recent {
+        *duration = duration.mul_f64(factor);
+    }
+    for elapsed in span.tasks.values_mut() {
+        *elapsed = elapsed.mul_f64(factor);
+    }
+    for elapsed in span.threads.values_mut() {
+        *elapsed = elapsed.mul_f64(factor);
+    }
+    if let Some(ref mut series) = span.series {
+        series.scale(factor);
+    }
+
+    if let Some(ref mut moving_average) = span.moving_average {
+        moving_average.scale(factor);
+    }
+
+    span.mean *= factor;
+    span.m2 *= factor * factor;
+    // the rolling window only makes sense for live data
+    span.window = None;
+}
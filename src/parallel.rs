@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+
+use tracing::span::Id;
+use tracing::Span;
+
+thread_local! {
+    /// Spans entered on the current thread with `ParentSpan::in_scope`
+    static PROPAGATED: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Check if the span with the given `id` was entered on the current thread
+/// by `ParentSpan::in_scope`, in which case the time spent inside it should
+/// not be recorded.
+pub(crate) fn is_propagated(id: &Id) -> bool {
+    PROPAGATED.with(|propagated| propagated.borrow().contains(id))
+}
+
+/// Handle to a span, used to attach spans created on other threads to this
+/// span instead of making them root spans.
+///
+/// Spans are connected to their caller through the span entered on the
+/// current thread when they are created. Work executed on the worker threads
+/// of a thread pool such as [rayon](https://docs.rs/rayon) runs outside of the
+/// caller span, and the corresponding spans would appear as roots in the
+/// graph. Capturing the caller with `ParentSpan::current` and running the
+/// work inside `ParentSpan::in_scope` fixes this.
+///
+/// ```
+/// use tracing_timing_graph::ParentSpan;
+///
+/// #[tracing::instrument]
+/// fn work(item: usize) {
+///     // ...
+/// }
+///
+/// #[tracing::instrument]
+/// fn run(items: &[usize]) {
+///     let parent = ParentSpan::current();
+///     std::thread::scope(|scope| {
+///         for &item in items {
+///             // this works the same with `rayon::scope` or `par_iter`
+///             let parent = &parent;
+///             scope.spawn(move || parent.in_scope(|| work(item)));
+///         }
+///     });
+/// }
+/// ```
+///
+/// The time spent inside `in_scope` is not added to the parent span, since
+/// it already accounts for the time spent waiting on the worker threads.
+#[derive(Clone, Debug)]
+pub struct ParentSpan {
+    span: Span,
+}
+
+impl ParentSpan {
+    /// Capture the span currently entered on this thread
+    pub fn current() -> ParentSpan {
+        ParentSpan {
+            span: Span::current(),
+        }
+    }
+
+    /// Create a `ParentSpan` from an existing `span`
+    pub fn new(span: Span) -> ParentSpan {
+        ParentSpan { span: span }
+    }
+
+    /// Execute `function` with the parent span entered on the current
+    /// thread, without recording the corresponding time in the parent span.
+    /// Spans created inside `function` are attached to the parent span.
+    pub fn in_scope<F: FnOnce() -> R, R>(&self, function: F) -> R {
+        let id = match self.span.id() {
+            Some(id) => id,
+            None => return function(),
+        };
+
+        // the guard must be dropped after exiting the span, including when
+        // unwinding from a panic in `function`
+        let _propagated = Propagated::new(id);
+        return self.span.in_scope(function);
+    }
+}
+
+/// Guard marking a span as entered by `ParentSpan::in_scope` on the current
+/// thread, until it is dropped
+struct Propagated {
+    id: Id,
+}
+
+impl Propagated {
+    fn new(id: Id) -> Propagated {
+        PROPAGATED.with(|propagated| propagated.borrow_mut().push(id.clone()));
+        Propagated { id: id }
+    }
+}
+
+impl Drop for Propagated {
+    fn drop(&mut self) {
+        PROPAGATED.with(|propagated| {
+            let mut propagated = propagated.borrow_mut();
+            if let Some(position) = propagated.iter().rposition(|other| *other == self.id) {
+                propagated.remove(position);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_in_scope() {
+        let (_, _graph) = crate::profile(|| {
+            let span = tracing::info_span!("parent");
+            let id = span.id().unwrap();
+            let parent = ParentSpan::new(span);
+
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                parent.in_scope(|| {
+                    assert!(is_propagated(&id));
+                    panic!("failure in the worker");
+                })
+            }));
+            assert!(result.is_err());
+            assert!(!is_propagated(&id));
+        });
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use term_table::row::Row;
+use term_table::table_cell::TableCell;
+
+use crate::graph::right_aligned;
+use crate::{ReportOptions, SpanGraph, SpanIndex};
+
+impl SpanGraph {
+    /// Get a table with spans sorted by decreasing self time (see
+    /// `SpanGraph::self_time`), with the cumulative percentage of the total
+    /// self time accounted for by each span and all the spans above it. This
+    /// shows at a glance how many spans are responsible for most of the
+    /// runtime.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_pareto_table(&self) -> String {
+        self.as_pareto_table_with(&ReportOptions::default())
+    }
+
+    /// Get a table with spans sorted by decreasing self time, using the
+    /// given `options`. See `SpanGraph::as_pareto_table`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_pareto_table_with(&self, options: &ReportOptions) -> String {
+        let mut spans = self
+            .graph
+            .node_indices()
+            .filter(|&node| options.includes(&self.graph[node]))
+            .map(|node| (node, self.self_time(node.into())))
+            .collect::<Vec<_>>();
+        spans.sort_by_key(|&(_, self_time)| std::cmp::Reverse(self_time));
+
+        let total = spans
+            .iter()
+            .map(|&(_, self_time)| self_time)
+            .sum::<Duration>()
+            .as_secs_f64();
+
+        let mut table = term_table::Table::new();
+        table.add_row(Row::new(vec![
+            "rank",
+            // pad "span name" to make the table look nicer with short names
+            "span name                                   ",
+            "self time",
+            "percent",
+            "cumulative",
+        ]));
+
+        let mut cumulative = 0.0;
+        for (rank, &(node, self_time)) in spans.iter().enumerate() {
+            let span = SpanIndex::from(node);
+            let fraction = if total > 0.0 {
+                self_time.as_secs_f64() / total
+            } else {
+                0.0
+            };
+            cumulative += fraction;
+
+            table.add_row(Row::new(vec![
+                right_aligned(rank + 1),
+                TableCell::new(options.colorize(&self.span(span).name, fraction)),
+                right_aligned(options.colorize(&format!("{:.2?}", self_time), fraction)),
+                right_aligned(format!("{:.1}%", 100.0 * fraction)),
+                right_aligned(format!("{:.1}%", 100.0 * cumulative)),
+            ]));
+        }
+
+        return options.render_table(table);
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+use crate::{SpanGraph, SpanTimingLayer};
+
+/// Run `function` with a fresh `SpanTimingLayer` installed as the default
+/// subscriber for the current thread, and get back both the return value of
+/// `function` and the corresponding span graph.
+///
+/// This is useful for one-off measurements and in tests, since it does not
+/// require setting up a global subscriber. Spans entered on other threads are
+/// not recorded.
+///
+/// ```
+/// #[tracing::instrument]
+/// fn compute(n: u64) -> u64 {
+///     (0..n).sum()
+/// }
+///
+/// let (sum, graph) = tracing_timing_graph::profile(|| compute(10));
+/// assert_eq!(sum, 45);
+/// # // the layer does not record anything with the `noop` features
+/// # #[cfg(not(any(feature = "noop", all(feature = "release_noop", not(debug_assertions)))))]
+/// assert_eq!(graph.spans().count(), 1);
+/// ```
+pub fn profile<F, T>(function: F) -> (T, SpanGraph)
+where
+    F: FnOnce() -> T,
+{
+    let layer = SpanTimingLayer::new();
+    let graph = Arc::clone(&layer.timings);
+
+    let subscriber = Registry::default().with(layer);
+    let result = tracing::subscriber::with_default(subscriber, function);
+
+    let graph = std::mem::replace(&mut *graph.write(), SpanGraph::new());
+    return (result, graph);
+}
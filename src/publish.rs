@@ -0,0 +1,18 @@
+use std::time::Duration;
+
+/// Name of the counter containing the number of calls to each span
+const CALLS: &str = "tracing_timing_graph.calls";
+/// Name of the histogram containing the duration of each span invocation
+const DURATION: &str = "tracing_timing_graph.duration_seconds";
+/// Name of the counter containing the total time spent in each span, in
+/// nanoseconds
+const ELAPSED: &str = "tracing_timing_graph.elapsed_nanoseconds";
+
+/// Publish the data from a single invocation of the span called `name`
+/// through the `metrics` facade
+pub(crate) fn publish(name: &str, elapsed: Duration) {
+    let labels = [("span", name.to_string())];
+    metrics::counter!(CALLS, &labels).increment(1);
+    metrics::counter!(ELAPSED, &labels).increment(elapsed.as_nanos() as u64);
+    metrics::histogram!(DURATION, &labels).record(elapsed.as_secs_f64());
+}
@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use term_table::row::Row;
+use term_table::table_cell::TableCell;
+
+use crate::graph::right_aligned;
+use crate::{ReportOptions, SpanGraph};
+
+/// Metadata key used to store the rank of a graph, see `SpanGraph::set_rank`
+const RANK_METADATA: &str = "rank";
+
+/// Statistics of the elapsed time of a single span group across the graphs
+/// recorded by multiple ranks (i.e. processes) of a parallel run, such as a
+/// MPI run.
+#[derive(Clone, Debug)]
+pub struct RankStatistics {
+    /// Full span name, used to match spans between ranks
+    pub name: String,
+    /// Number of ranks in which this span was called
+    pub ranks: usize,
+    /// Mean elapsed time across all ranks, ranks in which the span was not
+    /// called counting as zero
+    pub mean: Duration,
+    /// Smallest elapsed time across all ranks
+    pub min: Duration,
+    /// Rank with the smallest elapsed time
+    pub min_rank: usize,
+    /// Largest elapsed time across all ranks
+    pub max: Duration,
+    /// Rank with the largest elapsed time
+    pub max_rank: usize,
+}
+
+impl RankStatistics {
+    /// Get the load imbalance of this span, defined as the ratio of the
+    /// largest elapsed time to the mean elapsed time across ranks. A
+    /// perfectly balanced span has an imbalance of `1.0`, and larger values
+    /// indicate that some ranks spend more time than others in this span.
+    pub fn imbalance(&self) -> f64 {
+        if self.mean == Duration::new(0, 0) {
+            return 1.0;
+        }
+        return self.max.as_secs_f64() / self.mean.as_secs_f64();
+    }
+}
+
+impl SpanGraph {
+    /// Tag this graph with the `rank` of the process which recorded it in a
+    /// parallel run, such as the MPI rank. The rank is stored in the graph
+    /// metadata, and saved in the JSON output.
+    pub fn set_rank(&mut self, rank: usize) {
+        self.set_metadata(RANK_METADATA, &rank.to_string());
+    }
+
+    /// Get the rank of the process which recorded this graph, as set with
+    /// `SpanGraph::set_rank` or `SpanTimingLayer::with_rank`
+    pub fn rank(&self) -> Option<usize> {
+        self.metadata.get(RANK_METADATA)?.parse().ok()
+    }
+
+    /// Compute statistics of the elapsed time of each span across the
+    /// `graphs` recorded by the different ranks of a parallel run, to find
+    /// load imbalance between ranks. Spans are matched by name.
+    ///
+    /// The rank of each graph is taken from `SpanGraph::rank`, falling back
+    /// to the position of the graph in `graphs`. Per-rank graphs saved as
+    /// JSON can be loaded with `SpanGraph::read_json_file`, and combined in a
+    /// single graph with `SpanGraph::merge`.
+    pub fn rank_statistics(graphs: &[SpanGraph]) -> Vec<RankStatistics> {
+        let names = span_names(graphs);
+        let mut statistics = Vec::with_capacity(names.len());
+        for name in names {
+            let mut span = RankStatistics {
+                name: name,
+                ranks: 0,
+                mean: Duration::new(0, 0),
+                min: Duration::new(0, 0),
+                min_rank: 0,
+                max: Duration::new(0, 0),
+                max_rank: 0,
+            };
+
+            let mut total = Duration::new(0, 0);
+            for (i, graph) in graphs.iter().enumerate() {
+                let rank = graph.rank().unwrap_or(i);
+                let elapsed = match graph.find(&span.name) {
+                    Some(node) => {
+                        span.ranks += 1;
+                        graph.span(node).elapsed
+                    }
+                    None => Duration::new(0, 0),
+                };
+
+                total += elapsed;
+                if i == 0 || elapsed < span.min {
+                    span.min = elapsed;
+                    span.min_rank = rank;
+                }
+                if i == 0 || elapsed > span.max {
+                    span.max = elapsed;
+                    span.max_rank = rank;
+                }
+            }
+            span.mean = total / graphs.len() as u32;
+
+            statistics.push(span);
+        }
+
+        return statistics;
+    }
+
+    /// Get a table containing the statistics of each span across the `graphs`
+    /// recorded by the different ranks of a parallel run, see
+    /// `SpanGraph::rank_statistics`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_rank_table(graphs: &[SpanGraph]) -> String {
+        SpanGraph::as_rank_table_with(graphs, &ReportOptions::default())
+    }
+
+    /// Get a table containing the statistics of each span across the `graphs`
+    /// recorded by the different ranks of a parallel run, using the table
+    /// style from `options`. See `SpanGraph::as_rank_table`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_rank_table_with(graphs: &[SpanGraph], options: &ReportOptions) -> String {
+        let mut table = term_table::Table::new();
+
+        table.add_row(Row::new(vec![
+            // pad "span name" to make the table look nicer with short names
+            "span name                                   ",
+            "ranks",
+            "mean",
+            "min (rank)",
+            "max (rank)",
+            "imbalance",
+        ]));
+
+        for span in SpanGraph::rank_statistics(graphs) {
+            table.add_row(Row::new(vec![
+                TableCell::new(&span.name),
+                right_aligned(format!("{}/{}", span.ranks, graphs.len())),
+                right_aligned(format!("{:.2?}", span.mean)),
+                right_aligned(format!("{:.2?} ({})", span.min, span.min_rank)),
+                right_aligned(format!("{:.2?} ({})", span.max, span.max_rank)),
+                right_aligned(format!("{:.2}", span.imbalance())),
+            ]));
+        }
+
+        return options.render_table(table);
+    }
+}
+
+/// Get the names of all spans in `graphs`, in the order of
+/// `SpanGraph::as_table` for the first graph containing each span
+pub(crate) fn span_names(graphs: &[SpanGraph]) -> Vec<String> {
+    let mut names = Vec::new();
+    for graph in graphs {
+        for node in graph.sorted_nodes() {
+            let name = &graph.graph[node].name;
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+    return names;
+}
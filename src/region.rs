@@ -0,0 +1,71 @@
+use crate::{SpanGraph, SpanTimingLayer};
+
+/// Data recorded in the regions of a `SpanTimingLayer` which are already
+/// finished, see `SpanTimingLayer::region`
+#[derive(Default)]
+pub(crate) struct Regions {
+    /// name of the region currently being recorded
+    current: Option<String>,
+    /// finished regions and the corresponding data, in order
+    finished: Vec<(String, SpanGraph)>,
+}
+
+/// Name of the region used for data recorded before the first call to
+/// `SpanTimingLayer::region`
+const DEFAULT_REGION: &str = "default";
+
+impl SpanTimingLayer {
+    /// Start a new profiling region (or phase) called `name`, finishing the
+    /// current one. All the data recorded until the next call to this
+    /// function will be part of this region, allowing to analyze different
+    /// phases of a single run separately.
+    ///
+    /// Spans are attributed to the region active when they are closed. Data
+    /// recorded before the first region is started is part of a region named
+    /// `"default"`. Since clones of a layer share the same data, a clone can
+    /// be kept around to switch regions after the layer is installed.
+    ///
+    /// ```no_run
+    /// # use tracing_subscriber::layer::SubscriberExt;
+    /// # use tracing_timing_graph::SpanTimingLayer;
+    /// let layer = SpanTimingLayer::new();
+    /// let handle = layer.clone();
+    /// let subscriber = tracing_subscriber::registry().with(layer);
+    /// tracing::subscriber::set_global_default(subscriber).unwrap();
+    ///
+    /// handle.region("warm-up");
+    /// // ...
+    /// handle.region("steady-state");
+    /// // ...
+    ///
+    /// for (name, graph) in handle.regions() {
+    ///     println!("{}\n{}", name, graph.as_table());
+    /// }
+    /// ```
+    pub fn region(&self, name: &str) {
+        let mut regions = self.regions.lock();
+        let data = self.timings.write().take();
+
+        let previous = regions
+            .current
+            .replace(name.into())
+            .unwrap_or_else(|| DEFAULT_REGION.into());
+        if previous != DEFAULT_REGION || data.span_count() != 0 {
+            regions.finished.push((previous, data));
+        }
+    }
+
+    /// Get the data recorded in all regions (see `SpanTimingLayer::region`),
+    /// in the order they were started. The data for the current region is
+    /// a snapshot of the span graph.
+    pub fn regions(&self) -> Vec<(String, SpanGraph)> {
+        let regions = self.regions.lock();
+        let mut all = regions.finished.clone();
+        let current = regions
+            .current
+            .clone()
+            .unwrap_or_else(|| DEFAULT_REGION.into());
+        all.push((current, self.timings.read().clone()));
+        return all;
+    }
+}
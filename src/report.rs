@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use tracing::Level;
+
+use crate::{SpanFilter, SpanGraph, SpanTiming};
+
+/// When to use ANSI colors in reports, see `ReportOptions::with_colors`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Use colors if the standard output is a terminal, and the `NO_COLOR`
+    /// environment variable is not set. This is the default.
+    #[default]
+    Auto,
+    /// Always use colors
+    Always,
+    /// Never use colors
+    Never,
+}
+
+/// Style of the borders of tables in reports, see
+/// `ReportOptions::with_table_style`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TableStyle {
+    /// Borders drawn with double box-drawing characters
+    #[default]
+    Extended,
+    /// Borders drawn with ASCII `+`, `-` and `|` characters
+    Simple,
+    /// No borders, columns are only separated by spaces
+    Blank,
+    /// Markdown-like table, with a separator line only after the header
+    Markdown,
+}
+
+/// Options controlling the content of reports generated from a `SpanGraph`,
+/// such as `SpanGraph::as_table_with`.
+#[derive(Clone, Debug, Default)]
+pub struct ReportOptions {
+    pub(crate) outlier_factor: Option<f64>,
+    pub(crate) per_root: bool,
+    pub(crate) max_level: Option<Level>,
+    pub(crate) busy_idle: bool,
+    pub(crate) sort_by_idle: bool,
+    pub(crate) colors: ColorMode,
+    pub(crate) table_style: TableStyle,
+    pub(crate) no_padding: bool,
+    pub(crate) filter: Option<SpanFilter>,
+}
+
+impl ReportOptions {
+    /// Create the default set of report options
+    pub fn new() -> ReportOptions {
+        ReportOptions::default()
+    }
+
+    /// Mark spans with a maximal invocation duration larger than `factor`
+    /// times their mean duration as outliers (see `SpanGraph::outliers`).
+    pub fn with_outliers(mut self, factor: f64) -> ReportOptions {
+        self.outlier_factor = Some(factor);
+        self
+    }
+
+    /// Only include spans with a verbosity level of at most `level` in the
+    /// report, for example excluding fine-grained `DEBUG` and `TRACE` spans
+    /// from a summary with `Level::INFO`. The excluded spans are still
+    /// recorded in the graph.
+    pub fn with_max_level(mut self, level: Level) -> ReportOptions {
+        self.max_level = Some(level);
+        self
+    }
+
+    /// Only include spans selected by `filter` in the report, for example
+    /// `"name ~ \"compute\" && elapsed > 10ms"`. See `SpanFilter` for the
+    /// syntax of filter expressions.
+    pub fn with_filter(mut self, filter: SpanFilter) -> ReportOptions {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Include the busy and idle time of spans in the report (see
+    /// `SpanTiming::idle`). This is mainly useful for spans attached to
+    /// futures, to separate the time spent running from the time spent
+    /// waiting.
+    pub fn with_busy_idle(mut self) -> ReportOptions {
+        self.busy_idle = true;
+        self
+    }
+
+    /// Sort spans by decreasing idle time instead of sorting callers before
+    /// callees, to find futures spending most of their life waiting. This
+    /// also includes the busy and idle time in the report.
+    pub fn with_sort_by_idle(mut self) -> ReportOptions {
+        self.sort_by_idle = true;
+        self
+    }
+
+    /// Color spans in terminal reports (`SpanGraph::as_table_with` and
+    /// `SpanGraph::as_tree_with`) according to the fraction of the total time
+    /// spent in them: red above 30%, yellow above 10% and green otherwise.
+    ///
+    /// By default (`ColorMode::Auto`), colors are used only when the standard
+    /// output is a terminal. Use `ColorMode::Never` when the report is sent
+    /// somewhere else, e.g. to a log file, while running in a terminal.
+    pub fn with_colors(mut self, mode: ColorMode) -> ReportOptions {
+        self.colors = mode;
+        self
+    }
+
+    /// Use the given `style` for the borders of tables in reports, instead
+    /// of the default box-drawing characters which can be mangled by some
+    /// log aggregation systems.
+    pub fn with_table_style(mut self, style: TableStyle) -> ReportOptions {
+        self.table_style = style;
+        self
+    }
+
+    /// Set whether the content of table cells should be padded with a space
+    /// on each side. Padding is enabled by default.
+    pub fn with_padding(mut self, padding: bool) -> ReportOptions {
+        self.no_padding = !padding;
+        self
+    }
+
+    /// Render `table` with the style and padding from these options
+    pub(crate) fn render_table(&self, mut table: term_table::Table) -> String {
+        table.style = match self.table_style {
+            TableStyle::Extended => term_table::TableStyle::extended(),
+            TableStyle::Simple => term_table::TableStyle::simple(),
+            TableStyle::Blank => term_table::TableStyle::empty(),
+            TableStyle::Markdown => term_table::TableStyle {
+                top_left_corner: '|',
+                top_right_corner: '|',
+                bottom_left_corner: '|',
+                bottom_right_corner: '|',
+                outer_left_vertical: '|',
+                outer_right_vertical: '|',
+                outer_bottom_horizontal: '|',
+                outer_top_horizontal: '|',
+                intersection: '|',
+                vertical: '|',
+                horizontal: '-',
+            },
+        };
+
+        if self.table_style == TableStyle::Blank || self.table_style == TableStyle::Markdown {
+            // only keep the line separating the header from the data
+            table.has_top_boarder = false;
+            table.has_bottom_boarder = false;
+            for row in table.rows.iter_mut().skip(2) {
+                row.has_separator = false;
+            }
+        }
+
+        for row in &mut table.rows {
+            for cell in &mut row.cells {
+                cell.pad_content = !self.no_padding;
+            }
+        }
+
+        let output = table.render();
+        if !self.no_padding {
+            return output;
+        }
+
+        // term_table marks the edges of unpadded cells with NUL characters,
+        // but still counts them in the width of the columns. Remove them,
+        // and shorten the separator lines accordingly.
+        let mut unpadded = String::new();
+        for line in output.lines() {
+            if line.contains('\0') {
+                unpadded += &line.replace('\0', "");
+                unpadded += "\n";
+                continue;
+            }
+
+            let mut run = 0_usize;
+            for c in line.chars().chain(std::iter::once('\n')) {
+                if c == table.style.horizontal {
+                    run += 1;
+                } else {
+                    for _ in 0..run.saturating_sub(2) {
+                        unpadded.push(table.style.horizontal);
+                    }
+                    run = 0;
+                    unpadded.push(c);
+                }
+            }
+        }
+        return unpadded;
+    }
+
+    /// Wrap `text` in the ANSI color corresponding to `fraction` of the total
+    /// time, if colors are enabled
+    pub(crate) fn colorize(&self, text: &str, fraction: f64) -> String {
+        let enabled = match self.colors {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                use std::io::IsTerminal;
+                std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+            }
+        };
+
+        if !enabled {
+            return text.into();
+        }
+
+        let color = if fraction >= 0.3 {
+            "31"
+        } else if fraction >= 0.1 {
+            "33"
+        } else {
+            "32"
+        };
+        return format!("\x1b[{}m{}\x1b[0m", color, text);
+    }
+
+    /// Check if `span` should be included in reports using these options
+    pub(crate) fn includes(&self, span: &SpanTiming) -> bool {
+        let level = match (self.max_level, span.level) {
+            (Some(max), Some(level)) => level <= max,
+            _ => true,
+        };
+        return level
+            && self
+                .filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(span));
+    }
+
+    /// Render a separate section for each root span (see `SpanGraph::roots`),
+    /// containing the root and all the spans it called. This is useful for
+    /// programs with multiple independent entry points, such as request
+    /// handlers or CLI subcommands.
+    ///
+    /// Spans called from multiple roots appear in each corresponding section,
+    /// with their full data.
+    pub fn with_per_root_sections(mut self) -> ReportOptions {
+        self.per_root = true;
+        self
+    }
+}
+
+/// Summary of the data for a single span group in a `Report`
+#[derive(Clone, Debug)]
+pub struct SpanReport {
+    /// Span identifier, see `SpanTiming::id`
+    pub id: usize,
+    /// Identifier stable across runs, see `SpanTiming::stable_id`
+    pub stable_id: u64,
+    /// Full span name
+    pub name: String,
+    /// Verbosity level of this span, see `SpanTiming::level`
+    pub level: Option<Level>,
+    /// Number of time this span has been called
+    pub called: usize,
+    /// Total time spent in this span
+    pub elapsed: Duration,
+    /// Total time between the creation and closing of this span, see
+    /// `SpanTiming::lifetime`
+    pub lifetime: Duration,
+    /// Total time this span existed without being entered, see
+    /// `SpanTiming::idle`
+    pub idle: Duration,
+    /// Mean duration of a single invocation of this span
+    pub mean: Duration,
+    /// Standard deviation of the duration of invocations of this span
+    pub stddev: Duration,
+    /// Longest duration of a single invocation of this span
+    pub max: Duration,
+    /// Maximal nesting depth of this span
+    pub max_depth: usize,
+    /// Number of times this span has been entered, see `SpanTiming::polls`
+    pub polls: usize,
+    /// Moving average of the duration of invocations of this span, see
+    /// `SpanTiming::moving_average`
+    pub moving_average: Option<Duration>,
+    /// Estimated energy consumed in this span, in joules, see
+    /// `SpanTiming::energy`
+    pub energy: f64,
+    /// Largest resident set size observed in this span, in bytes, see
+    /// `SpanTiming::max_rss`
+    pub max_rss: u64,
+    /// Number of `ERROR` events emitted in this span, see
+    /// `SpanTiming::errors`
+    pub errors: usize,
+    /// Number of calls recording a successful outcome, see
+    /// `SpanTiming::successes`
+    pub successes: usize,
+    /// Number of calls recording a failed outcome, see
+    /// `SpanTiming::failures`
+    pub failures: usize,
+    /// Number of calls unwound by a panic, see `SpanTiming::panics`
+    pub panics: usize,
+    /// Is this span an outlier, according to `ReportOptions::with_outliers`?
+    pub outlier: bool,
+    /// Time budget of this span, see `SpanGraph::set_budget`
+    pub budget: Option<Duration>,
+    /// Did this span exceed its time budget?
+    pub over_budget: bool,
+    /// Custom metrics of this span, see `SpanGraph::add_metric`
+    pub metrics: BTreeMap<String, f64>,
+}
+
+/// Summary of the calls between two span groups in a `Report`
+#[derive(Clone, Debug)]
+pub struct CallReport {
+    /// Full name of the outer/calling span
+    pub caller: String,
+    /// Full name of the inner/called span
+    pub callee: String,
+    /// Number of time the caller called the callee
+    pub count: usize,
+}
+
+/// Typed summary of all the data in a `SpanGraph`, for programmatic
+/// consumption
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// All spans in the graph, sorted with callers before callees (or by
+    /// decreasing idle time with `ReportOptions::with_sort_by_idle`)
+    pub spans: Vec<SpanReport>,
+    /// All calls between spans in the graph
+    pub calls: Vec<CallReport>,
+    /// Metadata attached to the graph, see `SpanGraph::set_metadata`
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl SpanGraph {
+    /// Get a typed summary of the data in this graph
+    pub fn report(&self) -> Report {
+        self.report_with(&ReportOptions::default())
+    }
+
+    /// Get a typed summary of the data in this graph, using the given
+    /// `options`
+    pub fn report_with(&self, options: &ReportOptions) -> Report {
+        let spans = self
+            .report_nodes(options)
+            .into_iter()
+            .filter(|&node| options.includes(&self.graph[node]))
+            .map(|node| {
+                let span = &self.graph[node];
+                SpanReport {
+                    id: span.id,
+                    stable_id: span.stable_id(),
+                    name: span.name.clone(),
+                    level: span.level,
+                    called: span.called,
+                    elapsed: span.elapsed,
+                    lifetime: span.lifetime,
+                    idle: span.idle,
+                    mean: span.mean(),
+                    stddev: span.stddev(),
+                    max: span.max,
+                    max_depth: span.max_depth,
+                    polls: span.polls,
+                    moving_average: span.moving_average(),
+                    energy: span.energy,
+                    max_rss: span.max_rss,
+                    errors: span.errors,
+                    successes: span.successes,
+                    failures: span.failures,
+                    panics: span.panics,
+                    outlier: options
+                        .outlier_factor
+                        .is_some_and(|factor| span.is_outlier(factor)),
+                    budget: self.budget(node.into()),
+                    over_budget: self.is_over_budget(node.into()),
+                    metrics: span.metrics.clone(),
+                }
+            })
+            .collect();
+
+        let calls = self
+            .calls()
+            .filter(|call| {
+                options.includes(self.span(call.caller)) && options.includes(self.span(call.callee))
+            })
+            .map(|call| CallReport {
+                caller: self.span(call.caller).name.clone(),
+                callee: self.span(call.callee).name.clone(),
+                count: call.count,
+            })
+            .collect();
+
+        return Report {
+            spans: spans,
+            calls: calls,
+            metadata: self.metadata.clone(),
+        };
+    }
+}
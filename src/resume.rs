@@ -0,0 +1,25 @@
+use crate::SpanGraph;
+
+/// Metadata key used to store the number of runs accumulated in a graph, see
+/// `SpanTimingLayer::with_resume`
+const RUNS_METADATA: &str = "runs";
+
+impl SpanGraph {
+    /// Get the number of runs of the program accumulated in this graph. This
+    /// is larger than one for graphs resumed from a previous run with
+    /// `SpanTimingLayer::with_resume`, and one otherwise.
+    pub fn runs(&self) -> usize {
+        self.metadata
+            .get(RUNS_METADATA)
+            .and_then(|runs| runs.parse().ok())
+            .unwrap_or(1)
+    }
+
+    /// Add all the data from the `previous` run(s) to this graph, and count
+    /// this graph as one more run
+    pub(crate) fn resume(&mut self, previous: &SpanGraph) {
+        let runs = previous.runs() + self.runs();
+        self.merge(previous);
+        self.set_metadata(RUNS_METADATA, &runs.to_string());
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use term_table::row::Row;
+use term_table::table_cell::TableCell;
+
+use crate::graph::right_aligned;
+use crate::rank::span_names;
+use crate::{ReportOptions, SpanGraph};
+
+/// Two-sided 95% critical values of the Student t distribution, indexed by
+/// the number of degrees of freedom minus one
+const STUDENT_T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// Statistics of the elapsed time of a single span group across the graphs
+/// recorded by repeated runs of the same program, see
+/// `SpanGraph::run_statistics`.
+#[derive(Clone, Debug)]
+pub struct RunStatistics {
+    /// Full span name, used to match spans between runs
+    pub name: String,
+    /// Number of runs in which this span was called
+    pub runs: usize,
+    /// Total number of runs
+    pub total_runs: usize,
+    /// Mean elapsed time across all runs, runs in which the span was not
+    /// called counting as zero
+    pub mean: Duration,
+    /// Smallest elapsed time across all runs
+    pub min: Duration,
+    /// Largest elapsed time across all runs
+    pub max: Duration,
+    /// Sample standard deviation of the elapsed time across all runs
+    pub stddev: Duration,
+}
+
+impl RunStatistics {
+    /// Get the half-width of the 95% confidence interval on the mean elapsed
+    /// time, using the Student t distribution. The true mean is expected to
+    /// be within `mean ± confidence_interval()`. This is zero when less than
+    /// two runs are available.
+    pub fn confidence_interval(&self) -> Duration {
+        if self.total_runs < 2 {
+            return Duration::new(0, 0);
+        }
+
+        let t = STUDENT_T_95
+            .get(self.total_runs - 2)
+            .copied()
+            .unwrap_or(1.960);
+        let error = self.stddev.as_secs_f64() / (self.total_runs as f64).sqrt();
+        return Duration::from_secs_f64(t * error);
+    }
+}
+
+impl SpanGraph {
+    /// Compute statistics of the elapsed time of each span across the
+    /// `graphs` recorded by repeated runs of the same program, such as
+    /// multiple executions of a noisy benchmark. Spans are matched by name.
+    ///
+    /// ```no_run
+    /// # use tracing_timing_graph::SpanGraph;
+    /// let graphs = (0..10)
+    ///     .map(|i| SpanGraph::read_json_file(format!("run-{}.json", i)))
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    ///
+    /// for span in SpanGraph::run_statistics(&graphs) {
+    ///     println!("{}: {:.2?} ± {:.2?}", span.name, span.mean, span.confidence_interval());
+    /// }
+    /// # Ok::<(), tracing_timing_graph::ImportError>(())
+    /// ```
+    pub fn run_statistics(graphs: &[SpanGraph]) -> Vec<RunStatistics> {
+        let names = span_names(graphs);
+        let mut statistics = Vec::with_capacity(names.len());
+        for name in names {
+            let elapsed = graphs
+                .iter()
+                .map(|graph| match graph.find(&name) {
+                    Some(node) => graph.span(node).elapsed,
+                    None => Duration::new(0, 0),
+                })
+                .collect::<Vec<_>>();
+
+            let runs = graphs
+                .iter()
+                .filter(|graph| graph.find(&name).is_some())
+                .count();
+
+            let count = elapsed.len() as f64;
+            let mean = elapsed.iter().map(Duration::as_secs_f64).sum::<f64>() / count;
+            let stddev = if elapsed.len() > 1 {
+                let squares = elapsed
+                    .iter()
+                    .map(|elapsed| (elapsed.as_secs_f64() - mean).powi(2))
+                    .sum::<f64>();
+                (squares / (count - 1.0)).sqrt()
+            } else {
+                0.0
+            };
+
+            statistics.push(RunStatistics {
+                name: name,
+                runs: runs,
+                total_runs: graphs.len(),
+                mean: Duration::from_secs_f64(mean),
+                min: elapsed.iter().copied().min().unwrap_or_default(),
+                max: elapsed.iter().copied().max().unwrap_or_default(),
+                stddev: Duration::from_secs_f64(stddev),
+            });
+        }
+
+        return statistics;
+    }
+
+    /// Get a table containing the statistics of each span across the `graphs`
+    /// recorded by repeated runs of the same program, see
+    /// `SpanGraph::run_statistics`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_run_table(graphs: &[SpanGraph]) -> String {
+        SpanGraph::as_run_table_with(graphs, &ReportOptions::default())
+    }
+
+    /// Get a table containing the statistics of each span across the `graphs`
+    /// recorded by repeated runs of the same program, using the table style
+    /// from `options`. See `SpanGraph::as_run_table`.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_run_table_with(graphs: &[SpanGraph], options: &ReportOptions) -> String {
+        let mut table = term_table::Table::new();
+
+        table.add_row(Row::new(vec![
+            // pad "span name" to make the table look nicer with short names
+            "span name                                   ",
+            "runs",
+            "mean ± 95% CI",
+            "std dev",
+            "min",
+            "max",
+        ]));
+
+        for span in SpanGraph::run_statistics(graphs) {
+            table.add_row(Row::new(vec![
+                TableCell::new(&span.name),
+                right_aligned(format!("{}/{}", span.runs, span.total_runs)),
+                right_aligned(format!(
+                    "{:.2?} ± {:.2?}",
+                    span.mean,
+                    span.confidence_interval()
+                )),
+                right_aligned(format!("{:.2?}", span.stddev)),
+                right_aligned(format!("{:.2?}", span.min)),
+                right_aligned(format!("{:.2?}", span.max)),
+            ]));
+        }
+
+        return options.render_table(table);
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Number of calls and elapsed time accumulated in a single time window
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bucket {
+    /// Number of calls that finished in this window
+    pub called: usize,
+    /// Total elapsed time of the calls that finished in this window
+    pub elapsed: Duration,
+}
+
+/// Maximal number of windows kept in a time series. Older windows are
+/// dropped when new ones are added past this limit.
+pub(crate) const MAX_TIME_SERIES_BUCKETS: usize = 4096;
+
+/// Time series of calls to a span, bucketed into fixed-size time windows.
+///
+/// Calls are attributed to the window in which the corresponding span was
+/// closed. Windows are counted from the creation of the `SpanGraph` (or the
+/// last call to `SpanGraph::clear`). Only the most recent
+/// `MAX_TIME_SERIES_BUCKETS` (4096) windows are kept, to bound the memory
+/// used by long running processes.
+#[derive(Clone, Debug)]
+pub struct TimeSeries {
+    width: Duration,
+    /// index of the first window in `buckets`
+    first: usize,
+    buckets: Vec<Bucket>,
+}
+
+impl TimeSeries {
+    /// Create an empty time series with the given window `width`, which must
+    /// not be zero
+    pub(crate) fn new(width: Duration) -> TimeSeries {
+        debug_assert!(width > Duration::new(0, 0));
+        TimeSeries {
+            width: width,
+            first: 0,
+            buckets: Vec::new(),
+        }
+    }
+
+    /// Create a time series with the given window `width` and `buckets`,
+    /// the first bucket corresponding to the window with index `first`
+    pub(crate) fn from_buckets(width: Duration, first: usize, buckets: Vec<Bucket>) -> TimeSeries {
+        let mut series = TimeSeries::new(width);
+        series.first = first;
+        series.buckets = buckets;
+        series.truncate();
+        return series;
+    }
+
+    /// Record a call taking `elapsed` time, finishing `at` the given time
+    pub(crate) fn record(&mut self, at: Duration, elapsed: Duration) {
+        let index = (at.as_nanos() / self.width.as_nanos()) as usize;
+        if self.buckets.is_empty() {
+            self.first = index;
+        }
+
+        if index < self.first {
+            // this window was already dropped
+            return;
+        }
+
+        let index = index - self.first;
+        if index >= self.buckets.len() {
+            self.buckets.resize(index + 1, Bucket::default());
+        }
+
+        let bucket = &mut self.buckets[index];
+        bucket.called += 1;
+        bucket.elapsed += elapsed;
+
+        self.truncate();
+    }
+
+    /// Add all the calls in `other` to this time series. Both series must use
+    /// the same window width.
+    pub(crate) fn merge(&mut self, other: &TimeSeries) {
+        debug_assert_eq!(self.width, other.width);
+        if other.buckets.is_empty() {
+            return;
+        }
+        if self.buckets.is_empty() {
+            self.first = other.first;
+        }
+
+        if other.first < self.first {
+            let missing = self.first - other.first;
+            self.buckets
+                .splice(0..0, std::iter::repeat_n(Bucket::default(), missing));
+            self.first = other.first;
+        }
+
+        let end = other.first + other.buckets.len() - self.first;
+        if end > self.buckets.len() {
+            self.buckets.resize(end, Bucket::default());
+        }
+
+        let start = other.first - self.first;
+        for (bucket, other) in self.buckets[start..].iter_mut().zip(&other.buckets) {
+            bucket.called += other.called;
+            bucket.elapsed += other.elapsed;
+        }
+
+        self.truncate();
+    }
+
+    /// Drop the oldest windows to keep at most `MAX_TIME_SERIES_BUCKETS`
+    fn truncate(&mut self) {
+        if self.buckets.len() > MAX_TIME_SERIES_BUCKETS {
+            let excess = self.buckets.len() - MAX_TIME_SERIES_BUCKETS;
+            self.buckets.drain(..excess);
+            self.first += excess;
+        }
+    }
+
+    /// Multiply the window width and all elapsed times in this series by
+    /// `factor`, see `SpanGraph::normalized`
+    pub(crate) fn scale(&mut self, factor: f64) {
+        self.width = self.width.mul_f64(factor);
+        for bucket in &mut self.buckets {
+            bucket.elapsed = bucket.elapsed.mul_f64(factor);
+        }
+    }
+
+    /// Get the number of bytes allocated on the heap by this series
+    pub(crate) fn heap_size(&self) -> usize {
+        self.buckets.capacity() * std::mem::size_of::<Bucket>()
+    }
+
+    /// Get the width of the time windows in this series
+    pub fn width(&self) -> Duration {
+        self.width
+    }
+
+    /// Get the index of the first window kept in this series, i.e. the
+    /// number of windows before it which were never recorded or dropped
+    pub fn first(&self) -> usize {
+        self.first
+    }
+
+    /// Get all the windows kept in this series, starting from the oldest one
+    /// (see `TimeSeries::first`). Windows in which the span was not called
+    /// are included and empty.
+    pub fn buckets(&self) -> &[Bucket] {
+        &self.buckets
+    }
+
+    /// Iterate over the windows in this series, together with the start time
+    /// of each window
+    pub fn iter(&self) -> impl Iterator<Item = (Duration, &Bucket)> + '_ {
+        let width = self.width;
+        let first = self.first;
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(move |(i, bucket)| (width * (first + i) as u32, bucket))
+    }
+}
+
+/// Number of slots used to discretize a rolling window
+pub(crate) const ROLLING_WINDOW_SLOTS: u128 = 60;
+
+/// Calls and elapsed time over a rolling time window, discretized in a fixed
+/// number of slots to keep memory usage bounded.
+#[derive(Clone, Debug)]
+pub(crate) struct RollingWindow {
+    /// width of a single slot
+    slot: Duration,
+    /// slot index and corresponding data, oldest first
+    slots: VecDeque<(u128, Bucket)>,
+}
+
+impl RollingWindow {
+    pub fn new(width: Duration) -> RollingWindow {
+        let slot = width / ROLLING_WINDOW_SLOTS as u32;
+        debug_assert!(slot > Duration::new(0, 0));
+        RollingWindow {
+            slot: slot,
+            slots: VecDeque::new(),
+        }
+    }
+
+    /// Record a call taking `elapsed` time, finishing `at` the given time
+    pub fn record(&mut self, at: Duration, elapsed: Duration) {
+        let index = at.as_nanos() / self.slot.as_nanos();
+        self.evict(index);
+
+        match self.slots.back_mut() {
+            Some((last, bucket)) if *last == index => {
+                bucket.called += 1;
+                bucket.elapsed += elapsed;
+            }
+            _ => self.slots.push_back((
+                index,
+                Bucket {
+                    called: 1,
+                    elapsed: elapsed,
+                },
+            )),
+        }
+    }
+
+    /// Get the total calls and elapsed time inside the window ending `at` the
+    /// given time
+    pub fn total(&self, at: Duration) -> Bucket {
+        let index = at.as_nanos() / self.slot.as_nanos();
+        let mut total = Bucket::default();
+        for (_, bucket) in self
+            .slots
+            .iter()
+            .filter(|(i, _)| i + ROLLING_WINDOW_SLOTS > index)
+        {
+            total.called += bucket.called;
+            total.elapsed += bucket.elapsed;
+        }
+        return total;
+    }
+
+    /// Get the number of bytes allocated on the heap by this window
+    pub fn heap_size(&self) -> usize {
+        self.slots.capacity() * std::mem::size_of::<(u128, Bucket)>()
+    }
+
+    /// Remove slots that are outside of the window ending in slot `index`
+    fn evict(&mut self, index: u128) {
+        while let Some(&(first, _)) = self.slots.front() {
+            if first + ROLLING_WINDOW_SLOTS <= index {
+                self.slots.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// Exponentially weighted moving average of invocation durations, where the
+/// weight of each invocation is halved every `half_life`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MovingAverage {
+    half_life: Duration,
+    /// decayed sum of invocation durations, in seconds
+    sum: f64,
+    /// decayed number of invocations
+    weight: f64,
+    /// time of the last recorded invocation
+    last: Duration,
+}
+
+impl MovingAverage {
+    pub fn new(half_life: Duration) -> MovingAverage {
+        MovingAverage {
+            half_life: half_life,
+            sum: 0.0,
+            weight: 0.0,
+            last: Duration::new(0, 0),
+        }
+    }
+
+    /// Restore a moving average from its `half_life`, decayed `sum` of
+    /// durations in seconds and decayed `weight`, for example when importing
+    /// data from a previous run. The data is considered to be recorded at the
+    /// start of the current run.
+    pub fn from_parts(half_life: Duration, sum: f64, weight: f64) -> MovingAverage {
+        MovingAverage {
+            half_life: half_life,
+            sum: sum,
+            weight: weight,
+            last: Duration::new(0, 0),
+        }
+    }
+
+    pub fn half_life(&self) -> Duration {
+        self.half_life
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    /// Record a call taking `elapsed` time, finishing `at` the given time
+    pub fn record(&mut self, at: Duration, elapsed: Duration) {
+        let age = at.saturating_sub(self.last).as_secs_f64();
+        let decay = f64::powf(0.5, age / self.half_life.as_secs_f64());
+        self.sum = decay * self.sum + elapsed.as_secs_f64();
+        self.weight = decay * self.weight + 1.0;
+        self.last = std::cmp::max(self.last, at);
+    }
+
+    /// Get the current value of the moving average
+    pub fn value(&self) -> Duration {
+        if self.weight == 0.0 {
+            return Duration::new(0, 0);
+        }
+        Duration::try_from_secs_f64(self.sum / self.weight).unwrap_or(Duration::MAX)
+    }
+
+    /// Multiply the recorded durations by `factor`
+    pub fn scale(&mut self, factor: f64) {
+        self.sum *= factor;
+    }
+}
@@ -0,0 +1,38 @@
+use signal_hook::consts::SIGUSR1;
+use signal_hook::iterator::Signals;
+
+use crate::{Format, LayerError, SpanTimingLayer};
+
+impl SpanTimingLayer {
+    /// Dump the current span graph every time the process receives the
+    /// `SIGUSR1` signal. The graph is written to the file configured with
+    /// `SpanTimingLayer::with_output` if any, and printed as a table on the
+    /// standard error otherwise.
+    ///
+    /// This is useful to look at the state of long-running processes, for
+    /// example to diagnose stuck jobs with `kill -USR1 <pid>`. The signal is
+    /// handled on a separate background thread.
+    pub fn with_dump_on_sigusr1(self) -> std::io::Result<SpanTimingLayer> {
+        let mut signals = Signals::new([SIGUSR1])?;
+        let view = self.view();
+        let on_error = self.on_error.clone();
+        std::thread::Builder::new()
+            .name("span-timing-sigusr1".into())
+            .spawn(move || {
+                for _ in signals.forever() {
+                    let graph = view.read();
+                    if graph.settings.output.is_some() {
+                        if let Err(error) = graph.flush() {
+                            if let Some(ref on_error) = on_error {
+                                on_error(LayerError::Output { kind: error.kind() });
+                            }
+                        }
+                    } else {
+                        eprintln!("{}", graph.render(Format::Table));
+                    }
+                }
+            })?;
+
+        Ok(self)
+    }
+}
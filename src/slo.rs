@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use crate::{SpanGraph, SpanIndex, SpanTiming};
+
+/// Latency objective for a span group, see `SpanGraph::set_objective`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LatencyObjective {
+    /// The mean duration of invocations should stay below the given duration
+    Mean(Duration),
+    /// The longest invocation should stay below the given duration
+    Max(Duration),
+    /// The given percentile (between 0 and 100, e.g. `99.0` for p99) of
+    /// invocation durations should stay below the given duration.
+    ///
+    /// This is computed from the durations recorded with
+    /// `SpanTimingLayer::with_recent_durations` if enabled, or
+    /// `SpanTimingLayer::with_invocation_durations` otherwise, and can not be
+    /// checked if neither is enabled.
+    Percentile(f64, Duration),
+}
+
+impl LatencyObjective {
+    /// Get the maximal duration allowed by this objective
+    pub fn threshold(&self) -> Duration {
+        match *self {
+            LatencyObjective::Mean(threshold) => threshold,
+            LatencyObjective::Max(threshold) => threshold,
+            LatencyObjective::Percentile(_, threshold) => threshold,
+        }
+    }
+
+    /// Get the value of the quantity constrained by this objective for
+    /// `span`, or `None` if it can not be computed
+    pub fn observed(&self, span: &SpanTiming) -> Option<Duration> {
+        if span.called == 0 {
+            return None;
+        }
+
+        match *self {
+            LatencyObjective::Mean(_) => Some(span.mean()),
+            LatencyObjective::Max(_) => Some(span.max),
+            LatencyObjective::Percentile(percentile, _) => {
+                let mut durations = if span.recent.is_empty() {
+                    span.durations.clone()
+                } else {
+                    span.recent.iter().copied().collect()
+                };
+                if durations.is_empty() {
+                    return None;
+                }
+                durations.sort_unstable();
+
+                // nearest-rank percentile
+                let rank = (percentile / 100.0 * durations.len() as f64).ceil() as usize;
+                let index = rank.clamp(1, durations.len()) - 1;
+                Some(durations[index])
+            }
+        }
+    }
+
+    /// Check if `span` violates this objective. Spans for which the
+    /// objective can not be computed are never in violation.
+    pub fn is_violated(&self, span: &SpanTiming) -> bool {
+        self.observed(span)
+            .is_some_and(|observed| observed > self.threshold())
+    }
+}
+
+impl std::fmt::Display for LatencyObjective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            LatencyObjective::Mean(threshold) => write!(f, "mean < {:.2?}", threshold),
+            LatencyObjective::Max(threshold) => write!(f, "max < {:.2?}", threshold),
+            LatencyObjective::Percentile(percentile, threshold) => {
+                write!(f, "p{} < {:.2?}", percentile, threshold)
+            }
+        }
+    }
+}
+
+/// A latency objective violated by a span group, see
+/// `SpanGraph::slo_violations`
+#[derive(Clone, Debug)]
+pub struct SloViolation {
+    /// Full name of the span violating the objective
+    pub name: String,
+    /// The violated objective
+    pub objective: LatencyObjective,
+    /// Observed value of the quantity constrained by the objective, larger
+    /// than `objective.threshold()`
+    pub observed: Duration,
+}
+
+impl std::fmt::Display for SloViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} violated '{}' (observed {:.2?})",
+            self.name, self.objective, self.observed
+        )
+    }
+}
+
+impl SpanGraph {
+    /// Register a latency `objective` for the span with the given full
+    /// `name`. Multiple objectives can be registered for the same span.
+    /// Spans violating one of their objectives are marked in reports, and
+    /// the violations can be checked with `SpanGraph::slo_violations`.
+    ///
+    /// # Panic
+    ///
+    /// This function panics if the percentile of a
+    /// `LatencyObjective::Percentile` is not between 0 and 100.
+    pub fn set_objective(&mut self, name: &str, objective: LatencyObjective) {
+        if let LatencyObjective::Percentile(percentile, _) = objective {
+            assert!(
+                percentile > 0.0 && percentile <= 100.0,
+                "percentile must be between 0 and 100, got {}",
+                percentile
+            );
+        }
+
+        self.settings
+            .objectives
+            .entry(name.into())
+            .or_default()
+            .push(objective);
+    }
+
+    /// Get the latency objectives of `span`, registered with
+    /// `SpanGraph::set_objective`
+    pub fn objectives(&self, span: SpanIndex) -> &[LatencyObjective] {
+        self.settings
+            .objectives
+            .get(&self.span(span).name)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Get all the latency objectives currently violated by spans in this
+    /// graph, in the same order as `SpanGraph::as_table`. This can be
+    /// checked periodically to trigger alerts.
+    pub fn slo_violations(&self) -> Vec<SloViolation> {
+        let mut violations = Vec::new();
+        for node in self.sorted_nodes() {
+            let span = &self.graph[node];
+            for objective in self.objectives(node.into()) {
+                if let Some(observed) = objective.observed(span) {
+                    if observed > objective.threshold() {
+                        violations.push(SloViolation {
+                            name: span.name.clone(),
+                            objective: *objective,
+                            observed: observed,
+                        });
+                    }
+                }
+            }
+        }
+        return violations;
+    }
+}
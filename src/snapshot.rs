@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::series::{Bucket, TimeSeries};
+use crate::{HardwareCounters, ImportError, SpanGraph, SpanTiming};
+
+/// Version of the snapshot format, to be incremented on every change to the
+/// structures below
+const SNAPSHOT_VERSION: u32 = 5;
+
+/// Binary representation of a `SpanGraph`. Durations are stored as integer
+/// numbers of nanoseconds.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    metadata: BTreeMap<String, String>,
+    spans: Vec<SpanSnapshot>,
+    /// (caller id, callee id, count)
+    calls: Vec<(usize, usize, usize)>,
+    /// (cause id, effect id, count)
+    follows: Vec<(usize, usize, usize)>,
+}
+
+/// Binary representation of a `TimeSeries`
+#[derive(Serialize, Deserialize)]
+struct SeriesSnapshot {
+    width: u64,
+    first: usize,
+    /// (called, elapsed)
+    buckets: Vec<(usize, u64)>,
+}
+
+/// Binary representation of a `SpanTiming`
+#[derive(Serialize, Deserialize)]
+struct SpanSnapshot {
+    id: usize,
+    name: String,
+    level: Option<String>,
+    target: Option<String>,
+    module_path: Option<String>,
+    span_name: Option<String>,
+    elapsed: u64,
+    called: usize,
+    series: Option<SeriesSnapshot>,
+    warmup: usize,
+    max: u64,
+    lifetime: u64,
+    idle: u64,
+    polls: usize,
+    max_poll: u64,
+    max_depth: usize,
+    durations: Vec<u64>,
+    recent: Vec<u64>,
+    counters: BTreeMap<String, f64>,
+    metrics: BTreeMap<String, f64>,
+    tasks: BTreeMap<u64, u64>,
+    threads: BTreeMap<String, u64>,
+    /// (instructions, cycles, cache misses)
+    hardware: (u64, u64, u64),
+    energy: f64,
+    max_rss: u64,
+    errors: usize,
+    successes: usize,
+    failures: usize,
+    panics: usize,
+    mean: f64,
+    m2: f64,
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos() as u64
+}
+
+impl SpanSnapshot {
+    fn new(span: &SpanTiming) -> SpanSnapshot {
+        SpanSnapshot {
+            id: span.id,
+            name: span.name.clone(),
+            level: span.level.map(|level| level.to_string()),
+            target: span.target.clone(),
+            module_path: span.module_path.clone(),
+            span_name: span.span_name.clone(),
+            elapsed: nanos(span.elapsed),
+            called: span.called,
+            series: span.series.as_ref().map(|series| {
+                let buckets = series
+                    .buckets()
+                    .iter()
+                    .map(|bucket| (bucket.called, nanos(bucket.elapsed)))
+                    .collect();
+                SeriesSnapshot {
+                    width: nanos(series.width()),
+                    first: series.first(),
+                    buckets: buckets,
+                }
+            }),
+            warmup: span.warmup,
+            max: nanos(span.max),
+            lifetime: nanos(span.lifetime),
+            idle: nanos(span.idle),
+            polls: span.polls,
+            max_poll: nanos(span.max_poll),
+            max_depth: span.max_depth,
+            durations: span.durations.iter().copied().map(nanos).collect(),
+            recent: span.recent.iter().copied().map(nanos).collect(),
+            counters: span.counters.clone(),
+            metrics: span.metrics.clone(),
+            tasks: span
+                .tasks
+                .iter()
+                .map(|(&task, &elapsed)| (task, nanos(elapsed)))
+                .collect(),
+            threads: span
+                .threads
+                .iter()
+                .map(|(thread, &elapsed)| (thread.clone(), nanos(elapsed)))
+                .collect(),
+            hardware: (
+                span.hardware.instructions,
+                span.hardware.cycles,
+                span.hardware.cache_misses,
+            ),
+            energy: span.energy,
+            max_rss: span.max_rss,
+            errors: span.errors,
+            successes: span.successes,
+            failures: span.failures,
+            panics: span.panics,
+            mean: span.mean,
+            m2: span.m2,
+        }
+    }
+
+    fn into_span(self) -> Result<SpanTiming, ImportError> {
+        let mut span = SpanTiming::new(self.name, self.id);
+        if let Some(level) = self.level {
+            let level = level
+                .parse()
+                .map_err(|_| ImportError::Invalid(format!("invalid level '{}'", level)))?;
+            span.level = Some(level);
+        }
+        span.target = self.target;
+        span.module_path = self.module_path;
+        span.span_name = self.span_name;
+        span.elapsed = Duration::from_nanos(self.elapsed);
+        span.called = self.called;
+        if let Some(series) = self.series {
+            if series.width == 0 {
+                return Err(ImportError::Invalid(
+                    "time series width can not be zero".into(),
+                ));
+            }
+            let buckets = series
+                .buckets
+                .into_iter()
+                .map(|(called, elapsed)| Bucket {
+                    called: called,
+                    elapsed: Duration::from_nanos(elapsed),
+                })
+                .collect();
+            span.series = Some(TimeSeries::from_buckets(
+                Duration::from_nanos(series.width),
+                series.first,
+                buckets,
+            ));
+        }
+        span.warmup = self.warmup;
+        span.max = Duration::from_nanos(self.max);
+        span.lifetime = Duration::from_nanos(self.lifetime);
+        span.idle = Duration::from_nanos(self.idle);
+        span.polls = self.polls;
+        span.max_poll = Duration::from_nanos(self.max_poll);
+        span.max_depth = self.max_depth;
+        span.durations = self
+            .durations
+            .into_iter()
+            .map(Duration::from_nanos)
+            .collect();
+        span.recent = self
+            .recent
+            .into_iter()
+            .map(Duration::from_nanos)
+            .collect::<VecDeque<_>>();
+        span.counters = self.counters;
+        span.metrics = self.metrics;
+        span.tasks = self
+            .tasks
+            .into_iter()
+            .map(|(task, elapsed)| (task, Duration::from_nanos(elapsed)))
+            .collect();
+        span.threads = self
+            .threads
+            .into_iter()
+            .map(|(thread, elapsed)| (thread, Duration::from_nanos(elapsed)))
+            .collect();
+        span.hardware = HardwareCounters {
+            instructions: self.hardware.0,
+            cycles: self.hardware.1,
+            cache_misses: self.hardware.2,
+        };
+        span.energy = self.energy;
+        span.max_rss = self.max_rss;
+        span.errors = self.errors;
+        span.successes = self.successes;
+        span.failures = self.failures;
+        span.panics = self.panics;
+        if !self.mean.is_finite() || !self.m2.is_finite() || self.m2 < 0.0 {
+            return Err(ImportError::Invalid(format!(
+                "invalid running variance for span '{}'",
+                span.name
+            )));
+        }
+        span.mean = self.mean;
+        span.m2 = self.m2;
+        return Ok(span);
+    }
+}
+
+impl SpanGraph {
+    /// Serialize this graph to a compact binary format, suitable for cheap
+    /// checkpointing of large graphs. Use `SpanGraph::from_bytes` to load the
+    /// data back.
+    ///
+    /// Contrary to `SpanGraph::as_json`, times are stored with nanosecond
+    /// resolution. Settings from the layer are not saved. The format is only
+    /// guaranteed to be readable by the same version of this crate.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            metadata: self.metadata.clone(),
+            spans: self.spans().map(SpanSnapshot::new).collect(),
+            calls: self
+                .calls()
+                .map(|call| {
+                    (
+                        self.span(call.caller).id,
+                        self.span(call.callee).id,
+                        call.count,
+                    )
+                })
+                .collect(),
+            follows: self
+                .follows_from()
+                .map(|relationship| {
+                    (
+                        self.span(relationship.cause).id,
+                        self.span(relationship.effect).id,
+                        relationship.count,
+                    )
+                })
+                .collect(),
+        };
+
+        return bincode::serialize(&snapshot).expect("failed to serialize graph");
+    }
+
+    /// Load a graph previously serialized with `SpanGraph::to_bytes`
+    pub fn from_bytes(data: &[u8]) -> Result<SpanGraph, ImportError> {
+        let snapshot: Snapshot =
+            bincode::deserialize(data).map_err(|error| ImportError::Invalid(error.to_string()))?;
+
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(ImportError::Invalid(format!(
+                "unsupported snapshot version {}",
+                snapshot.version
+            )));
+        }
+
+        let mut graph = SpanGraph::new();
+        graph.metadata = snapshot.metadata;
+
+        let mut nodes = BTreeMap::new();
+        for span in snapshot.spans {
+            let span = span.into_span()?;
+            graph.last_id = std::cmp::max(graph.last_id, span.id + 1);
+            let id = span.id;
+            if nodes.insert(id, graph.graph.add_node(span)).is_some() {
+                return Err(ImportError::Invalid(format!("duplicated span id {}", id)));
+            }
+        }
+
+        let node = |id: usize| {
+            nodes
+                .get(&id)
+                .copied()
+                .ok_or_else(|| ImportError::Invalid(format!("unknown span id {}", id)))
+        };
+
+        for (caller, callee, count) in snapshot.calls {
+            graph.graph.add_edge(node(caller)?, node(callee)?, count);
+        }
+
+        for (cause, effect, count) in snapshot.follows {
+            graph
+                .follows
+                .insert((node(cause)?.index(), node(effect)?.index()), count);
+        }
+
+        return Ok(graph);
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::SpanGraph;
+
+impl SpanGraph {
+    /// Write the spans and calls of this graph to the SQLite database at
+    /// `path`, creating it if needed. See `SpanGraph::write_sqlite_to`.
+    pub fn write_sqlite<P: AsRef<Path>>(&self, path: P, run: &str) -> rusqlite::Result<()> {
+        let mut connection = Connection::open(path)?;
+        return self.write_sqlite_to(&mut connection, run);
+    }
+
+    /// Write the spans and calls of this graph to the SQLite database behind
+    /// `connection`, tagging all rows with the `run` name. Data from multiple
+    /// runs can be stored in the same database, and compared with SQL:
+    ///
+    /// ```sql
+    /// SELECT run, elapsed_ns FROM spans WHERE name = 'app::compute' ORDER BY run;
+    /// ```
+    ///
+    /// The data goes into two tables, which are created if they do not exist
+    /// yet. Writing the same `run` again replaces the previous data.
+    ///
+    /// - `spans(run, id, name, level, target, module_path, called, elapsed_ns,
+    ///   mean_ns, stddev_ns, max_ns, idle_ns, max_depth)`;
+    /// - `calls(run, caller, callee, count)`, where `caller` and `callee`
+    ///   refer to the `id` of spans in the same run.
+    pub fn write_sqlite_to(&self, connection: &mut Connection, run: &str) -> rusqlite::Result<()> {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(
+            "CREATE TABLE IF NOT EXISTS spans (
+                run TEXT NOT NULL,
+                id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                level TEXT,
+                target TEXT,
+                module_path TEXT,
+                called INTEGER NOT NULL,
+                elapsed_ns INTEGER NOT NULL,
+                mean_ns INTEGER NOT NULL,
+                stddev_ns INTEGER NOT NULL,
+                max_ns INTEGER NOT NULL,
+                idle_ns INTEGER NOT NULL,
+                max_depth INTEGER NOT NULL,
+                PRIMARY KEY (run, id)
+            );
+            CREATE TABLE IF NOT EXISTS calls (
+                run TEXT NOT NULL,
+                caller INTEGER NOT NULL,
+                callee INTEGER NOT NULL,
+                count INTEGER NOT NULL,
+                PRIMARY KEY (run, caller, callee)
+            );",
+        )?;
+
+        transaction.execute("DELETE FROM spans WHERE run = ?1", params![run])?;
+        transaction.execute("DELETE FROM calls WHERE run = ?1", params![run])?;
+
+        {
+            let mut insert = transaction.prepare(
+                "INSERT INTO spans VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            )?;
+            for span in self.spans() {
+                insert.execute(params![
+                    run,
+                    span.id as i64,
+                    span.name,
+                    span.level.map(|level| level.to_string()),
+                    span.target,
+                    span.module_path,
+                    span.called as i64,
+                    span.elapsed.as_nanos() as i64,
+                    span.mean().as_nanos() as i64,
+                    span.stddev().as_nanos() as i64,
+                    span.max.as_nanos() as i64,
+                    span.idle.as_nanos() as i64,
+                    span.max_depth as i64,
+                ])?;
+            }
+
+            let mut insert = transaction.prepare("INSERT INTO calls VALUES (?1, ?2, ?3, ?4)")?;
+            for call in self.calls() {
+                insert.execute(params![
+                    run,
+                    self.span(call.caller).id as i64,
+                    self.span(call.callee).id as i64,
+                    call.count as i64,
+                ])?;
+            }
+        }
+
+        return transaction.commit();
+    }
+}
@@ -0,0 +1,256 @@
+use petgraph::graph::NodeIndex;
+
+use crate::export::xml_escape;
+use crate::SpanGraph;
+
+/// Approximate width of a single character in the SVG output
+const CHAR_WIDTH: f64 = 7.0;
+/// Minimal height of a node
+const NODE_HEIGHT: f64 = 36.0;
+/// Additional height of the node with the largest elapsed time
+const NODE_EXTRA_HEIGHT: f64 = 24.0;
+/// Vertical distance between the top of two successive layers
+const LAYER_HEIGHT: f64 = 110.0;
+/// Horizontal space between two nodes in the same layer
+const NODE_SPACING: f64 = 40.0;
+/// Margin around the whole drawing
+const MARGIN: f64 = 20.0;
+
+/// Position and size of a node in the SVG output
+#[derive(Clone, Copy, Debug, Default)]
+struct Rectangle {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Rectangle {
+    fn center(&self) -> f64 {
+        self.x + self.width / 2.0
+    }
+
+    fn bottom(&self) -> f64 {
+        self.y + self.height
+    }
+}
+
+impl SpanGraph {
+    /// Get the full graph as an SVG image, without requiring graphviz. Spans
+    /// are drawn as boxes, sized and colored according to their elapsed time,
+    /// and arranged in layers with callers above callees. Calls are drawn as
+    /// arrows labeled with the number of calls, and `follows_from`
+    /// relationships as dashed arrows.
+    ///
+    /// The layout is simpler than the one from graphviz (see
+    /// `SpanGraph::as_dot`), and works best for graphs with a few dozen
+    /// spans.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_svg(&self) -> String {
+        let order = self.sorted_nodes();
+        let mut position = vec![0; self.graph.node_count()];
+        for (i, &node) in order.iter().enumerate() {
+            position[node.index()] = i;
+        }
+
+        // assign each span to a layer, callees going below their callers.
+        // Calls going back up in the sorted order (recursion) are ignored.
+        let mut layers = vec![0; self.graph.node_count()];
+        for &node in &order {
+            for callee in self.graph.neighbors(node) {
+                if position[callee.index()] > position[node.index()] {
+                    layers[callee.index()] =
+                        std::cmp::max(layers[callee.index()], layers[node.index()] + 1);
+                }
+            }
+        }
+
+        let max = self
+            .spans()
+            .map(|span| span.elapsed.as_secs_f64())
+            .fold(0.0, f64::max);
+        let fraction = |node: NodeIndex| {
+            if max > 0.0 {
+                self.graph[node].elapsed.as_secs_f64() / max
+            } else {
+                0.0
+            }
+        };
+
+        let labels = order
+            .iter()
+            .map(|&node| {
+                let span = &self.graph[node];
+                let details = format!("{:.2?}, {} calls", span.elapsed, span.called);
+                (node, span.name.clone(), details)
+            })
+            .collect::<Vec<_>>();
+
+        // compute the size of all nodes, and place them from left to right
+        // in their layer
+        let mut rectangles = vec![Rectangle::default(); self.graph.node_count()];
+        let layer_count = layers.iter().max().map_or(0, |&max| max + 1);
+        let mut layer_widths = vec![0.0; layer_count];
+        for (node, name, details) in &labels {
+            let characters = std::cmp::max(name.chars().count(), details.chars().count());
+            let layer = layers[node.index()];
+            let rectangle = &mut rectangles[node.index()];
+            rectangle.width = characters as f64 * CHAR_WIDTH + 20.0;
+            rectangle.height = NODE_HEIGHT + NODE_EXTRA_HEIGHT * fraction(*node);
+            rectangle.x = layer_widths[layer];
+            rectangle.y = MARGIN + layer as f64 * LAYER_HEIGHT;
+            layer_widths[layer] += rectangle.width + NODE_SPACING;
+        }
+
+        // center all layers
+        let width = layer_widths.iter().fold(0.0, |a: f64, &b| a.max(b)) - NODE_SPACING;
+        for (node, _, _) in &labels {
+            let layer = layers[node.index()];
+            rectangles[node.index()].x +=
+                MARGIN + (width - (layer_widths[layer] - NODE_SPACING)) / 2.0;
+        }
+        let mut width = width.max(0.0) + 2.0 * MARGIN;
+
+        // leave space for the curves drawn on the right of the nodes
+        let curves = !self.follows.is_empty()
+            || self
+                .graph
+                .raw_edges()
+                .iter()
+                .any(|edge| layers[edge.target().index()] <= layers[edge.source().index()]);
+        if curves {
+            let right = rectangles.iter().fold(0.0, |right: f64, rectangle| {
+                right.max(rectangle.x + rectangle.width)
+            });
+            width = width.max(right + 80.0);
+        }
+        let height = layer_count as f64 * LAYER_HEIGHT + 2.0 * MARGIN;
+
+        let mut output = String::new();
+        output += &format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" viewBox=\"0 0 {:.0} {:.0}\" font-family=\"monospace\" font-size=\"12\">\n",
+            width, height, width, height
+        );
+        output += "  <defs>\n";
+        output += "    <marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"8\" markerHeight=\"8\" orient=\"auto-start-reverse\">\n";
+        output += "      <path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"#555\"/>\n";
+        output += "    </marker>\n";
+        output += "  </defs>\n";
+
+        let arrow = |output: &mut String,
+                     caller: NodeIndex,
+                     callee: NodeIndex,
+                     label: String,
+                     dashed: bool| {
+            let from = rectangles[caller.index()];
+            let to = rectangles[callee.index()];
+            let style = if dashed {
+                " stroke-dasharray=\"6,4\""
+            } else {
+                ""
+            };
+
+            let (path, label_x, label_y) = if caller == callee {
+                // self loop on the right side of the node
+                let x = from.x + from.width;
+                let y = from.y + from.height / 2.0;
+                (
+                    format!(
+                        "M {:.1} {:.1} C {:.1} {:.1}, {:.1} {:.1}, {:.1} {:.1}",
+                        x,
+                        y - 8.0,
+                        x + 40.0,
+                        y - 30.0,
+                        x + 40.0,
+                        y + 30.0,
+                        x,
+                        y + 8.0
+                    ),
+                    x + 34.0,
+                    y,
+                )
+            } else if to.y > from.y {
+                let (x1, y1) = (from.center(), from.bottom());
+                let (x2, y2) = (to.center(), to.y);
+                (
+                    format!("M {:.1} {:.1} L {:.1} {:.1}", x1, y1, x2, y2),
+                    (x1 + x2) / 2.0 + 4.0,
+                    (y1 + y2) / 2.0,
+                )
+            } else {
+                // calls going up or staying in the same layer are drawn as
+                // curves on the right of the nodes
+                let (x1, y1) = (from.x + from.width, from.y + from.height / 2.0);
+                let (x2, y2) = (to.x + to.width, to.y + to.height / 2.0);
+                let bulge = x1.max(x2) + 60.0;
+                (
+                    format!(
+                        "M {:.1} {:.1} C {:.1} {:.1}, {:.1} {:.1}, {:.1} {:.1}",
+                        x1, y1, bulge, y1, bulge, y2, x2, y2
+                    ),
+                    bulge - 10.0,
+                    (y1 + y2) / 2.0,
+                )
+            };
+
+            *output += &format!(
+                "  <path d=\"{}\" fill=\"none\" stroke=\"#555\"{} marker-end=\"url(#arrow)\"/>\n",
+                path, style
+            );
+            *output += &format!(
+                "  <text x=\"{:.1}\" y=\"{:.1}\" fill=\"#333\">{}</text>\n",
+                label_x, label_y, label
+            );
+        };
+
+        for edge in self.graph.raw_edges() {
+            arrow(
+                &mut output,
+                edge.source(),
+                edge.target(),
+                edge.weight.to_string(),
+                false,
+            );
+        }
+
+        for (&(cause, effect), count) in &self.follows {
+            arrow(
+                &mut output,
+                NodeIndex::new(cause),
+                NodeIndex::new(effect),
+                count.to_string(),
+                true,
+            );
+        }
+
+        for (node, name, details) in &labels {
+            let rectangle = rectangles[node.index()];
+            // go from pale yellow for short spans to red for the longest one
+            let fraction = fraction(*node);
+            let hue = 60.0 * (1.0 - fraction);
+            let lightness = 90.0 - 35.0 * fraction;
+
+            output += &format!(
+                "  <rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" rx=\"4\" fill=\"hsl({:.0}, 85%, {:.0}%)\" stroke=\"#333\"/>\n",
+                rectangle.x, rectangle.y, rectangle.width, rectangle.height, hue, lightness
+            );
+            let middle = rectangle.y + rectangle.height / 2.0;
+            output += &format!(
+                "  <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+                rectangle.center(),
+                middle - 3.0,
+                xml_escape(name)
+            );
+            output += &format!(
+                "  <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\" fill=\"#333\">{}</text>\n",
+                rectangle.center(),
+                middle + 12.0,
+                xml_escape(details)
+            );
+        }
+
+        output += "</svg>\n";
+        return output;
+    }
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use crate::{SpanGraph, SpanIndex};
+
+/// A single invocation of a span, as recorded in the timeline
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimelineEntry {
+    /// The span group this invocation belongs to
+    pub span: SpanIndex,
+    /// Time at which this invocation was first entered
+    pub start: Duration,
+    /// Time at which this invocation was closed
+    pub end: Duration,
+}
+
+/// Bounded record of individual span invocations, with begin and end
+/// timestamps relative to the creation of the `SpanGraph`.
+#[derive(Clone, Debug)]
+pub(crate) struct Timeline {
+    capacity: usize,
+    entries: Vec<TimelineEntry>,
+    dropped: usize,
+}
+
+impl Timeline {
+    pub fn new(capacity: usize) -> Timeline {
+        Timeline {
+            capacity: capacity,
+            entries: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, entry: TimelineEntry) {
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    /// Update the span of all entries with `map`, removing entries for which
+    /// `map` returns `None`
+    pub fn remap<F>(&mut self, map: F)
+    where
+        F: Fn(SpanIndex) -> Option<SpanIndex>,
+    {
+        self.entries.retain_mut(|entry| match map(entry.span) {
+            Some(span) => {
+                entry.span = span;
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Multiply the start and end time of all entries by `factor`
+    pub fn scale(&mut self, factor: f64) {
+        for entry in &mut self.entries {
+            entry.start = entry.start.mul_f64(factor);
+            entry.end = entry.end.mul_f64(factor);
+        }
+    }
+
+    /// Get the number of bytes allocated on the heap by this timeline
+    pub fn heap_size(&self) -> usize {
+        self.entries.capacity() * std::mem::size_of::<TimelineEntry>()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn entries(&self) -> &[TimelineEntry] {
+        &self.entries
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+impl SpanGraph {
+    /// Get the invocations recorded in the timeline, in the order they were
+    /// closed. This is empty unless the timeline was enabled with
+    /// `SpanTimingLayer::with_timeline`.
+    pub fn timeline(&self) -> &[TimelineEntry] {
+        self.timeline
+            .as_ref()
+            .map_or(&[], |timeline| timeline.entries())
+    }
+
+    /// Get the number of invocations which where not recorded in the timeline
+    /// because it was already full.
+    pub fn timeline_dropped(&self) -> usize {
+        self.timeline
+            .as_ref()
+            .map_or(0, |timeline| timeline.dropped())
+    }
+
+    /// Get the timeline as a [mermaid](https://mermaid-js.github.io/) gantt
+    /// chart, with one section per span and times in milliseconds.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_mermaid_gantt(&self) -> String {
+        let mut entries = self.timeline().to_vec();
+        entries.sort_by_key(|entry| (entry.span.0, entry.start));
+
+        let mut output = String::from("gantt\n    dateFormat x\n    axisFormat %S.%L\n");
+        let mut current = None;
+        let mut invocation = 0;
+        for entry in entries {
+            if current != Some(entry.span) {
+                current = Some(entry.span);
+                invocation = 0;
+                output += &format!("    section {}\n", self.span(entry.span).name);
+            }
+            invocation += 1;
+
+            let start = entry.start.as_millis();
+            let end = std::cmp::max(start + 1, ceil_millis(entry.end));
+            output += &format!("    #{} : {}, {}\n", invocation, start, end);
+        }
+
+        return output;
+    }
+
+    /// Get the timeline as a [plotly](https://plotly.com/javascript/) figure
+    /// in JSON, using horizontal bars with one row per span and times in
+    /// milliseconds.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_plotly_timeline(&self) -> String {
+        let mut names = json::JsonValue::new_array();
+        let mut durations = json::JsonValue::new_array();
+        let mut starts = json::JsonValue::new_array();
+        for entry in self.timeline() {
+            let start = entry.start.as_secs_f64() * 1e3;
+            let duration = (entry.end - entry.start).as_secs_f64() * 1e3;
+            names
+                .push(self.span(entry.span).name.clone())
+                .expect("failed to add timeline to JSON");
+            durations
+                .push(duration)
+                .expect("failed to add timeline to JSON");
+            starts.push(start).expect("failed to add timeline to JSON");
+        }
+
+        let bars = json::object! {
+            "type" => "bar",
+            "orientation" => "h",
+            "y" => names,
+            "x" => durations,
+            "base" => starts,
+        };
+
+        return json::stringify(json::object! {
+            "data" => json::array![bars],
+            "layout" => json::object! {
+                "barmode" => "overlay",
+                "xaxis" => json::object! {"title" => "time (ms)"},
+            },
+        });
+    }
+}
+
+fn ceil_millis(duration: Duration) -> u128 {
+    duration.as_nanos().div_ceil(1_000_000)
+}
@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use crate::export::CallTree;
+use crate::{ReportOptions, SpanGraph};
+
+impl SpanGraph {
+    /// Get the call tree of this graph as an indented tree, starting with
+    /// the spans called outside of any other span. Each span shows the time
+    /// spent in it through the corresponding call path, the percentage of the
+    /// total time (see `SpanGraph::total_elapsed`) and the number of calls.
+    ///
+    /// Spans called from multiple places appear multiple times in the tree,
+    /// their elapsed time being shared between callers proportionally to the
+    /// number of calls coming from each caller. Recursive calls are not
+    /// expanded, and for very large graphs the calls past 100 000 lines are
+    /// collapsed into lines called `…`.
+    ///
+    /// ```text
+    /// app::main               12.10ms (100.0%), 1 calls
+    /// ├── app::load            2.03ms (16.8%), 1 calls
+    /// └── app::compute        10.05ms (83.1%), 10 calls
+    ///     └── app::details     5.12ms (42.3%), 100 calls
+    /// ```
+    ///
+    /// Spans are colored by hotness when the standard output is a terminal,
+    /// see `ReportOptions::with_colors` to override this.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_tree(&self) -> String {
+        self.as_tree_with(&ReportOptions::default())
+    }
+
+    /// Get the call tree of this graph as an indented tree, using the given
+    /// `options`. See `SpanGraph::as_tree`.
+    ///
+    /// Spans excluded by the options (for example with
+    /// `ReportOptions::with_filter`) are only shown when they lead to some
+    /// included span, to keep the structure of the tree.
+    ///
+    /// The exact output is unstable and should not be relied on.
+    pub fn as_tree_with(&self, options: &ReportOptions) -> String {
+        let total = self.total_elapsed();
+
+        let mut lines = Vec::new();
+        for tree in self
+            .call_tree()
+            .into_iter()
+            .filter_map(|tree| self.included_tree(tree, options))
+        {
+            self.tree_lines(&tree, "", None, total, &mut lines);
+        }
+
+        let width = lines
+            .iter()
+            .map(|(label, _, _)| label.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let mut output = String::new();
+        for (label, details, fraction) in lines {
+            let line = format!("{:width$}  {}", label, details, width = width);
+            output += &options.colorize(&line, fraction);
+            output += "\n";
+        }
+        return output;
+    }
+
+    /// Remove the nodes of `tree` excluded by `options` which do not lead to
+    /// any included span, returning `None` if nothing is left
+    fn included_tree(&self, mut tree: CallTree, options: &ReportOptions) -> Option<CallTree> {
+        tree.children = tree
+            .children
+            .into_iter()
+            .filter_map(|child| self.included_tree(child, options))
+            .collect();
+
+        let included = tree
+            .span
+            .is_none_or(|span| options.includes(self.span(span)));
+        if tree.children.is_empty() && !included {
+            return None;
+        }
+        return Some(tree);
+    }
+
+    /// Add the lines for `tree` and all its children to `lines`, containing
+    /// the tree label, the timing details and the fraction of the total time
+    /// spent in this call path. `prefix` contains the drawing of the
+    /// parent levels, and `last` is `Some(true)` for the last child of a
+    /// span, `Some(false)` for the other children and `None` for roots.
+    fn tree_lines(
+        &self,
+        tree: &CallTree,
+        prefix: &str,
+        last: Option<bool>,
+        total: Duration,
+        lines: &mut Vec<(String, String, f64)>,
+    ) {
+        let (branch, indent) = match last {
+            None => ("", ""),
+            Some(false) => ("├── ", "│   "),
+            Some(true) => ("└── ", "    "),
+        };
+
+        let fraction = if total.as_nanos() != 0 {
+            tree.elapsed.as_secs_f64() / total.as_secs_f64()
+        } else {
+            0.0
+        };
+
+        lines.push((
+            format!("{}{}{}", prefix, branch, tree.name(self)),
+            format!(
+                "{:>9} ({:.1}%), {} calls",
+                format!("{:.2?}", tree.elapsed),
+                100.0 * fraction,
+                tree.count
+            ),
+            fraction,
+        ));
+
+        let prefix = format!("{}{}", prefix, indent);
+        for (i, child) in tree.children.iter().enumerate() {
+            let last = i + 1 == tree.children.len();
+            self.tree_lines(child, &prefix, Some(last), total, lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ColorMode, Format};
+
+    fn plain_tree(graph: &SpanGraph) -> String {
+        graph.as_tree_with(&ReportOptions::new().with_colors(ColorMode::Never))
+    }
+
+    #[test]
+    fn uncalled_child() {
+        let mut graph = SpanGraph::new();
+        let root = graph.find_or_create("root");
+        let child = graph.find_or_create("child");
+        graph.increase_timing(root, Duration::from_millis(3));
+        graph.increase_call_count(root, child);
+
+        let tree = plain_tree(&graph);
+        let lines = tree.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("root"));
+        assert!(lines[1].starts_with("└── child"));
+        assert!(lines[1].ends_with("0.00ns (0.0%), 1 calls"));
+
+        assert_eq!(graph.render(Format::Tree), tree);
+    }
+
+    #[test]
+    fn pruned_chain() {
+        // pruning the middle of a chain can leave calls to spans which were
+        // never called themselves
+        let mut graph = SpanGraph::new();
+        let a = graph.find_or_create("A");
+        let b = graph.find_or_create("B");
+        let c = graph.find_or_create("C");
+        graph.increase_timing(a, Duration::from_millis(3));
+        graph.increase_timing(b, Duration::from_millis(2));
+        graph.increase_call_count(a, b);
+        graph.increase_call_count(b, c);
+
+        graph.prune(|span| span.name == "B");
+        let tree = plain_tree(&graph);
+        assert!(tree.contains("└── C"));
+    }
+}
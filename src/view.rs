@@ -0,0 +1,64 @@
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use std::sync::Arc;
+
+use crate::SpanGraph;
+
+/// Cheap handle to the span graph of a `SpanTimingLayer`, giving read access
+/// to the recorded data.
+///
+/// Multiple readers can access the graph at the same time, but the layer can
+/// not record the end of a span while a reader holds the guard returned by
+/// `GraphView::read`: spans closing in the meantime block until the guard is
+/// dropped. Readers that need to do expensive work with the data (for example
+/// rendering a report for a dashboard) should use `GraphView::snapshot`,
+/// which only holds the lock while copying the graph, and work on the copy.
+#[derive(Clone)]
+pub struct GraphView {
+    graph: Arc<RwLock<SpanGraph>>,
+}
+
+impl GraphView {
+    pub(crate) fn new(graph: Arc<RwLock<SpanGraph>>) -> GraphView {
+        GraphView { graph: graph }
+    }
+
+    /// Get read access to the graph, blocking until the layer is done
+    /// recording if needed. Recording is blocked while the returned guard is
+    /// alive, so it should be dropped quickly.
+    pub fn read(&self) -> RwLockReadGuard<'_, SpanGraph> {
+        self.graph.read()
+    }
+
+    /// Get exclusive access to the graph, blocking until the layer is done
+    /// recording if needed
+    #[deprecated(note = "use GraphView::read or GraphView::snapshot instead")]
+    pub fn lock(&self) -> RwLockWriteGuard<'_, SpanGraph> {
+        self.graph.write()
+    }
+
+    /// Try to get read access to the graph without blocking, returning `None`
+    /// if the layer is currently recording
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, SpanGraph>> {
+        self.graph.try_read()
+    }
+
+    /// Write the graph to the file configured with
+    /// `SpanTimingLayer::with_output`, if any. See `SpanGraph::flush`.
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.graph.read().flush()
+    }
+
+    /// Remove all the data recorded so far, see `SpanGraph::clear`
+    pub fn clear(&self) {
+        self.graph.write().clear();
+    }
+
+    /// Get a copy of the current state of the graph. The lock on the graph
+    /// is released as soon as the copy is done, so the copy can be used for
+    /// expensive work without blocking the recording of spans.
+    pub fn snapshot(&self) -> SpanGraph {
+        let graph = self.graph.read();
+        return graph.clone();
+    }
+}
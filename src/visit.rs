@@ -0,0 +1,75 @@
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+
+use crate::{SpanGraph, SpanIndex, SpanTiming};
+
+/// A single span reached during a traversal of the graph, see
+/// `SpanGraph::visit_from`
+#[derive(Clone, Copy, Debug)]
+pub struct VisitedSpan<'a> {
+    /// Index of the span in the graph
+    pub index: SpanIndex,
+    /// Data associated with the span
+    pub span: &'a SpanTiming,
+    /// Depth of the span in the traversal, starting at 0 for the root
+    pub depth: usize,
+    /// Span through which this span was reached, `None` for the root
+    pub caller: Option<SpanIndex>,
+    /// Number of time `caller` called this span, 0 for the root
+    pub count: usize,
+}
+
+impl SpanGraph {
+    /// Walk the graph depth-first starting at `root`, calling `visitor` for
+    /// each span reached, callers before callees.
+    ///
+    /// Spans reachable through multiple call paths are visited once for each
+    /// path, the same way they would appear in a call tree. Recursive calls
+    /// are not followed.
+    ///
+    /// ```
+    /// # use tracing_timing_graph::SpanGraph;
+    /// # let mut graph = SpanGraph::new();
+    /// # let root = graph.find_or_create("root");
+    /// // print an indented call tree
+    /// graph.visit_from(root, |visited| {
+    ///     println!("{}{}", "  ".repeat(visited.depth), visited.span.name);
+    /// });
+    /// ```
+    pub fn visit_from<F>(&self, root: SpanIndex, mut visitor: F)
+    where
+        F: FnMut(VisitedSpan<'_>),
+    {
+        let mut path = Vec::new();
+        self.visit_node(root.into(), None, 0, &mut path, &mut visitor);
+    }
+
+    fn visit_node<F>(
+        &self,
+        node: NodeIndex,
+        caller: Option<NodeIndex>,
+        count: usize,
+        path: &mut Vec<NodeIndex>,
+        visitor: &mut F,
+    ) where
+        F: FnMut(VisitedSpan<'_>),
+    {
+        visitor(VisitedSpan {
+            index: node.into(),
+            span: &self.graph[node],
+            depth: path.len(),
+            caller: caller.map(SpanIndex::from),
+            count: count,
+        });
+
+        path.push(node);
+        for edge in self.graph.edges_directed(node, Direction::Outgoing) {
+            if path.contains(&edge.target()) {
+                continue;
+            }
+            self.visit_node(edge.target(), Some(node), *edge.weight(), path, visitor);
+        }
+        path.pop();
+    }
+}